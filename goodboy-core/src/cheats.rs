@@ -0,0 +1,271 @@
+use std::fmt::Display;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheatCode {
+    /// Replace a byte read from ROM. If `compare` is set, the byte is only replaced when
+    /// the original value matches it.
+    GameGenie {
+        addr: u16,
+        value: u8,
+        compare: Option<u8>,
+    },
+    /// Write `value` to RAM on every VBlank. `bank` is the code type byte: `0x9X` selects
+    /// the WRAM bank X, anything else writes through the current mapping.
+    GameShark { bank: u8, addr: u16, value: u8 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheatError {
+    /// The code doesn't have the size of a Game Genie (6 or 9 digits) or GameShark (8 digits) code
+    InvalidLength(usize),
+    InvalidCharacter(char),
+    /// Game Genie codes can only patch the ROM
+    NotRomAddress(u16),
+    /// GameShark codes can only write to RAM
+    NotRamAddress(u16),
+    /// Error in line `.0` of a cheat list
+    InvalidLine(usize, Box<CheatError>),
+}
+
+impl Display for CheatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheatError::InvalidLength(len) => write!(
+                f,
+                "invalid code length {len}, expected 6 or 9 (Game Genie) or 8 (GameShark) digits"
+            ),
+            CheatError::InvalidCharacter(c) => write!(f, "invalid character {c:?} in code"),
+            CheatError::NotRomAddress(addr) => {
+                write!(f, "Game Genie address 0x{addr:04X} is not in ROM")
+            }
+            CheatError::NotRamAddress(addr) => {
+                write!(f, "GameShark address 0x{addr:04X} is not in RAM")
+            }
+            CheatError::InvalidLine(line, e) => write!(f, "line {line}: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for CheatError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cheat {
+    pub name: String,
+    pub code: CheatCode,
+    pub enabled: bool,
+}
+
+impl Cheat {
+    /// Parse a Game Genie (`ABC-DEF` or `ABC-DEF-GHI`) or GameShark (`ABCDEFGH`) code
+    pub fn parse(name: &str, code: &str) -> Result<Cheat, CheatError> {
+        let digits = code
+            .chars()
+            .filter(|c| *c != '-' && !c.is_whitespace())
+            .map(|c| {
+                c.to_digit(16)
+                    .map(|d| d as u16)
+                    .ok_or(CheatError::InvalidCharacter(c))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let code = match digits.len() {
+            6 | 9 => Self::parse_game_genie(&digits)?,
+            8 => Self::parse_game_shark(&digits)?,
+            len => return Err(CheatError::InvalidLength(len)),
+        };
+
+        Ok(Cheat {
+            name: name.to_string(),
+            code,
+            enabled: true,
+        })
+    }
+
+    /// Parse a cheat list. Each line holds a code followed by an optional name, empty
+    /// lines and lines starting with `#` are ignored.
+    pub fn parse_list(list: &str) -> Result<Vec<Cheat>, CheatError> {
+        list.lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(i, line)| {
+                let (code, name) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+                Cheat::parse(name.trim(), code).map_err(|e| CheatError::InvalidLine(i, Box::new(e)))
+            })
+            .collect()
+    }
+
+    fn parse_game_genie(d: &[u16]) -> Result<CheatCode, CheatError> {
+        let value = ((d[0] << 4) | d[1]) as u8;
+        let addr = ((d[5] ^ 0xF) << 12) | (d[2] << 8) | (d[3] << 4) | d[4];
+
+        if addr >= 0x8000 {
+            return Err(CheatError::NotRomAddress(addr));
+        }
+
+        // the 8th digit is only used by the Game Genie to check the code
+        let compare = (d.len() == 9).then(|| (((d[6] << 4) | d[8]) as u8).rotate_right(2) ^ 0xBA);
+
+        Ok(CheatCode::GameGenie {
+            addr,
+            value,
+            compare,
+        })
+    }
+
+    fn parse_game_shark(d: &[u16]) -> Result<CheatCode, CheatError> {
+        let bank = ((d[0] << 4) | d[1]) as u8;
+        let value = ((d[2] << 4) | d[3]) as u8;
+        // the address is stored in little endian
+        let addr = (d[6] << 12) | (d[7] << 8) | (d[4] << 4) | d[5];
+
+        if addr < 0x8000 {
+            return Err(CheatError::NotRamAddress(addr));
+        }
+
+        Ok(CheatCode::GameShark { bank, addr, value })
+    }
+}
+
+/// The cheats enabled on a cartridge
+#[derive(Debug, Default)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+}
+
+impl Cheats {
+    pub fn add(&mut self, cheat: Cheat) -> usize {
+        log::info!("Adding cheat: {cheat:X?}");
+        self.cheats.push(cheat);
+        self.cheats.len() - 1
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Cheat> {
+        (index < self.cheats.len()).then(|| self.cheats.remove(index))
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        self.cheats
+            .get_mut(index)
+            .map(|cheat| cheat.enabled = enabled)
+            .is_some()
+    }
+
+    pub fn list(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    /// Apply the Game Genie codes to a byte read from ROM
+    pub fn rom_read(&self, addr: u16, value: u8) -> u8 {
+        if self.cheats.is_empty() {
+            return value;
+        }
+
+        self.cheats
+            .iter()
+            .filter(|cheat| cheat.enabled)
+            .find_map(|cheat| match cheat.code {
+                CheatCode::GameGenie {
+                    addr: cheat_addr,
+                    value: cheat_value,
+                    compare,
+                } if cheat_addr == addr && compare.is_none_or(|c| c == value) => Some(cheat_value),
+                _ => None,
+            })
+            .unwrap_or(value)
+    }
+
+    /// The enabled GameShark writes, as `(bank, addr, value)`
+    pub fn ram_writes(&self) -> impl Iterator<Item = (u8, u16, u8)> + '_ {
+        self.cheats
+            .iter()
+            .filter(|cheat| cheat.enabled)
+            .filter_map(|cheat| match cheat.code {
+                CheatCode::GameShark { bank, addr, value } => Some((bank, addr, value)),
+                _ => None,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_game_genie() {
+        let cheat = Cheat::parse("", "00A-17B-C49").unwrap();
+        assert_eq!(
+            cheat.code,
+            CheatCode::GameGenie {
+                addr: 0x4A17,
+                value: 0x00,
+                compare: Some(0xC8),
+            }
+        );
+
+        let cheat = Cheat::parse("", "3E5-F2A").unwrap();
+        assert_eq!(
+            cheat.code,
+            CheatCode::GameGenie {
+                addr: 0x55F2,
+                value: 0x3E,
+                compare: None,
+            }
+        );
+
+        assert_eq!(
+            Cheat::parse("", "00A-17B-C").unwrap_err(),
+            CheatError::InvalidLength(7)
+        );
+        assert_eq!(
+            Cheat::parse("", "00A-17B-C4Z").unwrap_err(),
+            CheatError::InvalidCharacter('Z')
+        );
+    }
+
+    #[test]
+    fn parse_game_shark() {
+        let cheat = Cheat::parse("", "010238CD").unwrap();
+        assert_eq!(
+            cheat.code,
+            CheatCode::GameShark {
+                bank: 0x01,
+                addr: 0xCD38,
+                value: 0x02,
+            }
+        );
+
+        assert_eq!(
+            Cheat::parse("", "01020040").unwrap_err(),
+            CheatError::NotRamAddress(0x4000)
+        );
+    }
+
+    #[test]
+    fn game_genie_compare() {
+        let mut cheats = Cheats::default();
+        cheats.add(Cheat::parse("", "00A-17B-C49").unwrap());
+
+        assert_eq!(cheats.rom_read(0x4A17, 0xC8), 0x00);
+        // the compare byte doesn't match
+        assert_eq!(cheats.rom_read(0x4A17, 0x11), 0x11);
+
+        cheats.set_enabled(0, false);
+        assert_eq!(cheats.rom_read(0x4A17, 0xC8), 0xC8);
+    }
+
+    #[test]
+    fn parse_cheat_list() {
+        let list = "# Infinite lives\n010238CD Lives\n\n3E5-F2A  Jump higher\n";
+        let cheats = Cheat::parse_list(list).unwrap();
+
+        assert_eq!(cheats.len(), 2);
+        assert_eq!(cheats[0].name, "Lives");
+        assert_eq!(cheats[1].name, "Jump higher");
+
+        assert!(matches!(
+            Cheat::parse_list("010238CD\nnot-a-code"),
+            Err(CheatError::InvalidLine(2, _))
+        ));
+    }
+}
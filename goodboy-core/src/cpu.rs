@@ -1,11 +1,16 @@
+pub mod disassembler;
 pub mod instruction;
+pub mod profiler;
 pub mod register;
+pub mod trace;
 
 use std::fmt::Debug;
 
 use crate::{cpu::instruction::Operand, mmu::Bus, mmu::MemoryAccess, utils::UnsignedValue};
 use instruction::{Condition, Instruction, Opcode, CB_OPCODE_MAP, OPCODE_MAP};
+use profiler::{Flow, Profiler};
 use register::{Flags, Registers};
+use trace::Tracer;
 
 pub struct Cpu {
     // CPU registers
@@ -25,6 +30,11 @@ pub struct Cpu {
     set_di: u8,
 
     halted: bool,
+
+    /// Instruction trace, disabled by default
+    tracer: Option<Box<Tracer>>,
+    /// Cycles per function, disabled by default
+    profiler: Option<Box<Profiler>>,
 }
 
 impl Cpu {
@@ -46,7 +56,37 @@ impl Cpu {
             set_di: 0,
 
             halted: false,
+
+            tracer: None,
+            profiler: None,
+        }
+    }
+
+    /// Start or stop logging every instruction executed
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        if let Some(mut tracer) = self.tracer.take() {
+            tracer.flush().ok();
         }
+        self.tracer = tracer.map(Box::new);
+    }
+
+    /// Start or stop attributing the executed cycles to the functions, returns the
+    /// previous profiler
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) -> Option<Profiler> {
+        std::mem::replace(&mut self.profiler, profiler.map(Box::new)).map(|profiler| *profiler)
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_deref()
+    }
+
+    /// The interrupt master enable flag
+    pub fn ime(&self) -> bool {
+        self.ime
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted
     }
 
     pub fn run(&mut self) -> u32 {
@@ -56,39 +96,95 @@ impl Cpu {
     pub fn run_callback(&mut self, mut callback: impl FnMut(&mut Self)) -> u32 {
         callback(self);
         let clocks = self.tick().unwrap();
+        self.bus.cpu_halted = self.halted;
         self.bus.sync(clocks)
     }
 
     pub fn tick(&mut self) -> Result<u32, Box<dyn std::error::Error>> {
         // Update the interrupt state
         self.update_ime();
-        match self.handle_interruption() {
-            0 => (),
-            n => return Ok(n),
+        let (pc, sp) = (self.pc, self.sp);
+        let bank = match self.profiler {
+            Some(_) => self.bus.bank_at(pc),
+            None => 0,
         };
 
-        match self.halted {
-            true => Ok(4),
-            false => {
+        let (clocks, instruction) = match self.handle_interruption() {
+            0 if self.halted => (4, None),
+            0 => {
+                if self.tracer.is_some() {
+                    self.trace();
+                }
+
                 // Run the instruction
                 let opcode = self.fetch_and_decode();
-                self.exec_opcode(opcode).map_err(|e| e.into())
+                (self.exec_opcode(opcode)?, Some(opcode.instruction))
             }
+            n => (n, Some(Instruction::CALL(None))),
+        };
+
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.add_cycles(clocks);
+        }
+        if self.profiler.is_some() {
+            self.profile(bank, pc, sp, clocks, instruction);
+        }
+        Ok(clocks)
+    }
+
+    fn trace(&mut self) {
+        let mut tracer = self.tracer.take();
+        if let Some(Err(e)) = tracer.as_mut().map(|tracer| tracer.log(self)) {
+            log::error!("Stopping the trace: {e}");
+            tracer = None;
+        }
+        self.tracer = tracer;
+    }
+
+    /// Record an instruction that ran at `pc`, interrupts are recorded as `CALL`
+    fn profile(
+        &mut self,
+        bank: usize,
+        pc: u16,
+        sp: u16,
+        clocks: u32,
+        instruction: Option<Instruction>,
+    ) {
+        let flow = match instruction {
+            Some(Instruction::CALL(_) | Instruction::RST(_)) if self.sp == sp.wrapping_sub(2) => {
+                Flow::Call {
+                    target: (self.bus.bank_at(self.pc), self.pc),
+                    sp: self.sp,
+                }
+            }
+            Some(Instruction::RET(_) | Instruction::RETI) if self.sp == sp.wrapping_add(2) => {
+                Flow::Return { sp }
+            }
+            _ => Flow::Step,
+        };
+
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record(bank, pc, clocks, flow);
         }
     }
 
     /// Get the next byte and increment the PC by 1.
     pub fn fetch_byte(&mut self) -> u8 {
-        let byte = self.mem_read(self.pc);
+        let byte = self.bus.fetch(self.pc, false);
         self.pc += 1;
         byte
     }
 
     /// Get the next word and increment the PC by 2.
     pub fn fetch_word(&mut self) -> u16 {
-        let word = self.mem_read_word(self.pc);
-        self.pc += 2;
-        word
+        u16::from_le_bytes([self.fetch_byte(), self.fetch_byte()])
+    }
+
+    /// Get the first byte of an instruction and increment the PC by 1.
+    fn fetch_opcode(&mut self) -> u8 {
+        let byte = self.bus.fetch(self.pc, true);
+        self.pc += 1;
+        byte
     }
 
     /// Push a word to the stack
@@ -108,15 +204,24 @@ impl Cpu {
     pub fn fetch_and_decode(&mut self) -> &'static Opcode<'static> {
         let pc = self.pc;
 
-        let byte = self.fetch_byte();
+        let byte = self.fetch_opcode();
         let opcode = Cpu::decode(byte, false);
 
         let opcode = match opcode {
-            Some(Opcode { instruction: Instruction::CB, .. }) => Cpu::decode(self.fetch_byte(), true),
+            Some(Opcode {
+                instruction: Instruction::CB,
+                ..
+            }) => Cpu::decode(self.fetch_byte(), true),
             _ => opcode,
         };
 
-        opcode.unwrap_or_else(|| panic!("Unknown opcode at 0x{:04X}: 0x{:02X}", pc, byte))
+        opcode.unwrap_or_else(|| {
+            panic!(
+                "Unknown opcode at {}: 0x{:02X}",
+                self.bus.describe_addr(pc),
+                byte
+            )
+        })
     }
 
     /// Decode the given byte
@@ -155,7 +260,7 @@ impl Cpu {
         }
 
         self.halted = false;
-        if self.ime == false {
+        if !self.ime {
             return 0;
         }
         self.ime = false;
@@ -469,17 +574,11 @@ impl Cpu {
 
             Instruction::Unused => {
                 log::warn!(
-                    "Forbidden opcode at 0x{:04X}: {opcode:X?}",
-                    self.pc.wrapping_sub(opcode.length as u16),
+                    "Forbidden opcode at {}: {opcode:X?}",
+                    self.bus
+                        .describe_addr(self.pc.wrapping_sub(opcode.length as u16)),
                 )
             }
-
-            _ => {
-                return Err(format!(
-                    "Invalid opcode at 0x{:04X}: {opcode:X?}",
-                    self.pc.wrapping_sub(opcode.length as u16)
-                ))
-            }
         };
 
         Ok(cycles)
@@ -599,13 +698,12 @@ impl Cpu {
     /// then jump to the address denoted by the next word, otherwise, do nothing. \
     /// Returns the instruction cycles.
     fn branch_call(&mut self, condition: bool) -> u32 {
+        let addr = self.fetch_word();
         if condition {
-            self.push_stack(self.pc + 2);
-            self.pc = self.fetch_word();
+            self.push_stack(self.pc);
+            self.pc = addr;
             24
         } else {
-            // skip the next word
-            self.pc += 2;
             12
         }
     }
@@ -698,7 +796,6 @@ impl Cpu {
         self.regs.set_hl(result);
     }
     fn alu16_add_imm(&mut self, target: &Operand, rr: u16) {
-        let rr = rr;
         let n = self.fetch_byte() as i8 as i16 as u16;
 
         let result = rr.wrapping_add(n);
@@ -997,7 +1094,7 @@ impl Cpu {
     fn alu_swap(&mut self, target: &Operand) {
         let value = self.get_operand_value(target, true).unwrap_u8();
 
-        let result = (value >> 4) | (value << 4);
+        let result = value.rotate_left(4);
         self.regs.f.set(Flags::Z, result == 0);
         self.regs.f.remove(Flags::N | Flags::H | Flags::C);
 
@@ -0,0 +1,381 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Write},
+};
+
+use super::{
+    instruction::{Condition, Instruction, Opcode, Operand},
+    Cpu,
+};
+use crate::symbols::Symbols;
+
+/// The immediate value following an opcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Immediate {
+    U8(u8),
+    I8(i8),
+    U16(u16),
+}
+
+/// A decoded instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodedInstruction {
+    pub addr: u16,
+    /// The bank the instruction was read from
+    pub bank: usize,
+    /// `None` if the opcode is unused or truncated, it is shown as data
+    pub opcode: Option<&'static Opcode<'static>>,
+    pub immediate: Option<Immediate>,
+    /// Size of the instruction in bytes, including the prefix
+    pub length: usize,
+    bytes: [u8; 3],
+}
+
+impl DecodedInstruction {
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..self.length]
+    }
+
+    /// The address of the next instruction
+    pub fn next_addr(&self) -> u16 {
+        self.addr.wrapping_add(self.length as u16)
+    }
+
+    /// The destination of a jump, call or restart, with relative jumps resolved
+    pub fn target(&self) -> Option<u16> {
+        match (self.opcode?.instruction, self.immediate) {
+            (Instruction::JR(_), Some(Immediate::I8(offset))) => {
+                Some(self.next_addr().wrapping_add(offset as u16))
+            }
+            (Instruction::JP(_) | Instruction::CALL(_), Some(Immediate::U16(addr))) => Some(addr),
+            (Instruction::RST(addr), _) => Some(addr),
+            _ => None,
+        }
+    }
+
+    /// Format the instruction with RGBDS syntax, naming the addresses with `name`
+    pub fn format_with(&self, name: impl Fn(u16) -> Option<String>) -> String {
+        let opcode = match self.opcode {
+            Some(opcode) => opcode,
+            None => {
+                let bytes = self.bytes().iter().map(|b| format!("${b:02X}"));
+                return format!("db {}", bytes.collect::<Vec<_>>().join(", "));
+            }
+        };
+
+        let addr = |addr: u16| name(addr).unwrap_or_else(|| format!("${addr:04X}"));
+        let (u8, i8, u16) = match self.immediate {
+            Some(Immediate::U8(n)) => (n, n as i8, n as u16),
+            Some(Immediate::I8(n)) => (n as u8, n, n as u16),
+            Some(Immediate::U16(n)) => (n as u8, n as i8, n),
+            None => (0, 0, 0),
+        };
+        let signed = if i8 < 0 {
+            format!("- {}", i8.unsigned_abs())
+        } else {
+            format!("+ {i8}")
+        };
+        let target = self.target().map(addr).unwrap_or_default();
+
+        match opcode.instruction {
+            Instruction::Unused | Instruction::CB => format!("db ${:02X}", opcode.code),
+
+            Instruction::NOP => "nop".to_string(),
+            Instruction::STOP => "stop".to_string(),
+            Instruction::HALT => "halt".to_string(),
+            Instruction::DI => "di".to_string(),
+            Instruction::EI => "ei".to_string(),
+            Instruction::CCF => "ccf".to_string(),
+            Instruction::SCF => "scf".to_string(),
+            Instruction::CPL => "cpl".to_string(),
+            Instruction::DAA => "daa".to_string(),
+            Instruction::RLCA => "rlca".to_string(),
+            Instruction::RLA => "rla".to_string(),
+            Instruction::RRCA => "rrca".to_string(),
+            Instruction::RRA => "rra".to_string(),
+            Instruction::RETI => "reti".to_string(),
+
+            Instruction::LDIM16(r) => format!("ld {}, {}", reg16(r), addr(u16)),
+            Instruction::LDIM8(r) => format!("ld {}, ${u8:02X}", reg8(r)),
+            Instruction::LDMEM(r) => format!("ld {}, a", reg8(r)),
+            Instruction::LDRR(dst, src) => format!("ld {}, {}", reg8(dst), reg8(src)),
+            Instruction::LD16A => format!("ld [{}], a", addr(u16)),
+            Instruction::LDA16 => format!("ld a, [{}]", addr(u16)),
+            Instruction::LDFF8A => format!("ldh [{}], a", addr(0xFF00 | u16)),
+            Instruction::LDAFF8 => format!("ldh a, [{}]", addr(0xFF00 | u16)),
+            Instruction::LDFFCA => "ldh [c], a".to_string(),
+            Instruction::LDAFFC => "ldh a, [c]".to_string(),
+            Instruction::LD16SP => format!("ld [{}], sp", addr(u16)),
+            Instruction::LDSPHL => "ld sp, hl".to_string(),
+            Instruction::ADDHLSP => format!("ld hl, sp {signed}"),
+            Instruction::ADDSP => format!("add sp, {i8}"),
+
+            Instruction::INC16(r) => format!("inc {}", reg16(r)),
+            Instruction::DEC16(r) => format!("dec {}", reg16(r)),
+            Instruction::INC8(r) => format!("inc {}", reg8(r)),
+            Instruction::DEC8(r) => format!("dec {}", reg8(r)),
+            Instruction::ADDHL(r) => format!("add hl, {}", reg16(r)),
+
+            Instruction::ADD(r) => format!("add a, {}", alu_operand(r, u8)),
+            Instruction::ADC(r) => format!("adc a, {}", alu_operand(r, u8)),
+            Instruction::SUB(r) => format!("sub a, {}", alu_operand(r, u8)),
+            Instruction::SBC(r) => format!("sbc a, {}", alu_operand(r, u8)),
+            Instruction::AND(r) => format!("and a, {}", alu_operand(r, u8)),
+            Instruction::XOR(r) => format!("xor a, {}", alu_operand(r, u8)),
+            Instruction::OR(r) => format!("or a, {}", alu_operand(r, u8)),
+            Instruction::CP(r) => format!("cp a, {}", alu_operand(r, u8)),
+
+            Instruction::PUSH(r) => format!("push {}", reg16(r)),
+            Instruction::POP(r) => format!("pop {}", reg16(r)),
+
+            Instruction::JR(cond) => format!("jr {}{target}", condition(cond)),
+            Instruction::JP(cond) => format!("jp {}{target}", condition(cond)),
+            Instruction::JPHL => "jp hl".to_string(),
+            Instruction::CALL(cond) => format!("call {}{target}", condition(cond)),
+            Instruction::RET(None) => "ret".to_string(),
+            Instruction::RET(cond) => format!("ret {}", condition(cond).trim_end_matches(", ")),
+            Instruction::RST(vector) => format!("rst ${vector:02X}"),
+
+            Instruction::RLC(r) => format!("rlc {}", reg8(r)),
+            Instruction::RRC(r) => format!("rrc {}", reg8(r)),
+            Instruction::RL(r) => format!("rl {}", reg8(r)),
+            Instruction::RR(r) => format!("rr {}", reg8(r)),
+            Instruction::SLA(r) => format!("sla {}", reg8(r)),
+            Instruction::SRA(r) => format!("sra {}", reg8(r)),
+            Instruction::SWAP(r) => format!("swap {}", reg8(r)),
+            Instruction::SRL(r) => format!("srl {}", reg8(r)),
+            Instruction::BIT(b, r) => format!("bit {b}, {}", reg8(r)),
+            Instruction::RES(b, r) => format!("res {b}, {}", reg8(r)),
+            Instruction::SET(b, r) => format!("set {b}, {}", reg8(r)),
+        }
+    }
+}
+
+impl Display for DecodedInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.format_with(|_| None))
+    }
+}
+
+/// An 8 bit operand, the register pairs are memory accesses
+fn reg8(operand: Operand) -> &'static str {
+    match operand {
+        Operand::A => "a",
+        Operand::F => "f",
+        Operand::B => "b",
+        Operand::C => "c",
+        Operand::D => "d",
+        Operand::E => "e",
+        Operand::H => "h",
+        Operand::L => "l",
+        Operand::BC => "[bc]",
+        Operand::DE => "[de]",
+        Operand::HL => "[hl]",
+        Operand::HLI => "[hl+]",
+        Operand::HLD => "[hl-]",
+        Operand::AF | Operand::SP | Operand::IM8 => "?",
+    }
+}
+
+fn reg16(operand: Operand) -> &'static str {
+    match operand {
+        Operand::AF => "af",
+        Operand::BC => "bc",
+        Operand::DE => "de",
+        Operand::HL => "hl",
+        Operand::SP => "sp",
+        operand => reg8(operand),
+    }
+}
+
+fn alu_operand(operand: Operand, immediate: u8) -> String {
+    match operand {
+        Operand::IM8 => format!("${immediate:02X}"),
+        operand => reg8(operand).to_string(),
+    }
+}
+
+fn condition(condition: Option<Condition>) -> &'static str {
+    match condition {
+        None => "",
+        Some(Condition::Z) => "z, ",
+        Some(Condition::C) => "c, ",
+        Some(Condition::NZ) => "nz, ",
+        Some(Condition::NC) => "nc, ",
+    }
+}
+
+/// Decode the instruction at the start of `bytes`, located at `addr` in `bank`
+pub fn decode(bytes: &[u8], addr: u16, bank: usize) -> DecodedInstruction {
+    let mut instruction = DecodedInstruction {
+        addr,
+        bank,
+        opcode: None,
+        immediate: None,
+        length: 1,
+        bytes: [bytes.first().copied().unwrap_or(0), 0, 0],
+    };
+
+    let (opcode, length) = match bytes {
+        [] => return instruction,
+        [0xCB, code, ..] => (Cpu::decode(*code, true), 2),
+        [0xCB] => return instruction,
+        // STOP is followed by a padding byte
+        [0x10, ..] => (Cpu::decode(0x10, false), 2),
+        [code, ..] => {
+            let opcode = Cpu::decode(*code, false);
+            (opcode, opcode.map_or(1, |opcode| opcode.length))
+        }
+    };
+
+    let opcode = match opcode {
+        Some(opcode) if length <= bytes.len() => opcode,
+        _ => return instruction,
+    };
+    if matches!(opcode.instruction, Instruction::Unused) {
+        return instruction;
+    }
+
+    instruction.bytes[..length].copy_from_slice(&bytes[..length]);
+    instruction.length = length;
+    instruction.opcode = Some(opcode);
+    instruction.immediate = match (opcode.instruction, length, opcode.prefixed) {
+        (_, _, true) => None,
+        (Instruction::JR(_) | Instruction::ADDSP | Instruction::ADDHLSP, _, _) => {
+            Some(Immediate::I8(bytes[1] as i8))
+        }
+        (_, 2, _) => Some(Immediate::U8(bytes[1])),
+        (_, 3, _) => Some(Immediate::U16(u16::from_le_bytes([bytes[1], bytes[2]]))),
+        _ => None,
+    };
+
+    instruction
+}
+
+/// Decode all the instructions in `bytes`, starting at `addr`
+pub fn disassemble(bytes: &[u8], addr: u16, bank: usize) -> Vec<DecodedInstruction> {
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        let instruction = decode(&bytes[offset..], addr.wrapping_add(offset as u16), bank);
+        offset += instruction.length;
+        instructions.push(instruction);
+    }
+
+    instructions
+}
+
+/// Disassemble a whole ROM bank as a RGBDS source file. Only the labels defined in
+/// the bank are used, so the file can be assembled on its own.
+pub fn rgbds_bank(rom: &[u8], bank: usize, symbols: &Symbols) -> Option<String> {
+    let bytes = rom.get(bank * 0x4000..(bank + 1) * 0x4000)?;
+    let (addr, section) = match bank {
+        0 => (0x0000, "ROM0[$0000]".to_string()),
+        n => (0x4000, format!("ROMX[$4000], BANK[${n:02X}]")),
+    };
+
+    let instructions = disassemble(bytes, addr, bank);
+    let labels = instructions
+        .iter()
+        .filter_map(|i| Some((i.addr, symbols.name(bank, i.addr)?)))
+        .collect::<HashMap<_, _>>();
+    let name = |addr| labels.get(&addr).map(|name| name.to_string());
+
+    let mut source = format!("SECTION \"ROM Bank ${bank:02X}\", {section}\n\n");
+    for instruction in instructions {
+        if let Some(label) = labels.get(&instruction.addr) {
+            writeln!(source, "{label}:").unwrap();
+        }
+
+        let bytes = instruction.bytes().iter().map(|b| format!("{b:02X}"));
+        let bytes = bytes.collect::<Vec<_>>().join(" ");
+
+        let text = match (instruction.opcode, instruction.bytes()) {
+            // RGBDS always adds a 0x00 after STOP
+            (Some(_), [0x10, padding]) if *padding != 0x00 => {
+                format!("db $10, ${padding:02X}")
+            }
+            _ => instruction.format_with(name),
+        };
+
+        writeln!(source, "    {text:<24}; ${:04X}: {bytes}", instruction.addr).unwrap();
+    }
+
+    Some(source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_immediates() {
+        let code = [
+            0x3E, 0x42, // ld a, $42
+            0x18, 0xFC, // jr -4
+            0xCD, 0x34, 0x12, // call $1234
+            0xCB, 0x7C, // bit 7, h
+            0xF8, 0xFE, // ld hl, sp - 2
+            0xD3, // unused
+            0xE0, 0x40, // ldh [$FF40], a
+        ];
+        let instructions = disassemble(&code, 0x4000, 1);
+        let text = instructions
+            .iter()
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            text,
+            [
+                "ld a, $42",
+                "jr $4000",
+                "call $1234",
+                "bit 7, h",
+                "ld hl, sp - 2",
+                "db $D3",
+                "ldh [$FF40], a",
+            ]
+        );
+        assert_eq!(instructions[1].target(), Some(0x4000));
+        assert_eq!(instructions[2].immediate, Some(Immediate::U16(0x1234)));
+        assert_eq!(instructions[3].length, 2);
+    }
+
+    #[test]
+    fn bank_with_labels() {
+        let mut rom = vec![0; 0x8000];
+        // Loop: dec a; jr nz, Loop; jp $0150
+        rom[0x4000..0x4006].copy_from_slice(&[0x3D, 0x20, 0xFD, 0xC3, 0x50, 0x01]);
+
+        let mut symbols = Symbols::default();
+        symbols.insert(1, 0x4000, "Loop");
+        symbols.insert(0, 0x0150, "Start");
+
+        let source = rgbds_bank(&rom, 1, &symbols).unwrap();
+        let lines = source.lines().take(6).collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                "SECTION \"ROM Bank $01\", ROMX[$4000], BANK[$01]",
+                "",
+                "Loop:",
+                "    dec a                   ; $4000: 3D",
+                "    jr nz, Loop             ; $4001: 20 FD",
+                // Start isn't defined in this bank
+                "    jp $0150                ; $4003: C3 50 01",
+            ]
+        );
+        assert!(rgbds_bank(&rom, 2, &symbols).is_none());
+    }
+
+    #[test]
+    fn truncated_instruction() {
+        let instructions = disassemble(&[0x00, 0xC3, 0x50], 0x0000, 0);
+
+        assert_eq!(instructions.len(), 3);
+        assert_eq!(instructions[1].opcode, None);
+        assert_eq!(instructions[1].to_string(), "db $C3");
+    }
+}
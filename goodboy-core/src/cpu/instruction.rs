@@ -112,7 +112,7 @@ impl<'a> Opcode<'a> {
         cycles: u32,
         instruction: Instruction,
         name: &'a str,
-    ) -> Opcode {
+    ) -> Opcode<'a> {
         Opcode {
             code,
             instruction,
@@ -130,7 +130,7 @@ impl<'a> Opcode<'a> {
         cycles: u32,
         instruction: Instruction,
         name: &'a str,
-    ) -> Opcode {
+    ) -> Opcode<'a> {
         Opcode {
             code,
             instruction,
@@ -152,7 +152,7 @@ impl<'a> Eq for Opcode<'a> {}
 
 impl<'a> PartialOrd for Opcode<'a> {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.code.cmp(&other.code))
+        Some(self.cmp(other))
     }
 }
 
@@ -171,12 +171,12 @@ macro_rules! unused_instruction {
 lazy_static::lazy_static! {
     pub static ref OPCODE_MAP: HashMap<u8, &'static Opcode<'static>> = {
         OPCODE_VEC.iter()
-            .filter_map(|opcode| (!opcode.prefixed).then(|| (opcode.code, opcode)))
+            .filter_map(|opcode| (!opcode.prefixed).then_some((opcode.code, opcode)))
             .collect::<HashMap<_, _>>()
     };
     pub static ref CB_OPCODE_MAP: HashMap<u8, &'static Opcode<'static>> = {
         OPCODE_VEC.iter()
-            .filter_map(|opcode| opcode.prefixed.then(|| (opcode.code, opcode)))
+            .filter_map(|opcode| opcode.prefixed.then_some((opcode.code, opcode)))
             .collect::<HashMap<_, _>>()
     };
 
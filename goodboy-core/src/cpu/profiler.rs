@@ -0,0 +1,315 @@
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    fmt::Write,
+};
+
+use crate::symbols::Symbols;
+
+/// Clocks in a frame at single speed
+const FRAME_CLOCKS: u64 = 70224;
+
+/// The call stack is dropped past this depth, some games never return from their calls
+const MAX_DEPTH: usize = 256;
+
+/// A `bank:addr` location
+type Location = (usize, u16);
+
+/// How an instruction changed the call stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Flow {
+    Step,
+    /// A taken `CALL` or `RST`, or an interrupt, `sp` is after the return address push
+    Call {
+        target: Location,
+        sp: u16,
+    },
+    /// A taken `RET` or `RETI`, `sp` is before the return address pop
+    Return {
+        sp: u16,
+    },
+}
+
+/// A function entered with `CALL`, `RST` or an interrupt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionProfile {
+    pub bank: usize,
+    pub addr: u16,
+    /// The label of the symbol file, or `bank:addr`
+    pub name: String,
+    pub calls: u64,
+    /// Clocks spent in the function itself
+    pub self_cycles: u64,
+    /// Clocks spent in the function and everything it called
+    pub total_cycles: u64,
+}
+
+/// An instruction address and the clocks spent executing it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HotSpot {
+    pub bank: usize,
+    pub addr: u16,
+    /// The closest label, e.g. `Main.loop+$3`, or `bank:addr`
+    pub name: String,
+    pub cycles: u64,
+}
+
+/// Attribute the executed clocks to the instructions and, following `CALL` and `RET`,
+/// to the functions on the call stack
+#[derive(Debug, Default)]
+pub struct Profiler {
+    cycles: u64,
+    addresses: HashMap<Location, u64>,
+    calls: HashMap<Location, u64>,
+    /// Clocks per call stack, the folded stacks
+    stacks: HashMap<Vec<Location>, u64>,
+
+    // the current call stack, the functions and the SP of their return address
+    stack: Vec<Location>,
+    stack_sp: Vec<u16>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record an instruction at `bank:pc` that took `clocks`
+    pub(crate) fn record(&mut self, bank: usize, pc: u16, clocks: u32, flow: Flow) {
+        let clocks = u64::from(clocks);
+
+        // the function running when the profiler started, or after returning from it
+        if self.stack.is_empty() {
+            self.stack.push((bank, pc));
+            self.stack_sp.push(0xFFFF);
+        }
+
+        self.cycles += clocks;
+        *self.addresses.entry((bank, pc)).or_default() += clocks;
+        match self.stacks.get_mut(self.stack.as_slice()) {
+            Some(cycles) => *cycles += clocks,
+            None => {
+                self.stacks.insert(self.stack.clone(), clocks);
+            }
+        }
+
+        match flow {
+            Flow::Step => {}
+            Flow::Call { target, sp } => {
+                if self.stack.len() >= MAX_DEPTH {
+                    self.stack.remove(0);
+                    self.stack_sp.remove(0);
+                }
+                self.stack.push(target);
+                self.stack_sp.push(sp);
+                *self.calls.entry(target).or_default() += 1;
+            }
+            Flow::Return { sp } => {
+                // also unwinds the frames whose return address was dropped from the stack
+                while self.stack_sp.last().is_some_and(|&frame_sp| frame_sp <= sp) {
+                    self.stack.pop();
+                    self.stack_sp.pop();
+                }
+            }
+        }
+    }
+
+    /// The clocks recorded since the profiler started
+    pub fn total_cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// The functions sorted by their total clocks, most expensive first
+    pub fn functions(&self, symbols: &Symbols) -> Vec<FunctionProfile> {
+        let mut functions = HashMap::<Location, (u64, u64)>::new();
+
+        for (stack, &cycles) in &self.stacks {
+            if let Some(&function) = stack.last() {
+                functions.entry(function).or_default().0 += cycles;
+            }
+            // a recursive function only counts once
+            for &function in stack.iter().collect::<HashSet<_>>() {
+                functions.entry(function).or_default().1 += cycles;
+            }
+        }
+
+        let mut functions = functions
+            .into_iter()
+            .map(
+                |((bank, addr), (self_cycles, total_cycles))| FunctionProfile {
+                    bank,
+                    addr,
+                    name: function_name(symbols, (bank, addr)),
+                    calls: self.calls.get(&(bank, addr)).copied().unwrap_or_default(),
+                    self_cycles,
+                    total_cycles,
+                },
+            )
+            .collect::<Vec<_>>();
+        functions.sort_by_key(|f| {
+            (
+                Reverse(f.total_cycles),
+                Reverse(f.self_cycles),
+                f.bank,
+                f.addr,
+            )
+        });
+        functions
+    }
+
+    /// The `count` most expensive instructions
+    pub fn hot_spots(&self, symbols: &Symbols, count: usize) -> Vec<HotSpot> {
+        let mut addresses = self.addresses.iter().collect::<Vec<_>>();
+        addresses.sort_by_key(|&(location, cycles)| (Reverse(*cycles), *location));
+
+        addresses
+            .into_iter()
+            .take(count)
+            .map(|(&(bank, addr), &cycles)| HotSpot {
+                bank,
+                addr,
+                name: symbols
+                    .locate(bank, addr)
+                    .unwrap_or_else(|| format!("{bank:02X}:{addr:04X}")),
+                cycles,
+            })
+            .collect()
+    }
+
+    /// A human readable report of the functions and the hot spots
+    pub fn report(&self, symbols: &Symbols) -> String {
+        let total = self.cycles.max(1);
+        let percent = |cycles: u64| cycles as f64 * 100.0 / total as f64;
+        let frames = self.cycles as f64 / FRAME_CLOCKS as f64;
+
+        let mut report = String::new();
+        let _ = writeln!(
+            report,
+            "{} clocks, {frames:.1} frames at single speed\n",
+            self.cycles
+        );
+
+        let _ = writeln!(
+            report,
+            "{:>8} {:>8} {:>10} {:>8}  Function",
+            "Total%", "Self%", "Clk/frame", "Calls"
+        );
+        for function in self.functions(symbols) {
+            let _ = writeln!(
+                report,
+                "{:>7.2}% {:>7.2}% {:>10.0} {:>8}  {}",
+                percent(function.total_cycles),
+                percent(function.self_cycles),
+                function.total_cycles as f64 / frames.max(1.0),
+                function.calls,
+                function.name,
+            );
+        }
+
+        let _ = writeln!(report, "\n{:>8}  Hot spot", "Self%");
+        for spot in self.hot_spots(symbols, 20) {
+            let _ = writeln!(report, "{:>7.2}%  {}", percent(spot.cycles), spot.name);
+        }
+
+        report
+    }
+
+    /// The call stacks in the folded format of flamegraph tools: `Main;Update;Draw 1234`
+    pub fn folded(&self, symbols: &Symbols) -> String {
+        let mut lines = self
+            .stacks
+            .iter()
+            .map(|(stack, cycles)| {
+                let names = stack
+                    .iter()
+                    .map(|&function| function_name(symbols, function))
+                    .collect::<Vec<_>>();
+                format!("{} {cycles}\n", names.join(";"))
+            })
+            .collect::<Vec<_>>();
+        lines.sort();
+        lines.concat()
+    }
+}
+
+/// The label at the start of a function, a call target without label isn't a part of
+/// the function before it
+fn function_name(symbols: &Symbols, (bank, addr): Location) -> String {
+    match symbols.name(bank, addr) {
+        Some(name) => name.to_string(),
+        None => format!("{bank:02X}:{addr:04X}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follow_calls() {
+        let mut profiler = Profiler::new();
+
+        // Main calls Sub twice, Sub calls Leaf once
+        profiler.record(0, 0x0150, 4, Flow::Step);
+        for _ in 0..2 {
+            profiler.record(
+                0,
+                0x0151,
+                24,
+                Flow::Call {
+                    target: (1, 0x4000),
+                    sp: 0xFFFC,
+                },
+            );
+            profiler.record(1, 0x4000, 8, Flow::Step);
+            profiler.record(1, 0x4001, 16, Flow::Return { sp: 0xFFFC });
+        }
+        profiler.record(
+            0,
+            0x0154,
+            24,
+            Flow::Call {
+                target: (1, 0x4000),
+                sp: 0xFFFC,
+            },
+        );
+        profiler.record(
+            1,
+            0x4000,
+            24,
+            Flow::Call {
+                target: (1, 0x5000),
+                sp: 0xFFFA,
+            },
+        );
+        profiler.record(1, 0x5000, 4, Flow::Step);
+        // Leaf drops its return address and returns to Main directly
+        profiler.record(1, 0x5001, 16, Flow::Return { sp: 0xFFFC });
+        profiler.record(0, 0x0157, 4, Flow::Step);
+
+        let mut symbols = Symbols::default();
+        symbols.insert(0, 0x0150, "Main");
+        symbols.insert(1, 0x4000, "Sub");
+
+        let functions = profiler.functions(&symbols);
+        let summary = functions
+            .iter()
+            .map(|f| (f.name.as_str(), f.calls, f.self_cycles, f.total_cycles))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                ("Main", 0, 80, 172),
+                ("Sub", 3, 72, 92),
+                ("01:5000", 1, 20, 20),
+            ]
+        );
+
+        assert_eq!(profiler.total_cycles(), 172);
+        assert_eq!(
+            profiler.folded(&symbols),
+            "Main 80\nMain;Sub 72\nMain;Sub;01:5000 20\n"
+        );
+        assert_eq!(profiler.hot_spots(&symbols, 1)[0].name, "Main+$1");
+    }
+}
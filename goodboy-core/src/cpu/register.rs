@@ -111,6 +111,17 @@ impl Registers {
     }
 }
 
+impl std::fmt::Debug for Registers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("REGS")
+            .field("AF", &self.af())
+            .field("BC", &self.bc())
+            .field("DE", &self.de())
+            .field("HL", &self.hl())
+            .finish()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -183,14 +194,3 @@ mod tests {
         assert!(f.contains(Flags::C));
     }
 }
-
-impl std::fmt::Debug for Registers {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("REGS")
-            .field("AF", &self.af())
-            .field("BC", &self.bc())
-            .field("DE", &self.de())
-            .field("HL", &self.hl())
-            .finish()
-    }
-}
@@ -0,0 +1,138 @@
+use std::{
+    fs,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use super::{disassembler, Cpu};
+
+/// Extra fields appended to the gameboy-doctor line
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TraceOptions {
+    /// The current scan line (`LY:90`)
+    pub ly: bool,
+    /// The clocks elapsed since the trace started (`CY:1234`)
+    pub cycles: bool,
+    /// The instruction about to be executed (`; jp $0150`)
+    pub disassembly: bool,
+}
+
+/// Log the CPU state before every instruction, in the gameboy-doctor format:
+///
+/// `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`
+pub struct Tracer {
+    writer: Box<dyn Write + Send>,
+    options: TraceOptions,
+    cycles: u64,
+}
+
+impl Tracer {
+    pub fn new(writer: impl Write + Send + 'static, options: TraceOptions) -> Self {
+        Tracer {
+            writer: Box::new(writer),
+            options,
+            cycles: 0,
+        }
+    }
+
+    pub fn to_file(path: impl AsRef<Path>, options: TraceOptions) -> io::Result<Self> {
+        let file = fs::File::create(path)?;
+        Ok(Self::new(BufWriter::new(file), options))
+    }
+
+    pub(crate) fn add_cycles(&mut self, clocks: u32) {
+        self.cycles += u64::from(clocks);
+    }
+
+    pub(crate) fn log(&mut self, cpu: &Cpu) -> io::Result<()> {
+        let regs = &cpu.regs;
+        let pcmem = [0, 1, 2, 3].map(|i| cpu.bus.read(cpu.pc.wrapping_add(i)));
+
+        write!(
+            self.writer,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} \
+             SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            regs.a,
+            regs.f.bits(),
+            regs.b,
+            regs.c,
+            regs.d,
+            regs.e,
+            regs.h,
+            regs.l,
+            cpu.sp,
+            cpu.pc,
+            pcmem[0],
+            pcmem[1],
+            pcmem[2],
+            pcmem[3],
+        )?;
+
+        if self.options.ly {
+            write!(self.writer, " LY:{:02X}", cpu.bus.read(0xFF44))?;
+        }
+        if self.options.cycles {
+            write!(self.writer, " CY:{}", self.cycles)?;
+        }
+        if self.options.disassembly {
+            let instruction = disassembler::decode(&pcmem[..3], cpu.pc, cpu.bus.bank_at(cpu.pc));
+            let text = instruction.format_with(|addr| cpu.bus.symbol_at(addr).map(String::from));
+            write!(self.writer, " ; {text}")?;
+        }
+
+        writeln!(self.writer)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::mmu::Bus;
+
+    /// A writer that can be read after being moved into the tracer
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn gameboy_doctor_format() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+
+        let buffer = SharedBuffer::default();
+        let options = TraceOptions {
+            cycles: true,
+            disassembly: true,
+            ..Default::default()
+        };
+
+        let mut cpu = Cpu::new(Bus::new(&rom));
+        cpu.set_tracer(Some(Tracer::new(buffer.clone(), options)));
+        cpu.tick().unwrap();
+        cpu.tick().unwrap();
+
+        let log = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines = log.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,50,01 CY:0 ; nop",
+                "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0101 PCMEM:C3,50,01,00 CY:4 ; jp $0150",
+            ]
+        );
+    }
+}
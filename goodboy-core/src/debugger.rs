@@ -0,0 +1,355 @@
+use std::{cell::Cell, fmt::Display, ops::RangeInclusive};
+
+use crate::{cpu::Cpu, symbols::Symbols};
+
+/// A CPU register, as seen by the breakpoint conditions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+    PC,
+}
+
+impl Register {
+    pub fn read(&self, cpu: &Cpu) -> u16 {
+        let regs = &cpu.regs;
+        match self {
+            Register::A => regs.a as u16,
+            Register::F => regs.f.bits() as u16,
+            Register::B => regs.b as u16,
+            Register::C => regs.c as u16,
+            Register::D => regs.d as u16,
+            Register::E => regs.e as u16,
+            Register::H => regs.h as u16,
+            Register::L => regs.l as u16,
+            Register::AF => regs.af(),
+            Register::BC => regs.bc(),
+            Register::DE => regs.de(),
+            Register::HL => regs.hl(),
+            Register::SP => cpu.sp,
+            Register::PC => cpu.pc,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// Compare a register to a value, e.g. `A == 0x10`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BreakCondition {
+    pub register: Register,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+
+impl BreakCondition {
+    pub fn matches(&self, cpu: &Cpu) -> bool {
+        let register = self.register.read(cpu);
+        match self.comparison {
+            Comparison::Eq => register == self.value,
+            Comparison::Ne => register != self.value,
+            Comparison::Lt => register < self.value,
+            Comparison::Le => register <= self.value,
+            Comparison::Gt => register > self.value,
+            Comparison::Ge => register >= self.value,
+        }
+    }
+}
+
+/// Stop before executing an instruction. A breakpoint without address stops at any
+/// address where its condition matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Breakpoint {
+    pub addr: Option<u16>,
+    /// The bank mapped at `addr` (ROM, cartridge RAM or WRAM), any bank if `None`
+    pub bank: Option<usize>,
+    pub condition: Option<BreakCondition>,
+}
+
+impl Breakpoint {
+    pub fn at(addr: u16) -> Self {
+        Breakpoint {
+            addr: Some(addr),
+            ..Default::default()
+        }
+    }
+
+    pub fn at_bank(bank: usize, addr: u16) -> Self {
+        Breakpoint {
+            addr: Some(addr),
+            bank: Some(bank),
+            ..Default::default()
+        }
+    }
+
+    /// A breakpoint at a label of the symbol file
+    pub fn at_symbol(symbols: &Symbols, name: &str) -> Option<Self> {
+        let (bank, addr) = symbols.address(name)?;
+        Some(Self::at_bank(bank, addr))
+    }
+
+    pub fn with_condition(self, condition: BreakCondition) -> Self {
+        Breakpoint {
+            condition: Some(condition),
+            ..self
+        }
+    }
+
+    pub fn matches(&self, cpu: &Cpu) -> bool {
+        self.addr.is_none_or(|addr| addr == cpu.pc)
+            && self.bank.is_none_or(|bank| bank == cpu.bus.bank_at(cpu.pc))
+            && self
+                .condition
+                .is_none_or(|condition| condition.matches(cpu))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn matches(&self, access: Access) -> bool {
+        matches!(
+            (self, access),
+            (WatchKind::ReadWrite, _)
+                | (WatchKind::Read, Access::Read)
+                | (WatchKind::Write, Access::Write)
+        )
+    }
+}
+
+/// Stop after an instruction accesses an address in `range`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub range: RangeInclusive<u16>,
+    pub kind: WatchKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    /// Index of the watchpoint
+    pub index: usize,
+    pub addr: u16,
+    /// The value read or written
+    pub value: u8,
+    pub access: Access,
+}
+
+/// Why the execution stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The requested step is done
+    Step,
+    /// Stopped before the instruction at PC, the index of the breakpoint
+    Breakpoint(usize),
+    /// Stopped after the instruction that triggered the watchpoint
+    Watchpoint(WatchHit),
+    /// The clocks limit was reached
+    ClocksElapsed,
+}
+
+impl Display for StopReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StopReason::Step => write!(f, "step"),
+            StopReason::Breakpoint(index) => write!(f, "breakpoint #{index}"),
+            StopReason::Watchpoint(hit) => write!(
+                f,
+                "watchpoint #{}: {:?} 0x{:02X} at 0x{:04X}",
+                hit.index, hit.access, hit.value, hit.addr
+            ),
+            StopReason::ClocksElapsed => write!(f, "clocks limit reached"),
+        }
+    }
+}
+
+/// Breakpoints, watchpoints and symbols
+#[derive(Debug, Default)]
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    symbols: Symbols,
+    // the memory reads don't have mutable access to the bus
+    watch_hit: Cell<Option<WatchHit>>,
+}
+
+impl Debugger {
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        self.breakpoints.push(breakpoint);
+        self.breakpoints.len() - 1
+    }
+
+    pub fn remove_breakpoint(&mut self, index: usize) -> Option<Breakpoint> {
+        (index < self.breakpoints.len()).then(|| self.breakpoints.remove(index))
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        self.watchpoints.push(watchpoint);
+        self.watchpoints.len() - 1
+    }
+
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<Watchpoint> {
+        (index < self.watchpoints.len()).then(|| self.watchpoints.remove(index))
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    /// The index of the first breakpoint matching the CPU state
+    pub fn check_breakpoints(&self, cpu: &Cpu) -> Option<usize> {
+        self.breakpoints.iter().position(|bp| bp.matches(cpu))
+    }
+
+    /// Record a memory access, only the first hit is kept until it's taken
+    pub fn check_access(&self, addr: u16, value: u8, access: Access) {
+        if self.watchpoints.is_empty() || self.watch_hit.get().is_some() {
+            return;
+        }
+
+        let index = self
+            .watchpoints
+            .iter()
+            .position(|wp| wp.range.contains(&addr) && wp.kind.matches(access));
+
+        if let Some(index) = index {
+            self.watch_hit.set(Some(WatchHit {
+                index,
+                addr,
+                value,
+                access,
+            }));
+        }
+    }
+
+    pub fn take_watch_hit(&self) -> Option<WatchHit> {
+        self.watch_hit.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::Vm;
+
+    /// A 32 KiB ROM calling a subroutine at 0x0200 that writes A to 0xC000
+    fn program() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        // CALL 0x0200; NOP; JR -2
+        rom[0x100..0x106].copy_from_slice(&[0xCD, 0x00, 0x02, 0x00, 0x18, 0xFE]);
+        // LD A,0x42; LD (0xC000),A; RET
+        rom[0x200..0x206].copy_from_slice(&[0x3E, 0x42, 0xEA, 0x00, 0xC0, 0xC9]);
+        rom
+    }
+
+    #[test]
+    fn step_over_and_out() {
+        let mut vm = Vm::new(&program());
+
+        assert_eq!(vm.step_over(), StopReason::Step);
+        assert_eq!((vm.cpu().pc, vm.cpu().regs.a), (0x0103, 0x42));
+
+        let mut vm = Vm::new(&program());
+        vm.step();
+        vm.step();
+        assert_eq!(vm.cpu().pc, 0x0202);
+        assert_eq!(vm.step_out(), StopReason::Step);
+        assert_eq!(vm.cpu().pc, 0x0103);
+    }
+
+    #[test]
+    fn stop_at_breakpoints_and_watchpoints() {
+        let mut vm = Vm::new(&program());
+        let condition = BreakCondition {
+            register: Register::A,
+            comparison: Comparison::Eq,
+            value: 0x42,
+        };
+        vm.add_breakpoint(Breakpoint::at_bank(0, 0x0202).with_condition(condition));
+        vm.add_watchpoint(Watchpoint {
+            range: 0xC000..=0xC000,
+            kind: WatchKind::Write,
+        });
+
+        assert_eq!(vm.run_until(1000), StopReason::Breakpoint(0));
+        assert_eq!(vm.cpu().pc, 0x0202);
+
+        let hit = WatchHit {
+            index: 0,
+            addr: 0xC000,
+            value: 0x42,
+            access: Access::Write,
+        };
+        assert_eq!(vm.run_until(1000), StopReason::Watchpoint(hit));
+        assert_eq!(vm.run_until(1000), StopReason::ClocksElapsed);
+    }
+
+    #[test]
+    fn watchpoint_kind() {
+        let mut debugger = Debugger::default();
+        debugger.add_watchpoint(Watchpoint {
+            range: 0xC000..=0xC0FF,
+            kind: WatchKind::Write,
+        });
+
+        debugger.check_access(0xC010, 0x12, Access::Read);
+        assert_eq!(debugger.take_watch_hit(), None);
+
+        debugger.check_access(0xC100, 0x12, Access::Write);
+        assert_eq!(debugger.take_watch_hit(), None);
+
+        debugger.check_access(0xC010, 0x34, Access::Write);
+        debugger.check_access(0xC020, 0x56, Access::Write);
+        assert_eq!(
+            debugger.take_watch_hit(),
+            Some(WatchHit {
+                index: 0,
+                addr: 0xC010,
+                value: 0x34,
+                access: Access::Write,
+            })
+        );
+        assert_eq!(debugger.take_watch_hit(), None);
+    }
+}
@@ -1,15 +1,45 @@
 // use std::io::Write;
 
-use crate::mmu::MemoryAccess;
+use crate::{gb_mode::GbMode, mmu::MemoryAccess};
+
+/// The CPU clocks per bit of the internal clock, 8192 Hz or 262144 Hz with the CGB
+/// fast clock. The clock follows the CPU speed.
+const BIT_CLOCKS: u32 = 512;
+const FAST_BIT_CLOCKS: u32 = 16;
 
 #[derive(Default)]
 pub struct Serial {
+    /// The fast clock only exists on CGB
+    gb_mode: GbMode,
     data: u8,
     control: u8,
+    /// The CPU clocks left in the transfer started with the internal clock
+    transfer_clocks: u32,
     pub interrupt: u8,
 }
 
 impl Serial {
+    pub fn new(gb_mode: GbMode) -> Self {
+        Serial {
+            gb_mode,
+            ..Default::default()
+        }
+    }
+
+    /// Shift the byte out, there is no link cable so 1s are shifted in
+    pub fn sync(&mut self, clocks: u32) {
+        if self.transfer_clocks == 0 {
+            return;
+        }
+
+        self.transfer_clocks = self.transfer_clocks.saturating_sub(clocks);
+        if self.transfer_clocks == 0 {
+            self.data = 0xFF;
+            self.control &= 0x7F;
+            self.interrupt = 0x08;
+        }
+    }
+
     fn display(&self) {
         let data = self.data;
         let char_data = char::from_u32(data as u32).unwrap().escape_default();
@@ -27,7 +57,8 @@ impl MemoryAccess for Serial {
     fn mem_read(&self, addr: u16) -> u8 {
         match addr {
             0xFF01 => self.data,
-            0xFF02 => self.control,
+            0xFF02 if self.gb_mode == GbMode::Cgb => self.control | 0x7C,
+            0xFF02 => self.control | 0x7E,
             _ => panic!("Invalid Serial address"),
         }
     }
@@ -35,14 +66,45 @@ impl MemoryAccess for Serial {
         match addr {
             0xFF01 => self.data = value,
             0xFF02 => {
-                if value == 0x81 {
+                self.control = value;
+                // with an external clock the transfer waits for the other Game Boy
+                if value & 0x81 == 0x81 {
                     self.display();
 
-                    self.data = value;
-                    self.interrupt = 0x08;
+                    let fast = self.gb_mode == GbMode::Cgb && value & 0x02 != 0;
+                    let bit_clocks = if fast { FAST_BIT_CLOCKS } else { BIT_CLOCKS };
+                    self.transfer_clocks = 8 * bit_clocks;
                 }
             }
             _ => panic!("Invalid Serial address"),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transfer_clocks(serial: &mut Serial) -> u32 {
+        serial.mem_write(0xFF02, 0x83);
+        let mut clocks = 0;
+        while serial.interrupt == 0 {
+            serial.sync(4);
+            clocks += 4;
+        }
+        clocks
+    }
+
+    #[test]
+    fn fast_clock_on_cgb_only() {
+        let mut serial = Serial::new(GbMode::Cgb);
+        assert_eq!(serial.mem_read(0xFF02), 0x7C);
+        assert_eq!(transfer_clocks(&mut serial), 8 * FAST_BIT_CLOCKS);
+        assert_eq!(serial.mem_read(0xFF02), 0x7F);
+
+        let mut serial = Serial::new(GbMode::Dmg);
+        assert_eq!(serial.mem_read(0xFF02), 0x7E);
+        assert_eq!(transfer_clocks(&mut serial), 8 * BIT_CLOCKS);
+        assert_eq!(serial.mem_read(0xFF02), 0x7F);
+    }
+}
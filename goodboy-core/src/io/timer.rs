@@ -17,6 +17,12 @@ pub struct Timer {
 }
 
 impl Timer {
+    /// A write to DIV or STOP resets the divider
+    pub fn reset_div(&mut self) {
+        self.div = 0;
+        self.div_clocks = 0;
+    }
+
     pub fn sync(&mut self, clocks: u32) {
         self.div_clocks += clocks;
         while self.div_clocks >= 256 {
@@ -68,7 +74,7 @@ impl MemoryAccess for Timer {
     }
     fn mem_write(&mut self, addr: u16, value: u8) {
         match addr {
-            0xFF04 => self.div = 0,
+            0xFF04 => self.reset_div(),
             0xFF05 => self.counter = value,
             0xFF06 => self.modulo = value,
             0xFF07 => {
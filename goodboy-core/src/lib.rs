@@ -1,7 +1,10 @@
+pub mod cheats;
 pub mod cpu;
+pub mod debugger;
 mod gb_mode;
 pub mod io;
 pub mod mmu;
 pub mod ppu;
+pub mod symbols;
 pub mod utils;
 pub mod vm;
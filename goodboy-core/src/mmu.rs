@@ -2,11 +2,17 @@ mod mbc;
 mod system_bus;
 
 pub mod cartridge;
+pub mod cdl;
 mod dma;
+pub mod patch;
 
 pub use mbc::Mbc;
 pub use system_bus::Bus;
 
+/// The 8 WRAM banks of the CGB, the DMG only uses the first 2
+pub const WRAM_SIZE: usize = 0x8000;
+pub const HRAM_SIZE: usize = 0x7F;
+
 bitflags::bitflags! {
     #[derive(Default)]
     pub struct InterruptFlags: u8 {
@@ -2,14 +2,21 @@
 
 use std::fmt::Debug;
 
-use crate::gb_mode::GbMode;
+use crate::{gb_mode::GbMode, utils::crc32};
 
-use super::mbc::{self, Mbc};
+use super::{
+    mbc::{self, Mbc},
+    patch::{self, Patch, PatchError},
+};
 
 pub const MBC_KIND_ADDR: usize = 0x147;
 pub const TITLE_ADDR: usize = 0x134;
 pub const GB_MODE_ADDR: usize = 0x143;
 pub const RAM_SIZE_ADDR: usize = 0x149;
+pub const HEADER_CHECKSUM_ADDR: usize = 0x14D;
+
+/// Size of the MMM01 menu, which holds the real header of the multicart
+const MMM01_MENU_SIZE: usize = 0x8000;
 
 pub struct Cartridge {
     title: String,
@@ -20,7 +27,29 @@ pub struct Cartridge {
 
 impl Cartridge {
     pub fn new(rom: &[u8]) -> Cartridge {
-        let mode = match rom[GB_MODE_ADDR] {
+        Self::with_save_key(rom, None)
+    }
+
+    /// Apply `patches` to `rom`, in order, then create the cartridge from the patched ROM.
+    ///
+    /// Patched cartridges save the game to their own file, keyed by the CRC32 of the
+    /// patched ROM, so a ROM hack never overwrites the save of the original game.
+    pub fn with_patches(rom: &[u8], patches: &[Patch]) -> Result<Cartridge, PatchError> {
+        if patches.is_empty() {
+            return Ok(Self::new(rom));
+        }
+
+        let rom = patch::apply_all(rom, patches)?;
+        let save_key = crc32(&rom);
+
+        Ok(Self::with_save_key(&rom, Some(save_key)))
+    }
+
+    fn with_save_key(rom: &[u8], save_key: Option<u32>) -> Cartridge {
+        let header_offset = header_offset(rom);
+        let header = &rom[header_offset..];
+
+        let mode = match header[GB_MODE_ADDR] {
             // CGB only
             0xC0 => GbMode::Cgb,
             // game works on both CGB and DMG
@@ -32,7 +61,7 @@ impl Cartridge {
             _ => GbMode::Dmg,
         };
 
-        let ram_size = match rom[RAM_SIZE_ADDR] {
+        let ram_size = match header[RAM_SIZE_ADDR] {
             0x00 => 0x00000, // No RAM
             0x01 => 0x00800, // Undocumented
             0x02 => 0x02000, // 8 KB
@@ -45,18 +74,23 @@ impl Cartridge {
         let title_len = if mode == GbMode::Dmg { 16 } else { 11 };
         let title = (0..title_len)
             .filter_map(|i| {
-                rom.get(TITLE_ADDR + i)
+                header
+                    .get(TITLE_ADDR + i)
                     .copied()
                     .filter(|byte| *byte != 0)
                     .map(char::from)
             })
             .collect::<String>();
 
-        let save_path = format!("{}.gbsave", title.to_ascii_lowercase());
+        let save_path = match save_key {
+            Some(key) => format!("{}-{key:08x}.gbsave", title.to_ascii_lowercase()),
+            None => format!("{}.gbsave", title.to_ascii_lowercase()),
+        };
 
-        let mbc = match rom[MBC_KIND_ADDR] {
+        let mbc = match header[MBC_KIND_ADDR] {
             0x00 => mbc::Mbc0::new(rom.to_owned()),
             0x01..=0x03 => mbc::Mbc1::new(rom.to_owned(), ram_size, &save_path),
+            0x0B..=0x0D => mbc::Mmm01::new(rom.to_owned(), header_offset, ram_size, &save_path),
             0x0F..=0x13 => mbc::Mbc3::new(rom.to_owned(), ram_size, &save_path),
             0x19..=0x1B => mbc::Mbc5::new(rom.to_owned(), ram_size, &save_path),
             _ => panic!("Unsupported cartridge MBC"),
@@ -75,6 +109,35 @@ impl Cartridge {
     }
 }
 
+/// Compute the header checksum of the given bank 0
+pub fn header_checksum(bank0: &[u8]) -> u8 {
+    bank0[TITLE_ADDR..HEADER_CHECKSUM_ADDR]
+        .iter()
+        .fold(0u8, |checksum, byte| {
+            checksum.wrapping_sub(*byte).wrapping_sub(1)
+        })
+}
+
+/// Return where the cartridge header bank starts in `rom`.
+///
+/// MMM01 multicarts boot from their menu, stored in the last 32 KiB of the ROM, so the
+/// header found in the first bank belongs to the first game instead of the cartridge.
+pub fn header_offset(rom: &[u8]) -> usize {
+    let is_mmm01 = |bank0: &[u8]| {
+        bank0.len() > HEADER_CHECKSUM_ADDR
+            && matches!(bank0[MBC_KIND_ADDR], 0x0B..=0x0D)
+            && header_checksum(bank0) == bank0[HEADER_CHECKSUM_ADDR]
+    };
+
+    match rom.len().checked_sub(MMM01_MENU_SIZE) {
+        Some(menu) if menu > 0 && !is_mmm01(rom) && is_mmm01(&rom[menu..]) => {
+            log::info!("Found a MMM01 header at 0x{menu:X}");
+            menu
+        }
+        _ => 0,
+    }
+}
+
 impl Mbc for Cartridge {
     fn ram_read(&self, addr: u16) -> u8 {
         self.mbc.ram_read(addr)
@@ -88,6 +151,27 @@ impl Mbc for Cartridge {
     fn rom_write(&mut self, addr: u16, value: u8) {
         self.mbc.rom_write(addr, value)
     }
+    fn rom_bank(&self, addr: u16) -> usize {
+        self.mbc.rom_bank(addr)
+    }
+    fn ram_bank(&self) -> usize {
+        self.mbc.ram_bank()
+    }
+    fn rom(&self) -> &[u8] {
+        self.mbc.rom()
+    }
+    fn rom_mut(&mut self) -> &mut [u8] {
+        self.mbc.rom_mut()
+    }
+    fn ram(&self) -> &[u8] {
+        self.mbc.ram()
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        self.mbc.ram_mut()
+    }
+    fn description(&self) -> Option<mbc::MbcDescription<'_>> {
+        self.mbc.description()
+    }
 }
 
 impl Debug for Cartridge {
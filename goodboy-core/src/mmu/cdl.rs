@@ -0,0 +1,250 @@
+use std::cell::Cell;
+
+use super::{cartridge::Cartridge, Mbc, WRAM_SIZE};
+
+/// The header of BizHawk's CDL files
+const MAGIC: &str = "BIZHAWK-CDL-2";
+/// The core type of the Game Boy cores, padded to 15 characters in the file
+const SUB_TYPE: &str = "GB";
+
+bitflags::bitflags! {
+    /// How a byte was used, with the GB flags of BizHawk's CDL
+    #[derive(Default)]
+    pub struct Usage: u8 {
+        /// The first byte of an instruction
+        const OPCODE  = 1 << 0;
+        const OPERAND = 1 << 1;
+        /// Read by an instruction or a DMA
+        const DATA    = 1 << 2;
+        /// GoodBoy only, copied by the OAM DMA or the CGB HDMA
+        const DMA     = 1 << 6;
+        /// GoodBoy only, written to RAM
+        const WRITE   = 1 << 7;
+    }
+}
+
+/// The Code/Data Logger, records how every ROM and RAM byte was accessed so a
+/// disassembler can tell the code from the data
+#[derive(Debug, Clone)]
+pub struct CodeDataLogger {
+    rom: Vec<Cell<u8>>,
+    /// The 8 WRAM banks
+    wram: Vec<Cell<u8>>,
+    cart_ram: Vec<Cell<u8>>,
+}
+
+impl CodeDataLogger {
+    pub fn new(rom_size: usize, cart_ram_size: usize) -> Self {
+        CodeDataLogger {
+            rom: vec![Cell::new(0); rom_size],
+            wram: vec![Cell::new(0); WRAM_SIZE],
+            cart_ram: vec![Cell::new(0); cart_ram_size],
+        }
+    }
+
+    /// A logger sized for the ROM and the RAM of `cartridge`
+    pub fn for_cartridge(cartridge: &Cartridge) -> Self {
+        Self::new(cartridge.rom().len(), cartridge.ram().len())
+    }
+
+    /// The blocks of the CDL file, named like BizHawk's memory domains
+    fn blocks(&self) -> impl Iterator<Item = (&'static str, &[Cell<u8>])> {
+        [
+            ("ROM", self.rom.as_slice()),
+            ("WRAM", self.wram.as_slice()),
+            ("CartRAM", self.cart_ram.as_slice()),
+        ]
+        .into_iter()
+        // the games without cartridge RAM have no CartRAM block
+        .filter(|(name, block)| *name != "CartRAM" || !block.is_empty())
+    }
+
+    /// The CDL file in BizHawk's format: the ROM, WRAM and cartridge RAM blocks with a
+    /// [`Usage`] per byte
+    pub fn to_file(&self) -> Vec<u8> {
+        let mut file = Vec::new();
+        write_string(&mut file, MAGIC);
+        write_string(&mut file, &format!("{SUB_TYPE:<15}"));
+        file.extend_from_slice(&(self.blocks().count() as i32).to_le_bytes());
+
+        for (name, block) in self.blocks() {
+            write_string(&mut file, name);
+            file.extend_from_slice(&(block.len() as i32).to_le_bytes());
+            file.extend(block.iter().map(Cell::get));
+        }
+        file
+    }
+
+    /// Continue the CDL file of a previous session, the new accesses are added to it
+    pub fn load_file(&mut self, file: &[u8]) -> Result<(), String> {
+        let mut reader = Reader { data: file };
+
+        if reader.string()? != MAGIC {
+            return Err("Not a BizHawk CDL file".to_string());
+        }
+        let sub_type = reader.string()?;
+        if sub_type.trim_end() != SUB_TYPE {
+            return Err(format!(
+                "The log is for a {} core, not GB",
+                sub_type.trim_end()
+            ));
+        }
+
+        let count = reader.i32()?;
+        if count != self.blocks().count() as i32 {
+            return Err(format!(
+                "The log has {count} blocks, not {}",
+                self.blocks().count()
+            ));
+        }
+
+        for _ in 0..count {
+            let name = reader.string()?;
+            let len = reader.i32()?;
+            let data = reader.bytes(usize::try_from(len).map_err(|_| "Corrupted log")?)?;
+            let Some((_, block)) = self.blocks().find(|(block, _)| *block == name) else {
+                return Err(format!("Unknown block {name}"));
+            };
+            if data.len() != block.len() {
+                return Err(format!(
+                    "The {name} log is for {} bytes, not {} bytes",
+                    data.len(),
+                    block.len()
+                ));
+            }
+
+            for (usage, &old) in block.iter().zip(data) {
+                usage.set(usage.get() | old);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn rom_usage(&self, offset: usize) -> Usage {
+        usage_at(&self.rom, offset)
+    }
+
+    /// The usage of the byte at `offset` in the 32 KiB of WRAM, all banks included
+    pub fn wram_usage(&self, offset: usize) -> Usage {
+        usage_at(&self.wram, offset)
+    }
+
+    pub fn cart_ram_usage(&self, offset: usize) -> Usage {
+        usage_at(&self.cart_ram, offset)
+    }
+
+    pub(crate) fn log_rom(&self, offset: usize, usage: Usage) {
+        // the ROM bank may be out of the ROM on broken headers
+        log(&self.rom, offset, usage);
+    }
+
+    pub(crate) fn log_wram(&self, offset: usize, usage: Usage) {
+        log(&self.wram, offset, usage);
+    }
+
+    pub(crate) fn log_cart_ram(&self, offset: usize, usage: Usage) {
+        log(&self.cart_ram, offset, usage);
+    }
+}
+
+fn usage_at(block: &[Cell<u8>], offset: usize) -> Usage {
+    block
+        .get(offset)
+        .map(|usage| Usage::from_bits_truncate(usage.get()))
+        .unwrap_or_default()
+}
+
+fn log(block: &[Cell<u8>], offset: usize, usage: Usage) {
+    if let Some(cell) = block.get(offset) {
+        cell.set(cell.get() | usage.bits());
+    }
+}
+
+/// A string of .NET's `BinaryWriter`, all the strings of the file are shorter than 128
+/// bytes so their length fits a byte
+fn write_string(file: &mut Vec<u8>, s: &str) {
+    file.push(s.len() as u8);
+    file.extend_from_slice(s.as_bytes());
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if len > self.data.len() {
+            return Err("The log is truncated".to_string());
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.bytes(1)?[0];
+        if len >= 0x80 {
+            return Err("Corrupted log".to_string());
+        }
+        let bytes = self.bytes(len.into())?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| "Corrupted log".to_string())
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        let bytes = self.bytes(4)?;
+        Ok(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::Vm;
+
+    #[test]
+    fn log_code_and_data() {
+        let mut rom = vec![0; 0x8000];
+        // LD A,(0x0200); LD (0xC080),A; CALL NZ,0x0300; LD A,0x40; LDH (0x46),A
+        rom[0x100..0x10D].copy_from_slice(&[
+            0xFA, 0x00, 0x02, 0xEA, 0x80, 0xC0, 0xC4, 0x00, 0x03, 0x3E, 0x40, 0xE0, 0x46,
+        ]);
+
+        let mut vm = Vm::new(&rom);
+        vm.set_cdl(Some(vm.new_cdl()));
+        // the NOPs after the DMA start run until it ends
+        for _ in 0..5 + 170 {
+            vm.step();
+        }
+
+        let cdl = vm.cdl().unwrap();
+        assert_eq!(cdl.rom_usage(0x100), Usage::OPCODE);
+        assert_eq!(cdl.rom_usage(0x101), Usage::OPERAND);
+        assert_eq!(cdl.rom_usage(0x200), Usage::DATA);
+        // the operands of a call not taken
+        assert_eq!(cdl.rom_usage(0x108), Usage::OPERAND);
+        assert_eq!(cdl.rom_usage(0x300), Usage::empty());
+        // the OAM DMA from 0x4000
+        assert_eq!(cdl.rom_usage(0x409F), Usage::DATA | Usage::DMA);
+        assert_eq!(cdl.rom_usage(0x40A0), Usage::empty());
+        assert_eq!(cdl.wram_usage(0x80), Usage::WRITE);
+
+        let file = cdl.to_file();
+        assert_eq!(&file[..14], b"\x0DBIZHAWK-CDL-2");
+        assert_eq!(&file[14..30], b"\x0FGB             ");
+        // the ROM and WRAM blocks
+        assert_eq!(&file[30..34], &[2, 0, 0, 0]);
+        assert_eq!(&file[34..42], b"\x03ROM\x00\x80\x00\x00");
+        assert_eq!(file[42 + 0x100], 0x01);
+        assert_eq!(file.len(), 42 + 0x8000 + 9 + WRAM_SIZE);
+
+        let mut next = CodeDataLogger::new(0x8000, 0);
+        next.load_file(&file).unwrap();
+        assert_eq!(next.rom_usage(0x100), Usage::OPCODE);
+        assert_eq!(next.wram_usage(0x80), Usage::WRITE);
+        assert!(next.load_file(&file[..0x4000]).is_err());
+        assert!(CodeDataLogger::new(0x4000, 0).load_file(&file).is_err());
+        assert!(CodeDataLogger::new(0x8000, 0x2000)
+            .load_file(&file)
+            .is_err());
+    }
+}
@@ -6,7 +6,9 @@ pub enum DmaMode {
     Hdma,
 }
 
-#[derive(Default)]
+/// The dots to copy a block of 16 bytes, 8 M-cycles at single speed and 16 at double speed
+pub const BLOCK_DOTS: u32 = 32;
+
 pub struct Dma {
     // hdma1..hdma4
     pub regs: [u8; 4],
@@ -22,10 +24,50 @@ pub struct Dma {
     pub dst: u16,
 }
 
+impl Default for Dma {
+    fn default() -> Self {
+        Dma {
+            regs: [0; 4],
+            // no transfer, 0xFF55 reads 0xFF
+            dma_length: 0x7F,
+            dma_mode: None,
+            src: 0,
+            dst: 0,
+        }
+    }
+}
+
+impl Dma {
+    /// The VRAM DMA source read by the next block: VRAM can't be copied, it reads 0xFF,
+    /// and the cartridge RAM is read above 0xE000
+    pub fn source(&self) -> Option<u16> {
+        match self.src {
+            0x8000..=0x9FFF => None,
+            0xE000..=0xFFFF => Some(self.src - 0x4000),
+            src => Some(src),
+        }
+    }
+
+    /// Move to the next block, returns true when the transfer is done
+    pub fn next_block(&mut self) -> bool {
+        self.src = self.src.wrapping_add(0x10);
+        self.dst = 0x8000 | (self.dst.wrapping_add(0x10) & 0x1FF0);
+
+        if self.dma_length == 0 {
+            self.dma_length = 0x7F;
+            self.dma_mode = None;
+            true
+        } else {
+            self.dma_length -= 1;
+            false
+        }
+    }
+}
+
 impl MemoryAccess for Dma {
     fn mem_write(&mut self, addr: u16, value: u8) {
         match addr {
-            0xff51 => self.regs[0] = value & 0xFF,
+            0xff51 => self.regs[0] = value,
             0xff52 => self.regs[1] = value & 0xF0,
             0xff53 => self.regs[2] = value & 0x1F,
             0xff54 => self.regs[3] = value & 0xF0,
@@ -38,7 +80,6 @@ impl MemoryAccess for Dma {
                 }
 
                 self.src = ((self.regs[0] as u16) << 8) | (self.regs[1] as u16);
-                assert!(self.src <= 0x7FF0 || (self.src >= 0xA000 && self.src <= 0xDFF0));
                 self.dst = ((self.regs[2] as u16) << 8) | (self.regs[3] as u16) | 0x8000;
 
                 self.dma_mode = Some(match value & 0x80 {
@@ -54,15 +95,71 @@ impl MemoryAccess for Dma {
     fn mem_read(&self, addr: u16) -> u8 {
         match addr {
             0xff51..=0xff54 => self.regs[(addr - 0xFF51) as usize],
-            0xff55 => {
-                self.dma_length
-                    | if let Some(DmaMode::Hdma) = self.dma_mode {
-                        0x80
-                    } else {
-                        0x00
-                    }
-            }
+            // the blocks left minus one, bit 7 is set when no transfer runs: 0xFF after
+            // the end, the blocks left after a cancel
+            0xff55 => match self.dma_mode {
+                Some(DmaMode::Hdma) => self.dma_length,
+                _ => 0x80 | self.dma_length,
+            },
             _ => unreachable!(),
         }
     }
 }
+
+/// The OAM DMA bytes, and the M-cycle before the first one is copied
+const OAM_DMA_LENGTH: u8 = 0xA0;
+const OAM_DMA_DELAY: u32 = 4;
+
+/// The OAM DMA, copies a byte per M-cycle to OAM. The CPU can only use HRAM and the
+/// IO registers during the transfer.
+#[derive(Default)]
+pub struct OamDma {
+    /// 0xFF46, the high byte of the source
+    pub source: u8,
+    /// The next byte copied, `None` when no transfer runs
+    next: Option<u8>,
+    /// The clocks since the transfer started
+    clocks: u32,
+    /// The last byte copied, read by the CPU outside of HRAM
+    pub last: u8,
+}
+
+impl OamDma {
+    /// Start a transfer from `source << 8`, a running transfer restarts
+    pub fn start(&mut self, source: u8) {
+        self.source = source;
+        self.next = Some(0);
+        self.clocks = 0;
+    }
+
+    /// The transfer is running, the first byte may not be copied yet
+    pub fn active(&self) -> bool {
+        self.next.is_some()
+    }
+
+    /// Advance the transfer by `clocks` CPU clocks, see [`OamDma::next_byte`]
+    pub fn sync(&mut self, clocks: u32) {
+        if self.active() {
+            self.clocks += clocks;
+        }
+    }
+
+    /// The source and the OAM addresses of the next byte due, the echo of WRAM is read
+    /// above 0xE000
+    pub fn next_byte(&mut self) -> Option<(u16, u16)> {
+        let index = self.next?;
+        if self.clocks < OAM_DMA_DELAY + 4 * (u32::from(index) + 1) {
+            return None;
+        }
+
+        self.next = (index + 1 < OAM_DMA_LENGTH).then_some(index + 1);
+        let source = match self.source {
+            high @ 0xE0..=0xFF => high - 0x20,
+            high => high,
+        };
+        Some((
+            u16::from_be_bytes([source, index]),
+            0xFE00 | u16::from(index),
+        ))
+    }
+}
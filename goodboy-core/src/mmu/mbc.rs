@@ -1,12 +1,16 @@
+#![allow(clippy::new_ret_no_self)]
+
 mod mbc0;
 mod mbc1;
 mod mbc3;
 mod mbc5;
+mod mmm01;
 
 pub use mbc0::Mbc0;
 pub use mbc1::Mbc1;
 pub use mbc3::Mbc3;
 pub use mbc5::Mbc5;
+pub use mmm01::Mmm01;
 
 #[allow(unused)]
 pub trait Mbc: Send {
@@ -28,6 +32,31 @@ pub trait Mbc: Send {
     /// `addr` must be within (0xA000, 0xC000]
     fn ram_write(&mut self, addr: u16, value: u8) {}
 
+    /// The ROM bank mapped at `addr`
+    /// `addr` must be within (0x0000, 0x8000]
+    fn rom_bank(&self, addr: u16) -> usize {
+        usize::from(addr >= 0x4000)
+    }
+    /// The RAM bank mapped to (0xA000, 0xC000]
+    fn ram_bank(&self) -> usize {
+        0
+    }
+
+    /// The whole ROM, for the debugger
+    fn rom(&self) -> &[u8] {
+        &[]
+    }
+    fn rom_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+    /// The whole cartridge RAM, for the debugger
+    fn ram(&self) -> &[u8] {
+        &[]
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+
     /// Return the MBC type description
     fn description(&self) -> Option<MbcDescription<'_>> {
         None
@@ -40,6 +69,7 @@ pub enum MbcDescription<'a> {
     MBC1(&'a Vec<MbcCapability>),
     MBC3(&'a Vec<MbcCapability>),
     MBC5(&'a Vec<MbcCapability>),
+    MMM01(&'a Vec<MbcCapability>),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -17,4 +17,11 @@ impl Mbc for Mbc0 {
     fn description(&self) -> Option<super::MbcDescription<'_>> {
         Some(MbcDescription::MBC0)
     }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+    fn rom_mut(&mut self) -> &mut [u8] {
+        &mut self.rom
+    }
 }
@@ -30,7 +30,7 @@ impl Mbc1 {
         let capabilities = Mbc1::get_capabilities(rom[MBC_KIND_ADDR]);
 
         let mut ram = if capabilities.contains(&MbcCapability::Ram) {
-            std::iter::repeat(0).take(ram_size + 8).collect()
+            std::iter::repeat_n(0, ram_size + 8).collect()
         } else {
             Vec::new()
         };
@@ -41,7 +41,7 @@ impl Mbc1 {
             None
         };
 
-        save_path.as_ref().map(|path| {
+        if let Some(path) = save_path.as_ref() {
             let mut buf = vec![];
             fs::File::open(path)
                 .and_then(|mut save_file| save_file.read_to_end(&mut buf))
@@ -53,7 +53,7 @@ impl Mbc1 {
                     },
                     |_| ram = buf,
                 )
-        });
+        }
 
         Box::new(Mbc1 {
             capabilities,
@@ -99,6 +99,20 @@ impl Mbc for Mbc1 {
         };
         self.rom[addr]
     }
+    fn rom_bank(&self, addr: u16) -> usize {
+        if addr >= 0x4000 {
+            self.rom_bank % (self.rom.len() / 0x4000).max(1)
+        } else {
+            0
+        }
+    }
+    fn ram_bank(&self) -> usize {
+        if self.ram_mode {
+            self.ram_bank
+        } else {
+            0
+        }
+    }
     fn ram_read(&self, addr: u16) -> u8 {
         if !self.ram_enabled {
             return 0;
@@ -143,7 +157,20 @@ impl Mbc for Mbc1 {
         let ram_bank = if self.ram_mode { self.ram_bank } else { 0 };
         let addr = (ram_bank * 0x2000) | ((addr as usize) & 0x1FFF);
 
-        self.ram[addr as usize] = value;
+        self.ram[addr] = value;
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+    fn rom_mut(&mut self) -> &mut [u8] {
+        &mut self.rom
+    }
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
     }
 }
 
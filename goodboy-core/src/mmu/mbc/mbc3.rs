@@ -21,10 +21,10 @@ struct Rtc {
 }
 
 impl Rtc {
-    pub fn as_slice<'a>(&self) -> [&u8; 5] {
+    pub fn as_slice(&self) -> [&u8; 5] {
         [&self.sec, &self.min, &self.hour, &self.dayl, &self.dayh]
     }
-    pub fn as_mut_slice<'a>(&mut self) -> [&mut u8; 5] {
+    pub fn as_mut_slice(&mut self) -> [&mut u8; 5] {
         [
             &mut self.sec,
             &mut self.min,
@@ -119,14 +119,14 @@ impl Mbc3 {
                 let mut capabilities = match b {
                     0x0F => vec![MbcCapability::Timer],
                     0x10 => vec![MbcCapability::Timer, MbcCapability::Ram],
-                    0x13 | _ => vec![MbcCapability::Ram],
+                    _ => vec![MbcCapability::Ram],
                 };
                 capabilities.push(MbcCapability::Battery);
 
                 // try to retrieve the save file
                 let ram = match (cfg!(target_arch = "wasm32"), fs::File::open(&save_path)) {
                     (true, Ok(mut f)) => {
-                        let mut ram: Vec<u8> = std::iter::repeat(0).take(ram_size).collect();
+                        let mut ram: Vec<u8> = std::iter::repeat_n(0, ram_size).collect();
                         f.read_to_end(&mut ram).map(|_| ram).ok()
                     }
                     (_, Err(_)) | (false, _) => None,
@@ -135,9 +135,9 @@ impl Mbc3 {
                 (ram, Some(save_path.as_ref().to_path_buf()), capabilities)
             }
             0x12 => (None, None, vec![MbcCapability::Ram]),
-            0x11 | _ => (Some(Vec::new()), None, vec![]),
+            _ => (Some(Vec::new()), None, vec![]),
         };
-        let ram = ram.unwrap_or_else(|| std::iter::repeat(0).take(ram_size).collect());
+        let ram = ram.unwrap_or_else(|| std::iter::repeat_n(0, ram_size).collect());
 
         let rtc = if capabilities.contains(&MbcCapability::Timer) {
             Some(Rtc::default())
@@ -176,6 +176,18 @@ impl Mbc for Mbc3 {
         self.rom.get(addr).copied().unwrap_or(0)
     }
 
+    fn rom_bank(&self, addr: u16) -> usize {
+        if addr < 0x4000 {
+            0
+        } else {
+            self.rom_bank as usize
+        }
+    }
+
+    fn ram_bank(&self) -> usize {
+        self.ram_bank as usize
+    }
+
     fn ram_read(&self, addr: u16) -> u8 {
         if !self.ram_enabled {
             0
@@ -200,7 +212,7 @@ impl Mbc for Mbc3 {
             }
             0x4000..=0x5FFF => self.ram_bank = value,
             0x6000..=0x7FFF => {
-                self.rtc.as_mut().map(|rtc| {
+                if let Some(rtc) = self.rtc.as_mut() {
                     let latched = if value == 0x01 && self.rtc_last_byte == 0x00 {
                         rtc.toggle()
                     } else {
@@ -211,7 +223,7 @@ impl Mbc for Mbc3 {
                     if !latched {
                         rtc.update()
                     }
-                });
+                }
             }
             _ => panic!("Could not write to {:04X} (MBC3)", addr),
         }
@@ -224,12 +236,25 @@ impl Mbc for Mbc3 {
         if self.ram_bank <= 3 {
             self.ram[(self.ram_bank as usize * 0x2000) | ((addr as usize) & 0x1FFF)] = value;
         } else {
-            self.rtc.as_mut().map(|rtc| {
+            if let Some(rtc) = self.rtc.as_mut() {
                 *rtc.as_mut_slice()[(self.ram_bank - 0x08) as usize] = value;
                 rtc.update_start();
-            });
+            }
         }
     }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+    fn rom_mut(&mut self) -> &mut [u8] {
+        &mut self.rom
+    }
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
 }
 
 impl Drop for Mbc3 {
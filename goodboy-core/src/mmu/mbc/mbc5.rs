@@ -30,7 +30,7 @@ impl Mbc5 {
         let capabilities = Mbc5::get_capabilities(rom[MBC_KIND_ADDR]);
 
         let mut ram = if capabilities.contains(&MbcCapability::Ram) {
-            std::iter::repeat(0).take(ram_size + 8).collect()
+            std::iter::repeat_n(0, ram_size + 8).collect()
         } else {
             Vec::new()
         };
@@ -41,7 +41,7 @@ impl Mbc5 {
             None
         };
 
-        save_path.as_ref().map(|path| {
+        if let Some(path) = save_path.as_ref() {
             let mut buf = vec![];
             fs::File::open(path)
                 .and_then(|mut save_file| save_file.read_to_end(&mut buf))
@@ -53,7 +53,7 @@ impl Mbc5 {
                     },
                     |_| ram = buf,
                 )
-        });
+        }
 
         Box::new(Mbc5 {
             capabilities,
@@ -105,6 +105,16 @@ impl Mbc for Mbc5 {
         };
         self.rom[addr]
     }
+    fn rom_bank(&self, addr: u16) -> usize {
+        if addr >= 0x4000 {
+            self.rom_bank % (self.rom.len() / 0x4000).max(1)
+        } else {
+            0
+        }
+    }
+    fn ram_bank(&self) -> usize {
+        self.ram_bank
+    }
     fn ram_read(&self, addr: u16) -> u8 {
         if !self.ram_enabled {
             return 0;
@@ -130,6 +140,19 @@ impl Mbc for Mbc5 {
         }
         self.ram[(self.ram_bank * 0x2000) + ((addr as usize) & 0x1FFF)] = value;
     }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+    fn rom_mut(&mut self) -> &mut [u8] {
+        &mut self.rom
+    }
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
 }
 
 impl Drop for Mbc5 {
@@ -0,0 +1,362 @@
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::mmu::{cartridge::MBC_KIND_ADDR, mbc::MbcCapability};
+
+use super::{Mbc, MbcDescription};
+
+pub struct Mmm01 {
+    capabilities: Vec<MbcCapability>,
+
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    save_path: Option<PathBuf>,
+
+    // 0x0000..=0x1FFF
+    ram_enabled: bool,
+    ram_bank_mask: usize,
+    // once set, the outer bank registers can't be changed anymore
+    mapped: bool,
+
+    // 0x2000..=0x3FFF
+    rom_bank_low: usize,
+    rom_bank_mid: usize,
+
+    // 0x4000..=0x5FFF
+    ram_bank_low: usize,
+    ram_bank_high: usize,
+    rom_bank_high: usize,
+    mbc1_mode_locked: bool,
+
+    // 0x6000..=0x7FFF
+    mbc1_mode: bool,
+    rom_bank_mask: usize,
+    multiplex: bool,
+}
+
+impl Mmm01 {
+    /// The menu header is at `header_offset`, the last 32 KiB of a multicart
+    pub fn new(
+        rom: Vec<u8>,
+        header_offset: usize,
+        ram_size: usize,
+        save_path: impl AsRef<Path>,
+    ) -> Box<dyn Mbc + 'static> {
+        let capabilities = Mmm01::get_capabilities(rom[header_offset + MBC_KIND_ADDR]);
+
+        let mut ram = if capabilities.contains(&MbcCapability::Ram) {
+            std::iter::repeat_n(0, ram_size).collect()
+        } else {
+            Vec::new()
+        };
+
+        let save_path =
+            if !cfg!(target_arch = "wasm32") && capabilities.contains(&MbcCapability::Battery) {
+                Some(save_path.as_ref().to_path_buf())
+            } else {
+                None
+            };
+
+        if let Some(path) = save_path.as_ref() {
+            let mut buf = vec![];
+            fs::File::open(path)
+                .and_then(|mut save_file| save_file.read_to_end(&mut buf))
+                .map_or_else(
+                    |e| {
+                        if e.kind() != io::ErrorKind::NotFound {
+                            panic!("Error reading file \"{path:?}\": {e:?}")
+                        }
+                    },
+                    |_| ram = buf,
+                )
+        }
+
+        Box::new(Mmm01 {
+            capabilities,
+
+            rom,
+            ram,
+            save_path,
+
+            ram_enabled: false,
+            ram_bank_mask: 0,
+            mapped: false,
+
+            rom_bank_low: 0,
+            rom_bank_mid: 0,
+
+            ram_bank_low: 0,
+            ram_bank_high: 0,
+            rom_bank_high: 0,
+            mbc1_mode_locked: false,
+
+            mbc1_mode: false,
+            rom_bank_mask: 0,
+            multiplex: false,
+        })
+    }
+
+    pub fn get_capabilities(cartridge_kind: u8) -> Vec<MbcCapability> {
+        match cartridge_kind {
+            // mmm01
+            0x0B => vec![],
+            // mmm01 + ram
+            0x0C => vec![MbcCapability::Ram],
+            // mmm01 + ram + battery
+            0x0D => vec![MbcCapability::Ram, MbcCapability::Battery],
+            _ => panic!("Invalid MMM01 cartridge"),
+        }
+    }
+
+    fn rom_banks(&self) -> usize {
+        (self.rom.len() / 0x4000).max(2)
+    }
+
+    /// The ROM bank mapped to 0x4000..=0x7FFF
+    fn romx_bank(&self) -> usize {
+        if !self.mapped {
+            // the menu lives in the last 32 KiB of the ROM
+            return self.rom_banks() - 1;
+        }
+
+        // bits masked by the ROM bank mask are part of the game base bank
+        let unmasked = !(self.rom_bank_mask << 1) & 0x1F;
+        let low = match self.rom_bank_low & unmasked {
+            0 => self.rom_bank_low | 1,
+            _ => self.rom_bank_low,
+        };
+
+        low | (self.outer_rom_bank_mid() << 5) | (self.rom_bank_high << 7)
+    }
+
+    /// The ROM bank mapped to 0x0000..=0x3FFF
+    fn rom0_bank(&self) -> usize {
+        if !self.mapped {
+            return self.rom_banks() - 2;
+        }
+
+        let masked = (self.rom_bank_mask << 1) & 0x1F;
+        // like a MBC1, bits 5-6 only reach the first bank in mode 1
+        let mid = if self.multiplex && !self.mbc1_mode {
+            0
+        } else {
+            self.outer_rom_bank_mid()
+        };
+
+        (self.rom_bank_low & masked) | (mid << 5) | (self.rom_bank_high << 7)
+    }
+
+    /// In multiplex mode the RAM bank register drives the ROM bank bits 5-6, so
+    /// the games can use the register layout of a MBC1.
+    fn outer_rom_bank_mid(&self) -> usize {
+        if self.multiplex {
+            self.ram_bank_low
+        } else {
+            self.rom_bank_mid
+        }
+    }
+
+    fn ram_bank(&self) -> usize {
+        let low = match (self.multiplex, self.mbc1_mode) {
+            (false, _) | (true, true) => self.ram_bank_low,
+            (true, false) => 0,
+        };
+
+        low | (self.ram_bank_high << 2)
+    }
+
+    fn ram_addr(&self, addr: u16) -> Option<usize> {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return None;
+        }
+
+        let addr = (self.ram_bank() * 0x2000) | ((addr as usize) & 0x1FFF);
+        Some(addr % self.ram.len())
+    }
+}
+
+impl Mbc for Mmm01 {
+    fn description(&self) -> Option<super::MbcDescription<'_>> {
+        Some(MbcDescription::MMM01(&self.capabilities))
+    }
+
+    fn rom_read(&self, addr: u16) -> u8 {
+        let bank = if addr < 0x4000 {
+            self.rom0_bank()
+        } else {
+            self.romx_bank()
+        };
+
+        let addr = ((bank % self.rom_banks()) * 0x4000) | ((addr as usize) & 0x3FFF);
+        self.rom.get(addr).copied().unwrap_or(0xFF)
+    }
+
+    fn rom_bank(&self, addr: u16) -> usize {
+        let bank = if addr < 0x4000 {
+            self.rom0_bank()
+        } else {
+            self.romx_bank()
+        };
+
+        bank % self.rom_banks()
+    }
+
+    fn ram_bank(&self) -> usize {
+        self.ram_bank()
+    }
+
+    fn ram_read(&self, addr: u16) -> u8 {
+        self.ram_addr(addr).map_or(0xFF, |addr| self.ram[addr])
+    }
+
+    fn rom_write(&mut self, addr: u16, value: u8) {
+        let value = value as usize;
+
+        match addr {
+            0x0000..=0x1FFF => {
+                self.ram_enabled = value & 0x0F == 0x0A;
+                if !self.mapped {
+                    self.ram_bank_mask = (value >> 4) & 0x03;
+                    self.mapped = value & 0x40 == 0x40;
+                }
+            }
+            0x2000..=0x3FFF => {
+                if !self.mapped {
+                    self.rom_bank_mid = (value >> 5) & 0x03;
+                }
+
+                // only the bits not protected by the mask can be changed
+                let masked = (self.rom_bank_mask << 1) & 0x1F;
+                self.rom_bank_low = (self.rom_bank_low & masked) | (value & !masked & 0x1F);
+            }
+            0x4000..=0x5FFF => {
+                let masked = self.ram_bank_mask;
+                self.ram_bank_low = (self.ram_bank_low & masked) | (value & !masked & 0x03);
+
+                if !self.mapped {
+                    self.ram_bank_high = (value >> 2) & 0x03;
+                    self.rom_bank_high = (value >> 4) & 0x03;
+                    self.mbc1_mode_locked = value & 0x40 == 0x40;
+                }
+            }
+            0x6000..=0x7FFF => {
+                if !self.mbc1_mode_locked {
+                    self.mbc1_mode = value & 0x01 == 0x01;
+                }
+                if !self.mapped {
+                    self.rom_bank_mask = (value >> 2) & 0x0F;
+                    self.multiplex = value & 0x40 == 0x40;
+                }
+            }
+            _ => panic!("Invalid MMM01 ROM addr: 0x{:04X}", addr),
+        }
+    }
+
+    fn ram_write(&mut self, addr: u16, value: u8) {
+        if let Some(addr) = self.ram_addr(addr) {
+            self.ram[addr] = value;
+        }
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+    fn rom_mut(&mut self) -> &mut [u8] {
+        &mut self.rom
+    }
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+impl Drop for Mmm01 {
+    fn drop(&mut self) {
+        match self.save_path {
+            None => (),
+            Some(ref path) => {
+                log::info!("Saving game to file {:?}", path);
+                if let Ok(mut f) = fs::File::create(path) {
+                    f.write_all(&self.ram).ok();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmu::cartridge::{self, Cartridge, HEADER_CHECKSUM_ADDR, TITLE_ADDR};
+
+    /// Build a 128 KiB MMM01 image with the menu header in the last 32 KiB and a
+    /// MBC1 game header in the first bank. Every bank starts with its own number.
+    fn multicart() -> Vec<u8> {
+        let mut rom = vec![0; 8 * 0x4000];
+        for (bank, chunk) in rom.chunks_mut(0x4000).enumerate() {
+            chunk[0] = bank as u8;
+        }
+
+        let write_header = |rom: &mut [u8], base: usize, title: &[u8], kind: u8| {
+            rom[base + TITLE_ADDR..base + TITLE_ADDR + title.len()].copy_from_slice(title);
+            rom[base + MBC_KIND_ADDR] = kind;
+            rom[base + HEADER_CHECKSUM_ADDR] = cartridge::header_checksum(&rom[base..]);
+        };
+
+        let menu = rom.len() - 0x8000;
+        write_header(&mut rom, 0, b"GAME", 0x01);
+        write_header(&mut rom, menu, b"MENU", 0x0B);
+        // give the game header an invalid RAM size, like the dumps that used to crash
+        rom[cartridge::RAM_SIZE_ADDR] = 0xFF;
+
+        rom
+    }
+
+    #[test]
+    fn detect_header_at_the_end() {
+        let rom = multicart();
+        assert_eq!(cartridge::header_offset(&rom), rom.len() - 0x8000);
+
+        let cartridge = Cartridge::new(&rom);
+        assert_eq!(cartridge.rom_name(), "MENU");
+        assert!(matches!(
+            cartridge.description(),
+            Some(MbcDescription::MMM01(_))
+        ));
+    }
+
+    #[test]
+    fn boots_into_the_menu_and_locks() {
+        let rom = multicart();
+        let header_offset = cartridge::header_offset(&rom);
+        let mut mbc = Mmm01::new(rom, header_offset, 0, "");
+
+        // unmapped: the last 32 KiB are visible
+        assert_eq!(mbc.rom_read(0x0000), 6);
+        assert_eq!(mbc.rom_read(0x4000), 7);
+
+        // select the game at bank 2 with a 2 banks (32 KiB) wide window
+        mbc.rom_write(0x2000, 0x02);
+        mbc.rom_write(0x6000, 0b0011_1100);
+        // map and lock
+        mbc.rom_write(0x0000, 0x40);
+
+        assert_eq!(mbc.rom_read(0x0000), 2);
+        assert_eq!(mbc.rom_read(0x4000), 3);
+
+        // the game can only switch the banks within its window
+        mbc.rom_write(0x2000, 0x1F);
+        assert_eq!(mbc.rom_read(0x4000), 3);
+
+        // the outer registers are locked now
+        mbc.rom_write(0x6000, 0x00);
+        mbc.rom_write(0x2000, 0x05);
+        assert_eq!(mbc.rom_read(0x0000), 2);
+        assert_eq!(mbc.rom_read(0x4000), 3);
+    }
+}
@@ -0,0 +1,530 @@
+use std::fmt::Display;
+
+use crate::utils::crc32;
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: usize = 0x454F46;
+const UPS_MAGIC: &[u8] = b"UPS1";
+const BPS_MAGIC: &[u8] = b"BPS1";
+
+/// Size of the UPS and BPS footer (source, target and patch CRC32)
+const FOOTER_SIZE: usize = 12;
+/// The biggest ROM a patch may create, the 8 MiB of a MBC5 cartridge
+const MAX_TARGET_SIZE: usize = 0x800000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchKind {
+    Ips,
+    Ups,
+    Bps,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchError {
+    /// The patch doesn't start with a known magic number
+    UnknownFormat,
+    /// The patch ended in the middle of a record
+    UnexpectedEof,
+    /// A record points outside of the source or target ROM, or the target is too big
+    OutOfBounds,
+    SourceSize {
+        expected: usize,
+        found: usize,
+    },
+    SourceChecksum {
+        expected: u32,
+        found: u32,
+    },
+    TargetChecksum {
+        expected: u32,
+        found: u32,
+    },
+    PatchChecksum {
+        expected: u32,
+        found: u32,
+    },
+}
+
+impl Display for PatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PatchError::UnknownFormat => write!(f, "unknown patch format"),
+            PatchError::UnexpectedEof => write!(f, "unexpected end of the patch"),
+            PatchError::OutOfBounds => write!(f, "the patch writes outside of the ROM"),
+            PatchError::SourceSize { expected, found } => {
+                write!(f, "wrong source size: expected {expected}, found {found}")
+            }
+            PatchError::SourceChecksum { expected, found } => {
+                write!(
+                    f,
+                    "wrong source CRC32: expected {expected:08X}, found {found:08X}"
+                )
+            }
+            PatchError::TargetChecksum { expected, found } => {
+                write!(
+                    f,
+                    "wrong target CRC32: expected {expected:08X}, found {found:08X}"
+                )
+            }
+            PatchError::PatchChecksum { expected, found } => {
+                write!(
+                    f,
+                    "wrong patch CRC32: expected {expected:08X}, found {found:08X}"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+/// A ROM patch, applied in memory before the cartridge header is parsed
+#[derive(Debug, Clone)]
+pub struct Patch {
+    kind: PatchKind,
+    data: Vec<u8>,
+}
+
+impl Patch {
+    /// Create a patch from a IPS, UPS or BPS file buffer. The format is detected from the
+    /// file magic number.
+    pub fn new(data: Vec<u8>) -> Result<Patch, PatchError> {
+        let kind = if data.starts_with(IPS_MAGIC) {
+            PatchKind::Ips
+        } else if data.starts_with(UPS_MAGIC) {
+            PatchKind::Ups
+        } else if data.starts_with(BPS_MAGIC) {
+            PatchKind::Bps
+        } else {
+            return Err(PatchError::UnknownFormat);
+        };
+
+        if kind != PatchKind::Ips {
+            if data.len() < UPS_MAGIC.len() + FOOTER_SIZE {
+                return Err(PatchError::UnexpectedEof);
+            }
+
+            let (body, footer) = data.split_at(data.len() - 4);
+            let expected = u32::from_le_bytes(footer.try_into().unwrap());
+            let found = crc32(body);
+            if expected != found {
+                return Err(PatchError::PatchChecksum { expected, found });
+            }
+        }
+
+        Ok(Patch { kind, data })
+    }
+
+    pub fn kind(&self) -> PatchKind {
+        self.kind
+    }
+
+    /// Apply the patch to `rom`, returning the patched ROM
+    pub fn apply(&self, rom: &[u8]) -> Result<Vec<u8>, PatchError> {
+        log::info!("Applying {:?} patch", self.kind);
+
+        match self.kind {
+            PatchKind::Ips => self.apply_ips(rom),
+            PatchKind::Ups => self.apply_ups(rom),
+            PatchKind::Bps => self.apply_bps(rom),
+        }
+    }
+
+    fn apply_ips(&self, rom: &[u8]) -> Result<Vec<u8>, PatchError> {
+        let mut reader = Reader::new(&self.data[IPS_MAGIC.len()..]);
+        let mut target = rom.to_vec();
+
+        loop {
+            let offset = reader.read_be(3)?;
+            if offset == IPS_EOF {
+                break;
+            }
+
+            let (len, value) = match reader.read_be(2)? {
+                // RLE record
+                0 => (reader.read_be(2)?, Some(reader.read_byte()?)),
+                len => (len, None),
+            };
+
+            if target.len() < offset + len {
+                target.resize(offset + len, 0);
+            }
+
+            match value {
+                Some(value) => target[offset..offset + len].fill(value),
+                None => target[offset..offset + len].copy_from_slice(reader.read_bytes(len)?),
+            }
+        }
+
+        // some patches truncate the ROM after the EOF marker
+        if let Ok(len) = reader.read_be(3) {
+            target.truncate(len);
+        }
+
+        Ok(target)
+    }
+
+    fn apply_ups(&self, rom: &[u8]) -> Result<Vec<u8>, PatchError> {
+        let (body, footer) = self.data.split_at(self.data.len() - FOOTER_SIZE);
+        let [source_crc, target_crc, _] = read_footer(footer);
+
+        let mut reader = Reader::new(&body[UPS_MAGIC.len()..]);
+        let source_size = reader.read_varint()?;
+        let target_size = reader.read_varint()?;
+
+        check_source(rom, source_size, source_crc)?;
+        check_target_size(target_size)?;
+
+        let mut target = rom.to_vec();
+        target.resize(target_size, 0);
+
+        let mut pos = 0usize;
+        while !reader.is_empty() {
+            pos = pos
+                .checked_add(reader.read_varint()?)
+                .ok_or(PatchError::OutOfBounds)?;
+
+            loop {
+                let xor = reader.read_byte()?;
+                if xor != 0 {
+                    let source = rom.get(pos).copied().unwrap_or(0);
+                    *target.get_mut(pos).ok_or(PatchError::OutOfBounds)? = source ^ xor;
+                }
+                pos = pos.checked_add(1).ok_or(PatchError::OutOfBounds)?;
+
+                if xor == 0 {
+                    break;
+                }
+            }
+        }
+
+        check_target(&target, target_crc)?;
+        Ok(target)
+    }
+
+    fn apply_bps(&self, rom: &[u8]) -> Result<Vec<u8>, PatchError> {
+        let (body, footer) = self.data.split_at(self.data.len() - FOOTER_SIZE);
+        let [source_crc, target_crc, _] = read_footer(footer);
+
+        let mut reader = Reader::new(&body[BPS_MAGIC.len()..]);
+        let source_size = reader.read_varint()?;
+        let target_size = reader.read_varint()?;
+        let metadata_size = reader.read_varint()?;
+        reader.read_bytes(metadata_size)?;
+
+        check_source(rom, source_size, source_crc)?;
+        check_target_size(target_size)?;
+
+        let mut target = Vec::with_capacity(target_size);
+        let mut source_offset = 0;
+        let mut target_offset = 0;
+
+        while !reader.is_empty() {
+            let action = reader.read_varint()?;
+            let len = (action >> 2) + 1;
+            // no action may grow the target past its size
+            if len > target_size - target.len() {
+                return Err(PatchError::OutOfBounds);
+            }
+
+            match action & 0x03 {
+                // source read
+                0 => {
+                    let start = target.len();
+                    let bytes = rom.get(start..start + len).ok_or(PatchError::OutOfBounds)?;
+                    target.extend_from_slice(bytes);
+                }
+                // target read
+                1 => target.extend_from_slice(reader.read_bytes(len)?),
+                // source copy
+                2 => {
+                    source_offset = reader.read_offset(source_offset)?;
+                    let end = source_offset
+                        .checked_add(len)
+                        .ok_or(PatchError::OutOfBounds)?;
+                    let bytes = rom.get(source_offset..end).ok_or(PatchError::OutOfBounds)?;
+                    target.extend_from_slice(bytes);
+                    source_offset = end;
+                }
+                // target copy, the ranges may overlap so it must go byte by byte
+                _ => {
+                    target_offset = reader.read_offset(target_offset)?;
+                    for _ in 0..len {
+                        let byte = *target.get(target_offset).ok_or(PatchError::OutOfBounds)?;
+                        target.push(byte);
+                        target_offset += 1;
+                    }
+                }
+            }
+        }
+
+        if target.len() != target_size {
+            return Err(PatchError::OutOfBounds);
+        }
+
+        check_target(&target, target_crc)?;
+        Ok(target)
+    }
+}
+
+/// Apply every patch in order
+pub fn apply_all(rom: &[u8], patches: &[Patch]) -> Result<Vec<u8>, PatchError> {
+    patches
+        .iter()
+        .try_fold(rom.to_vec(), |rom, patch| patch.apply(&rom))
+}
+
+fn read_footer(footer: &[u8]) -> [u32; 3] {
+    let mut crcs = [0; 3];
+    for (crc, bytes) in crcs.iter_mut().zip(footer.chunks_exact(4)) {
+        *crc = u32::from_le_bytes(bytes.try_into().unwrap());
+    }
+    crcs
+}
+
+fn check_source(rom: &[u8], expected_size: usize, expected_crc: u32) -> Result<(), PatchError> {
+    if rom.len() != expected_size {
+        return Err(PatchError::SourceSize {
+            expected: expected_size,
+            found: rom.len(),
+        });
+    }
+
+    let found = crc32(rom);
+    if found != expected_crc {
+        return Err(PatchError::SourceChecksum {
+            expected: expected_crc,
+            found,
+        });
+    }
+
+    Ok(())
+}
+
+fn check_target_size(size: usize) -> Result<(), PatchError> {
+    if size > MAX_TARGET_SIZE {
+        return Err(PatchError::OutOfBounds);
+    }
+    Ok(())
+}
+
+fn check_target(target: &[u8], expected_crc: u32) -> Result<(), PatchError> {
+    let found = crc32(target);
+    if found != expected_crc {
+        return Err(PatchError::TargetChecksum {
+            expected: expected_crc,
+            found,
+        });
+    }
+
+    Ok(())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], PatchError> {
+        let end = self.pos.checked_add(len).ok_or(PatchError::UnexpectedEof)?;
+        let bytes = self
+            .data
+            .get(self.pos..end)
+            .ok_or(PatchError::UnexpectedEof)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn read_byte(&mut self) -> Result<u8, PatchError> {
+        self.read_bytes(1).map(|bytes| bytes[0])
+    }
+
+    /// Read a big endian number of `len` bytes
+    fn read_be(&mut self, len: usize) -> Result<usize, PatchError> {
+        let bytes = self.read_bytes(len)?;
+        Ok(bytes
+            .iter()
+            .fold(0, |value, byte| (value << 8) | *byte as usize))
+    }
+
+    /// Read a UPS/BPS variable length number, a number too big for a usize is out of bounds
+    fn read_varint(&mut self) -> Result<usize, PatchError> {
+        let mut value = 0usize;
+        let mut shift = 1usize;
+
+        loop {
+            let byte = self.read_byte()? as usize;
+            value = (byte & 0x7F)
+                .checked_mul(shift)
+                .and_then(|bits| value.checked_add(bits))
+                .ok_or(PatchError::OutOfBounds)?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift.checked_mul(0x80).ok_or(PatchError::OutOfBounds)?;
+            value = value.checked_add(shift).ok_or(PatchError::OutOfBounds)?;
+        }
+    }
+
+    /// Read a BPS signed relative offset and apply it to `offset`
+    fn read_offset(&mut self, offset: usize) -> Result<usize, PatchError> {
+        let data = self.read_varint()?;
+        let delta = data >> 1;
+
+        if data & 1 == 1 {
+            offset.checked_sub(delta)
+        } else {
+            offset.checked_add(delta)
+        }
+        .ok_or(PatchError::OutOfBounds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varint(mut value: usize) -> Vec<u8> {
+        let mut bytes = vec![];
+        loop {
+            let x = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(0x80 | x);
+                return bytes;
+            }
+            bytes.push(x);
+            value -= 1;
+        }
+    }
+
+    fn with_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend(crc32(source).to_le_bytes());
+        patch.extend(crc32(target).to_le_bytes());
+        patch.extend(crc32(&patch).to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn ips_records_and_rle() {
+        let rom = vec![0u8; 8];
+        let mut patch = IPS_MAGIC.to_vec();
+        // 2 bytes at 0x0001
+        patch.extend([0x00, 0x00, 0x01, 0x00, 0x02, 0xAA, 0xBB]);
+        // RLE: 4 times 0x11 at 0x0006, growing the ROM
+        patch.extend([0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x04, 0x11]);
+        patch.extend(b"EOF");
+
+        let patched = Patch::new(patch).unwrap().apply(&rom).unwrap();
+        assert_eq!(patched, [0, 0xAA, 0xBB, 0, 0, 0, 0x11, 0x11, 0x11, 0x11]);
+    }
+
+    #[test]
+    fn ups_xor_hunks() {
+        let source = b"Hello GoodBoy".to_vec();
+        let target = b"Hello BadBoy!".to_vec();
+
+        let mut patch = UPS_MAGIC.to_vec();
+        patch.extend(varint(source.len()));
+        patch.extend(varint(target.len()));
+        // skip "Hello "
+        patch.extend(varint(6));
+        patch.extend(source[6..].iter().zip(&target[6..]).map(|(s, t)| s ^ t));
+        patch.push(0);
+        let patch = with_footer(patch, &source, &target);
+
+        let patch = Patch::new(patch).unwrap();
+        assert_eq!(patch.apply(&source).unwrap(), target);
+        assert!(matches!(
+            patch.apply(b"Hello Goodboy"),
+            Err(PatchError::SourceChecksum { .. })
+        ));
+    }
+
+    #[test]
+    fn bps_actions() {
+        let source = b"ABCDEFGH".to_vec();
+        let target = b"ABCDxyxyxyGH".to_vec();
+
+        let mut patch = BPS_MAGIC.to_vec();
+        patch.extend(varint(source.len()));
+        patch.extend(varint(target.len()));
+        patch.extend(varint(0));
+        // source read "ABCD"
+        patch.extend(varint((4 - 1) << 2));
+        // target read "xy"
+        patch.extend(varint(((2 - 1) << 2) | 1));
+        patch.extend(b"xy");
+        // target copy "xyxy" from offset 4
+        patch.extend(varint(((4 - 1) << 2) | 3));
+        patch.extend(varint(4 << 1));
+        // source copy "GH" from offset 6
+        patch.extend(varint(((2 - 1) << 2) | 2));
+        patch.extend(varint(6 << 1));
+        let patch = with_footer(patch, &source, &target);
+
+        assert_eq!(Patch::new(patch).unwrap().apply(&source).unwrap(), target);
+    }
+
+    #[test]
+    fn oversized_patches() {
+        // a varint longer than a usize
+        let mut patch = UPS_MAGIC.to_vec();
+        patch.extend([0x7F; 12]);
+        patch.push(0x80);
+        let patch = with_footer(patch, b"", b"");
+        assert_eq!(
+            Patch::new(patch).unwrap().apply(b""),
+            Err(PatchError::OutOfBounds)
+        );
+
+        // a target copy much longer than the target
+        let mut patch = BPS_MAGIC.to_vec();
+        patch.extend(varint(1));
+        patch.extend(varint(4));
+        patch.extend(varint(0));
+        patch.extend(varint(0));
+        patch.extend(varint(((usize::MAX >> 3) << 2) | 3));
+        patch.extend(varint(0));
+        let patch = with_footer(patch, b"A", b"AAAA");
+        assert_eq!(
+            Patch::new(patch).unwrap().apply(b"A"),
+            Err(PatchError::OutOfBounds)
+        );
+
+        // a target bigger than any cartridge
+        let mut patch = BPS_MAGIC.to_vec();
+        patch.extend(varint(0));
+        patch.extend(varint(MAX_TARGET_SIZE + 1));
+        patch.extend(varint(0));
+        let patch = with_footer(patch, b"", b"");
+        assert_eq!(
+            Patch::new(patch).unwrap().apply(b""),
+            Err(PatchError::OutOfBounds)
+        );
+    }
+
+    #[test]
+    fn corrupted_patch() {
+        let mut patch = with_footer(BPS_MAGIC.to_vec(), b"", b"");
+        patch[4] ^= 0xFF;
+
+        assert!(matches!(
+            Patch::new(patch),
+            Err(PatchError::PatchChecksum { .. })
+        ));
+        assert_eq!(
+            Patch::new(b"NOT A PATCH".to_vec()).unwrap_err(),
+            PatchError::UnknownFormat
+        );
+    }
+}
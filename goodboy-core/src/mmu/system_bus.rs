@@ -1,6 +1,6 @@
-use std::iter;
-
 use crate::{
+    cheats::Cheats,
+    debugger::{Access, Debugger},
     gb_mode::GbMode,
     io::{Joypad, Serial, Timer},
     ppu::Gpu,
@@ -8,12 +8,13 @@ use crate::{
 
 use super::{
     cartridge::Cartridge,
-    dma::{Dma, DmaMode},
-    Mbc, MemoryAccess,
+    cdl::{CodeDataLogger, Usage},
+    dma::{self, Dma, DmaMode, OamDma},
+    Mbc, MemoryAccess, HRAM_SIZE, WRAM_SIZE,
 };
 
-const WRAM_SIZE: usize = 0x8000;
-const ZRAM_SIZE: usize = 0x7F;
+/// The CPU clocks of the speed switch, the CPU and the timer are stopped for 2050 M-cycles
+const SPEED_SWITCH_CLOCKS: u32 = 2050 * 4;
 
 /// The System Bus
 pub struct Bus {
@@ -30,6 +31,12 @@ pub struct Bus {
 
     // Cartridge
     pub cartridge: Cartridge,
+    /// Game Genie and GameShark codes
+    pub cheats: Cheats,
+    /// Breakpoints and watchpoints
+    pub debugger: Debugger,
+    /// Code/Data Logger, how every ROM and RAM byte was accessed
+    cdl: Option<Box<CodeDataLogger>>,
 
     /// Work RAM \
     /// 0xC000 ..= 0xCFFF -> WRAM0 \
@@ -38,7 +45,7 @@ pub struct Bus {
     wram: Vec<u8>,
     /// Zero-page RAM \
     /// 0xFF80 ..= 0xFFFE
-    zram: [u8; ZRAM_SIZE],
+    zram: [u8; HRAM_SIZE],
 
     /// GPU
     pub gpu: Gpu,
@@ -68,14 +75,25 @@ pub struct Bus {
     /// 0xFFFF
     pub ienable: u8,
 
+    /// OAM DMA \
+    /// 0xFF46
+    oam_dma: OamDma,
+
     // CGB registers
     // Direct Memory Access registers
     dma: Dma,
+    /// The CPU is in HALT, the HDMA waits
+    pub(crate) cpu_halted: bool,
     /// WRAM Bank
     wram_bank: usize,
 
+    /// KEY1 \
+    /// 0xFF4D, the CPU clocks per dot, 2 in double speed, and the switch prepared for
+    /// the next STOP
     speed: u8,
     speed_switch: bool,
+    /// The CPU clocks left in the speed switch pause
+    speed_switch_pause: u32,
 }
 
 impl Bus {
@@ -88,8 +106,8 @@ impl Bus {
     pub fn from_cartridge(cartridge: Cartridge) -> Bus {
         let gb_mode = cartridge.gb_mode;
 
-        let wram = iter::repeat(0).take(WRAM_SIZE).collect();
-        let zram = [0; ZRAM_SIZE];
+        let wram = std::iter::repeat_n(0, WRAM_SIZE).collect();
+        let zram = [0; HRAM_SIZE];
 
         let mut bus = Bus {
             gb_mode,
@@ -98,18 +116,24 @@ impl Bus {
             zram,
 
             cartridge,
+            cheats: Default::default(),
+            debugger: Default::default(),
+            cdl: None,
             gpu: Gpu::new(gb_mode),
             joypad: Default::default(),
-            serial: Default::default(),
+            serial: Serial::new(gb_mode),
             timer: Default::default(),
             // io_registers: [0; 0x80],
             ienable: Default::default(),
             iflag: Default::default(),
+            oam_dma: Default::default(),
 
             wram_bank: 1,
             dma: Default::default(),
+            cpu_halted: false,
             speed: 1,
             speed_switch: false,
+            speed_switch_pause: 0,
         };
         log::debug!("Loaded cartridge: {:?}", bus.cartridge);
         log::debug!("Game Boy mode: {gb_mode:?}");
@@ -157,6 +181,8 @@ impl Bus {
     /// Sync the IO devices
     pub fn sync(&mut self, clocks: u32) -> u32 {
         let dma_clocks = self.start_dma();
+        self.sync_oam_dma(clocks + dma_clocks * u32::from(self.speed));
+        let pause_clocks = std::mem::take(&mut self.speed_switch_pause);
 
         let Bus {
             ref speed,
@@ -169,9 +195,10 @@ impl Bus {
             ..
         } = self;
 
+        // the timer and the serial are clocked by the CPU, the PPU by the dots
         let speed = u32::from(*speed);
         let cpu_clocks = clocks + dma_clocks * speed;
-        let gpu_clocks = clocks / speed + dma_clocks;
+        let gpu_clocks = (clocks + pause_clocks) / speed + dma_clocks;
 
         // update the timer
         timer.sync(cpu_clocks);
@@ -183,19 +210,216 @@ impl Bus {
 
         // update the gpu
         gpu.sync(gpu_clocks);
+        let vblank = gpu.interrupt & 0x01 != 0;
         *iflag |= gpu.interrupt;
         gpu.interrupt = 0;
 
+        serial.sync(cpu_clocks);
         *iflag |= serial.interrupt;
         serial.interrupt = 0;
 
+        if vblank {
+            self.apply_cheats();
+        }
+
         gpu_clocks
     }
 
+    /// Copy the OAM DMA bytes due after `clocks` CPU clocks
+    fn sync_oam_dma(&mut self, clocks: u32) {
+        self.oam_dma.sync(clocks);
+
+        while let Some((src, dst)) = self.oam_dma.next_byte() {
+            let value = self.dma_read(src);
+            self.debugger.check_access(dst, value, Access::Write);
+            self.gpu.write_oam(dst, value);
+            self.oam_dma.last = value;
+        }
+    }
+
+    /// What the CPU reads at `addr` during the OAM DMA, `None` if it can access it. The
+    /// DMA uses the bus: OAM reads 0xFF and the rest the byte being copied.
+    fn oam_dma_conflict(&self, addr: u16) -> Option<u8> {
+        if !self.oam_dma.active() {
+            return None;
+        }
+
+        match addr {
+            0xFE00..=0xFE9F => Some(0xFF),
+            0xFF00..=0xFFFF => None,
+            _ => Some(self.oam_dma.last),
+        }
+    }
+
+    /// Apply the GameShark codes, done once per frame like the real device
+    fn apply_cheats(&mut self) {
+        let writes = self.cheats.ram_writes().collect::<Vec<_>>();
+
+        for (bank, addr, value) in writes {
+            match (bank & 0xF0, addr) {
+                (0x90, 0xD000..=0xDFFF) => {
+                    self.poke(usize::from(bank & 0x07).max(1), addr, value);
+                }
+                _ => {
+                    self.poke(self.bank_at(addr), addr, value);
+                }
+            }
+        }
+    }
+
+    /// The bank mapped at `addr`: the ROM, cartridge RAM, VRAM or WRAM bank, 0 elsewhere
+    pub fn bank_at(&self, addr: u16) -> usize {
+        match addr {
+            0x0000..=0x7FFF => self.cartridge.rom_bank(addr),
+            0x8000..=0x9FFF => self.gpu.vram_bank(),
+            0xA000..=0xBFFF => self.cartridge.ram_bank(),
+            0xD000..=0xDFFF => self.wram_bank,
+            _ => 0,
+        }
+    }
+
+    /// Read any bank without side effects nor changing the mapping. `bank` selects the ROM
+    /// bank (0x0000-0x7FFF), the VRAM bank, the cartridge RAM bank or the WRAM bank
+    /// (0xD000-0xDFFF), the other regions ignore it. `None` if the bank doesn't exist.
+    pub fn peek(&self, bank: usize, addr: u16) -> Option<u8> {
+        let offset = addr as usize;
+        match addr {
+            0x0000..=0x7FFF => self
+                .cartridge
+                .rom()
+                .get(bank * 0x4000 + (offset & 0x3FFF))
+                .copied(),
+            0x8000..=0x9FFF => self.gpu.peek_vram(bank, addr),
+            0xA000..=0xBFFF => self
+                .cartridge
+                .ram()
+                .get(bank * 0x2000 + (offset & 0x1FFF))
+                .copied(),
+            0xC000..=0xCFFF | 0xE000..=0xEFFF => Some(self.wram[offset & 0x0FFF]),
+            0xD000..=0xDFFF | 0xF000..=0xFDFF => {
+                self.wram.get(bank * 0x1000 + (offset & 0x0FFF)).copied()
+            }
+            // the PPU doesn't lock them for the debuggers
+            0xFE00..=0xFE9F | 0xFF69 | 0xFF6B => Some(self.gpu.peek(addr)),
+            // the reads of HRAM and the IO registers have no side effects
+            _ => Some(self.read(addr)),
+        }
+    }
+
+    /// Write any bank without side effects nor changing the mapping, see [`Bus::peek`].
+    /// The ROM changes are lost when the game is reloaded. Returns false if the bank
+    /// doesn't exist or `addr` is an IO register, those only change through their side
+    /// effects.
+    pub fn poke(&mut self, bank: usize, addr: u16, value: u8) -> bool {
+        let offset = addr as usize;
+        let byte = match addr {
+            0x0000..=0x7FFF => self
+                .cartridge
+                .rom_mut()
+                .get_mut(bank * 0x4000 + (offset & 0x3FFF)),
+            0x8000..=0x9FFF => return self.gpu.poke_vram(bank, addr, value),
+            0xA000..=0xBFFF => self
+                .cartridge
+                .ram_mut()
+                .get_mut(bank * 0x2000 + (offset & 0x1FFF)),
+            0xC000..=0xCFFF | 0xE000..=0xEFFF => self.wram.get_mut(offset & 0x0FFF),
+            0xD000..=0xDFFF | 0xF000..=0xFDFF => {
+                self.wram.get_mut(bank * 0x1000 + (offset & 0x0FFF))
+            }
+            0xFE00..=0xFE9F => {
+                // also updates the decoded sprite
+                self.gpu.write_oam(addr, value);
+                return true;
+            }
+            0xFF80..=0xFFFE => self.zram.get_mut(offset & 0x7F),
+            0xFFFF => Some(&mut self.ienable),
+            _ => None,
+        };
+
+        byte.map(|byte| *byte = value).is_some()
+    }
+
+    /// The label at `addr`, in the banks currently mapped
+    pub fn symbol_at(&self, addr: u16) -> Option<&str> {
+        self.debugger.symbols().name(self.bank_at(addr), addr)
+    }
+
+    /// Describe `addr` as `bank:addr`, followed by the closest label if there is one
+    pub fn describe_addr(&self, addr: u16) -> String {
+        let bank = self.bank_at(addr);
+        match self.debugger.symbols().locate(bank, addr) {
+            Some(label) => format!("{bank:02X}:{addr:04X} ({label})"),
+            None => format!("{bank:02X}:{addr:04X}"),
+        }
+    }
+
+    /// Start or stop logging the memory accesses, returns the previous logger
+    pub fn set_cdl(&mut self, cdl: Option<CodeDataLogger>) -> Option<CodeDataLogger> {
+        std::mem::replace(&mut self.cdl, cdl.map(Box::new)).map(|cdl| *cdl)
+    }
+
+    pub fn cdl(&self) -> Option<&CodeDataLogger> {
+        self.cdl.as_deref()
+    }
+
+    /// Read a byte of an instruction, `opcode` is false for the operands
+    pub(crate) fn fetch(&self, addr: u16, opcode: bool) -> u8 {
+        if let Some(value) = self.oam_dma_conflict(addr) {
+            return value;
+        }
+
+        let value = self.read(addr);
+        self.debugger.check_access(addr, value, Access::Read);
+
+        let usage = match opcode {
+            true => Usage::OPCODE,
+            false => Usage::OPERAND,
+        };
+        self.log_access(addr, usage);
+        value
+    }
+
+    /// Read a byte copied by the OAM DMA or the CGB HDMA
+    fn dma_read(&self, addr: u16) -> u8 {
+        let value = self.read(addr);
+        self.debugger.check_access(addr, value, Access::Read);
+        self.log_access(addr, Usage::DATA | Usage::DMA);
+        value
+    }
+
+    fn log_access(&self, addr: u16, usage: Usage) {
+        let Some(cdl) = self.cdl.as_deref() else {
+            return;
+        };
+
+        let offset = addr as usize;
+        match addr {
+            // the writes to ROM are MBC commands
+            0x0000..=0x7FFF if usage != Usage::WRITE => cdl.log_rom(
+                self.cartridge.rom_bank(addr) * 0x4000 + (offset & 0x3FFF),
+                usage,
+            ),
+            0xA000..=0xBFFF => cdl.log_cart_ram(
+                self.cartridge.ram_bank() * 0x2000 + (offset & 0x1FFF),
+                usage,
+            ),
+            0xC000..=0xCFFF | 0xE000..=0xEFFF => cdl.log_wram(offset & 0x0FFF, usage),
+            0xD000..=0xDFFF | 0xF000..=0xFDFF => {
+                cdl.log_wram(self.wram_bank * 0x1000 + (offset & 0x0FFF), usage)
+            }
+            _ => {}
+        }
+    }
+
+    /// STOP resets DIV, and switches the CPU speed if KEY1 prepared it. The CPU and the
+    /// timer then wait for the switch while the PPU runs on.
     pub fn switch_speed(&mut self) {
+        self.timer.reset_div();
+
         if self.speed_switch {
-            self.speed = [2, 1][usize::from(self.speed & 1)];
+            self.speed = 3 - self.speed;
             self.speed_switch = false;
+            self.speed_switch_pause = SPEED_SWITCH_CLOCKS;
         }
     }
 
@@ -206,50 +430,66 @@ impl Bus {
             None => 0x00,
         }
     }
+    /// Copy a block once per HBlank, the CPU waits for the copy
     fn start_hdma(&mut self) -> u32 {
-        if !self.gpu.hblanking {
+        if !self.gpu.hblanking || self.cpu_halted {
             return 0;
         }
 
         self.dma_cpblk();
+        self.dma.next_block();
 
-        if self.dma.dma_length == 0x7F {
-            self.dma.dma_mode = None
-        }
-
-        0x08
+        dma::BLOCK_DOTS
     }
+    /// Copy all the blocks at once, the CPU waits for the whole copy
     fn start_gdma(&mut self) -> u32 {
-        let gdma_len = 1 + self.dma.dma_length as u32;
-        for _ in 0..gdma_len {
+        let mut blocks = 1;
+        self.dma_cpblk();
+        while !self.dma.next_block() {
             self.dma_cpblk();
+            blocks += 1;
         }
-        self.dma.dma_mode = None;
 
-        0x08 * gdma_len as u32
+        dma::BLOCK_DOTS * blocks
     }
     fn dma_cpblk(&mut self) {
-        let src_addr = self.dma.src;
+        let source = self.dma.source();
 
         for i in 0x00..0x10 {
-            let src = self.mem_read(src_addr + i);
+            let src = source.map_or(0xFF, |addr| self.dma_read(addr.wrapping_add(i)));
             self.gpu.mem_write(self.dma.dst + i, src)
         }
-        self.dma.src += 0x10;
-        self.dma.dst += 0x10;
-
-        if self.dma.dma_length == 0 {
-            self.dma.dma_length = 0x7F;
-        } else {
-            self.dma.dma_length -= 1;
-        }
     }
 }
 
 impl MemoryAccess for Bus {
     fn mem_read(&self, addr: u16) -> u8 {
+        if let Some(value) = self.oam_dma_conflict(addr) {
+            return value;
+        }
+
+        let value = self.read(addr);
+        self.debugger.check_access(addr, value, Access::Read);
+        self.log_access(addr, Usage::DATA);
+        value
+    }
+
+    fn mem_write(&mut self, addr: u16, value: u8) {
+        if self.oam_dma_conflict(addr).is_some() {
+            return;
+        }
+
+        self.debugger.check_access(addr, value, Access::Write);
+        self.log_access(addr, Usage::WRITE);
+        self.write(addr, value)
+    }
+}
+
+impl Bus {
+    /// Read a byte without triggering the watchpoints
+    pub(crate) fn read(&self, addr: u16) -> u8 {
         match addr {
-            0x0000..=0x7FFF => self.cartridge.rom_read(addr),
+            0x0000..=0x7FFF => self.cheats.rom_read(addr, self.cartridge.rom_read(addr)),
 
             0x8000..=0x9FFF => self.gpu.mem_read(addr),
 
@@ -268,12 +508,17 @@ impl MemoryAccess for Bus {
 
             0xFF0F => self.iflag,
 
-            0xff4d => ((self.speed & 0x02) << 6) | self.speed_switch as u8,
+            0xFF46 => self.oam_dma.source,
+            0xff4d if self.gb_mode == GbMode::Cgb => {
+                0x7E | ((self.speed & 0x02) << 6) | self.speed_switch as u8
+            }
+            0xff4d => 0xFF,
             0xff40..=0xff4f => self.gpu.mem_read(addr),
             0xff51..=0xff55 => self.dma.mem_read(addr),
-            0xff68..=0xff6b => self.gpu.mem_read(addr),
+            0xff68..=0xff6c => self.gpu.mem_read(addr),
 
-            0xFF70 => self.wram_bank as u8,
+            0xFF70 if self.gb_mode == GbMode::Cgb => self.wram_bank as u8,
+            0xFF70 => 0xFF,
 
             0xFF80..=0xFFFE => self.zram[(addr & 0x7F) as usize],
 
@@ -282,7 +527,7 @@ impl MemoryAccess for Bus {
         }
     }
 
-    fn mem_write(&mut self, addr: u16, value: u8) {
+    fn write(&mut self, addr: u16, value: u8) {
         match addr {
             0x0000..=0x7FFF => self.cartridge.rom_write(addr, value),
 
@@ -292,7 +537,7 @@ impl MemoryAccess for Bus {
 
             0xC000..=0xCFFF | 0xE000..=0xEFFF => self.wram[(addr & 0x0FFF) as usize] = value,
             0xD000..=0xDFFF | 0xF000..=0xFDFF => {
-                self.wram[(addr & 0x0FFF | 0x1000) as usize] = value
+                self.wram[(self.wram_bank * 0x1000) | (addr & 0x0FFF) as usize] = value
             }
 
             0xFE00..=0xFE9F => self.gpu.mem_write(addr, value),
@@ -304,25 +549,13 @@ impl MemoryAccess for Bus {
             0xFF01..=0xFF02 => self.serial.mem_write(addr, value),
             0xFF04..=0xFF07 => self.timer.mem_write(addr, value),
 
-            0xFF46 => {
-                let src_addr = (value as u16) << 8;
-                for i in 0x00..=0x9F {
-                    let dst_addr = 0xFE00 + i;
-                    let src = self.mem_read(src_addr + i);
-
-                    self.mem_write(dst_addr, src);
-                }
-            }
+            0xFF46 => self.oam_dma.start(value),
             0xff40..=0xff4b | 0xff4f => self.gpu.mem_write(addr, value),
-            0xff4d => {
-                if value & 1 == 1 {
-                    self.speed_switch = true
-                }
-            }
+            0xff4d if self.gb_mode == GbMode::Cgb => self.speed_switch = value & 1 == 1,
             0xff51..=0xff55 => self.dma.mem_write(addr, value),
-            0xff68..=0xff6b => self.gpu.mem_write(addr, value),
+            0xff68..=0xff6c => self.gpu.mem_write(addr, value),
 
-            0xFF70 => {
+            0xFF70 if self.gb_mode == GbMode::Cgb => {
                 self.wram_bank = if (value & 0x7) == 0 {
                     1
                 } else {
@@ -337,3 +570,230 @@ impl MemoryAccess for Bus {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cheats::Cheat;
+
+    /// A MBC1 cartridge with 4 ROM banks and 32 KiB of RAM, each ROM bank starts with
+    /// its number
+    fn mbc1_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x10000];
+        rom[0x147] = 0x02;
+        rom[0x149] = 0x03;
+        for bank in 0..4 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        rom
+    }
+
+    fn mbc1_bus() -> Bus {
+        Bus::new(&mbc1_rom())
+    }
+
+    /// The MBC1 cartridge, for the CGB
+    fn cgb_bus() -> Bus {
+        let mut rom = mbc1_rom();
+        rom[0x143] = 0x80;
+        Bus::new(&rom)
+    }
+
+    #[test]
+    fn wramx_writes() {
+        let mut bus = cgb_bus();
+
+        bus.write(0xFF70, 3);
+        bus.write(0xD123, 0x33);
+        bus.write(0xFF70, 5);
+        bus.write(0xD123, 0x55);
+        assert_eq!(bus.read(0xD123), 0x55);
+        assert_eq!(bus.peek(3, 0xD123), Some(0x33));
+        assert_eq!(bus.peek(5, 0xD123), Some(0x55));
+        assert_eq!(bus.peek(1, 0xD123), Some(0));
+
+        assert!(bus.poke(3, 0xD123, 0x34));
+        bus.write(0xFF70, 3);
+        assert_eq!(bus.read(0xD123), 0x34);
+        // bank 0 selects bank 1
+        bus.write(0xFF70, 0);
+        assert_eq!(bus.read(0xD123), 0);
+
+        // no SVBK on DMG, WRAMX is always bank 1
+        let mut bus = mbc1_bus();
+        bus.write(0xD123, 0x11);
+        bus.write(0xFF70, 3);
+        assert_eq!(bus.read(0xFF70), 0xFF);
+        assert_eq!(bus.read(0xD123), 0x11);
+    }
+
+    #[test]
+    fn peek_and_poke_banks() {
+        let mut bus = mbc1_bus();
+
+        assert_eq!(bus.peek(3, 0x4000), Some(3));
+        assert_eq!(bus.peek(4, 0x4000), None);
+        // the mapping didn't change
+        assert_eq!(bus.read(0x4000), 1);
+
+        assert!(bus.poke(2, 0xA010, 0x42));
+        assert_eq!(bus.peek(2, 0xA010), Some(0x42));
+        assert_eq!(bus.peek(0, 0xA010), Some(0));
+
+        assert!(bus.poke(1, 0x8000, 0x24));
+        assert_eq!(bus.peek(1, 0x8000), Some(0x24));
+        assert_eq!(bus.read(0x8000), 0);
+        assert!(!bus.poke(2, 0x8000, 0x24));
+
+        // the IO registers only change through their side effects
+        assert!(!bus.poke(0, 0xFF46, 0xC0));
+    }
+
+    #[test]
+    fn game_shark_writes_ignore_the_ram_enable() {
+        let mut bus = mbc1_bus();
+        bus.cheats.add(Cheat::parse("", "01420AA0").unwrap());
+
+        // the cartridge RAM is disabled, the code still writes to it
+        bus.apply_cheats();
+        assert_eq!(bus.peek(0, 0xA00A), Some(0x42));
+    }
+
+    #[test]
+    fn timed_oam_dma() {
+        let mut bus = mbc1_bus();
+        for i in 0..0xA0 {
+            bus.write(0xC000 + i, 0x10 + i as u8);
+        }
+        bus.write(0xFF80, 0x42);
+
+        bus.mem_write(0xFF46, 0xC0);
+        assert_eq!(bus.mem_read(0xFF46), 0xC0);
+        bus.sync(8);
+        assert_eq!(bus.peek(0, 0xFE00), Some(0x10));
+        assert_eq!(bus.peek(0, 0xFE01), Some(0));
+
+        // the CPU only accesses HRAM and the IO registers
+        assert_eq!(bus.mem_read(0xFF80), 0x42);
+        assert_eq!(bus.mem_read(0xFE00), 0xFF);
+        assert_eq!(bus.mem_read(0x4000), 0x10);
+        bus.sync(4);
+        assert_eq!(bus.mem_read(0x4000), 0x11);
+        bus.mem_write(0xC000, 0x99);
+        assert_eq!(bus.peek(0, 0xC000), Some(0x10));
+
+        bus.sync(4 * 157);
+        assert_eq!(bus.peek(0, 0xFE9F), Some(0));
+        assert_eq!(bus.mem_read(0x4000), 0x10 + 0x9E);
+        bus.sync(4);
+        assert_eq!(bus.peek(0, 0xFE9F), Some(0x10 + 0x9F));
+        assert_eq!(bus.mem_read(0x4000), 1);
+    }
+
+    #[test]
+    fn vram_dma() {
+        let mut bus = mbc1_bus();
+        for i in 0..0x40 {
+            bus.write(0xC000 + i, i as u8);
+        }
+
+        // the GDMA copies 2 blocks at once, the CPU waits for it
+        bus.write(0xFF51, 0xC0);
+        bus.write(0xFF52, 0x00);
+        bus.write(0xFF53, 0x01);
+        bus.write(0xFF54, 0x00);
+        bus.write(0xFF55, 0x01);
+        assert_eq!(bus.sync(4), 4 + 2 * dma::BLOCK_DOTS);
+        assert_eq!(bus.peek(0, 0x811F), Some(0x1F));
+        assert_eq!(bus.read(0xFF55), 0xFF);
+
+        // the HDMA copies a block per HBlank
+        bus.write(0xFF40, 0x00);
+        bus.write(0xFF40, 0x91);
+        bus.write(0xFF52, 0x20);
+        bus.write(0xFF53, 0x02);
+        bus.write(0xFF55, 0x82);
+        assert_eq!(bus.read(0xFF55), 0x02);
+        bus.sync(4 * 40);
+        assert_eq!(bus.peek(0, 0x8200), Some(0));
+        bus.sync(4 * 40);
+        assert_eq!(bus.sync(4), 4 + dma::BLOCK_DOTS);
+        assert_eq!(bus.peek(0, 0x820F), Some(0x2F));
+        bus.sync(4);
+        assert_eq!(bus.peek(0, 0x8210), Some(0));
+        assert_eq!(bus.read(0xFF55), 0x01);
+
+        // HALT pauses it
+        bus.cpu_halted = true;
+        bus.sync(456);
+        bus.sync(4);
+        assert_eq!(bus.peek(0, 0x8210), Some(0));
+        bus.cpu_halted = false;
+        bus.sync(456);
+        bus.sync(4);
+        assert_eq!(bus.peek(0, 0x8210), Some(0x30));
+        assert_eq!(bus.read(0xFF55), 0x00);
+
+        // a cancel leaves the blocks left
+        bus.write(0xFF55, 0x00);
+        assert_eq!(bus.read(0xFF55), 0x80);
+
+        // VRAM reads 0xFF, the addresses above 0xE000 read the cartridge RAM
+        bus.write(0x0000, 0x0A);
+        bus.write(0xA000, 0x42);
+        bus.write(0xFF51, 0xE0);
+        bus.write(0xFF52, 0x00);
+        bus.write(0xFF55, 0x00);
+        bus.sync(4);
+        assert_eq!(bus.peek(0, 0x8200), Some(0x42));
+        bus.write(0xFF51, 0x80);
+        bus.write(0xFF55, 0x00);
+        bus.sync(4);
+        assert_eq!(bus.peek(0, 0x8200), Some(0xFF));
+    }
+
+    #[test]
+    fn double_speed() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x143] = 0x80;
+        let mut bus = Bus::new(&rom);
+        assert_eq!(bus.read(0xFF4D), 0x7E);
+
+        bus.write(0xFF4D, 0x01);
+        assert_eq!(bus.read(0xFF4D), 0x7F);
+        bus.sync(1000);
+        bus.switch_speed();
+        assert_eq!(bus.read(0xFF4D), 0xFE);
+        assert_eq!(bus.read(0xFF04), 0);
+
+        // the PPU runs during the switch, at a dot per 2 CPU clocks afterwards
+        assert_eq!(bus.sync(4), (4 + SPEED_SWITCH_CLOCKS) / 2);
+        assert_eq!(bus.read(0xFF04), 0);
+        assert_eq!(bus.sync(4), 2);
+
+        // the timer and the serial follow the CPU clock
+        bus.sync(248);
+        assert_eq!(bus.read(0xFF04), 1);
+        bus.write(0xFF01, 0x42);
+        bus.write(0xFF02, 0x81);
+        bus.sync(8 * 512 - 4);
+        assert_eq!(bus.iflag & 0x08, 0);
+        bus.sync(4);
+        assert_eq!(bus.iflag & 0x08, 0x08);
+        assert_eq!(bus.read(0xFF01), 0xFF);
+        assert_eq!(bus.read(0xFF02), 0x7D);
+
+        // a second STOP switches back
+        bus.write(0xFF4D, 0x01);
+        bus.switch_speed();
+        assert_eq!(bus.read(0xFF4D), 0x7E);
+        assert_eq!(bus.sync(4), 4 + SPEED_SWITCH_CLOCKS);
+
+        // no KEY1 on DMG
+        let mut bus = mbc1_bus();
+        bus.write(0xFF4D, 0x01);
+        assert_eq!(bus.read(0xFF4D), 0xFF);
+        bus.switch_speed();
+        assert_eq!(bus.sync(4), 4);
+    }
+}
@@ -5,7 +5,12 @@ mod palette;
 mod sprites;
 
 use color::Color;
-use sprites::Sprite;
 
-pub use color::ColorScheme;
-pub use gpu::Gpu;
+pub use color::{ColorCorrection, ColorScheme};
+pub use gpu::{
+    debug::{DebugImage, DebugPalettes, OamSprite},
+    layers::{Layer, Layers, PixelInfo},
+    Gpu,
+};
+pub use palette::ColorSchemes;
+pub use sprites::Sprite;
@@ -1,3 +1,5 @@
+use std::str::FromStr;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ColorType {
     Black,
@@ -88,6 +90,37 @@ pub struct Rgba {
     pub a: u8,
 }
 
+/// How the CGB colors are shown on a modern screen
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ColorCorrection {
+    /// The 5 bits channels scaled to 8 bits
+    Raw,
+    /// The washed out colors of the CGB LCD
+    #[default]
+    Cgb,
+    /// The darker colors of the GBA LCD, for the games made for its screen
+    Gba,
+    /// The raw colors through a gamma curve, above 1.0 darkens the midtones
+    Gamma(f32),
+}
+
+impl FromStr for ColorCorrection {
+    type Err = String;
+
+    /// `raw`, `cgb`, `gba`, or a gamma like `2.2`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "raw" => Ok(Self::Raw),
+            "cgb" | "gbc" => Ok(Self::Cgb),
+            "gba" => Ok(Self::Gba),
+            gamma => match gamma.parse::<f32>() {
+                Ok(gamma) if gamma > 0.0 => Ok(Self::Gamma(gamma)),
+                _ => Err(format!("Unknown color correction {s}")),
+            },
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Rgb555 {
     pub r: u8,
@@ -95,23 +128,48 @@ pub struct Rgb555 {
     pub b: u8,
 }
 impl Rgb555 {
-    pub const fn into_rgba(self) -> Rgba {
+    pub fn into_rgba(self, correction: ColorCorrection) -> Rgba {
         let Self { r, g, b } = self;
 
-        let (r, g, b) = (r as u32, g as u32, b as u32);
-
-        Rgba {
-            r: ((r * 13 + g * 2 + b) >> 1) as u8,
-            g: ((g * 3 + b) << 1) as u8,
-            b: ((r * 3 + g * 2 + b * 11) >> 1) as u8,
-            // r: ((r as u16 * 0xFF) / 0x1F) as u8,
-            // g: ((g as u16 * 0xFF) / 0x1F) as u8,
-            // b: ((b as u16 * 0xFF) / 0x1F) as u8,
-            a: 0xFF,
-        }
+        let (r, g, b) = match correction {
+            ColorCorrection::Raw => (scale_channel(r), scale_channel(g), scale_channel(b)),
+            ColorCorrection::Cgb => {
+                let (r, g, b) = (r as u32, g as u32, b as u32);
+                (
+                    ((r * 13 + g * 2 + b) >> 1) as u8,
+                    ((g * 3 + b) << 1) as u8,
+                    ((r * 3 + g * 2 + b * 11) >> 1) as u8,
+                )
+            }
+            ColorCorrection::Gba => {
+                // the LCD gamma, the channels bleeding into each other, then the
+                // gamma of the screen
+                let [r, g, b] = [r, g, b].map(|c| (f32::from(c) / 31.0).powf(4.0));
+                let mix = |lr: f32, lg: f32, lb: f32| {
+                    let linear = (lr * r + lg * g + lb * b) / 255.0;
+                    (linear.powf(1.0 / 2.2) * 255.0 * 255.0 / 280.0).round() as u8
+                };
+                (
+                    mix(255.0, 50.0, 0.0),
+                    mix(10.0, 230.0, 30.0),
+                    mix(50.0, 10.0, 220.0),
+                )
+            }
+            ColorCorrection::Gamma(gamma) => {
+                let curve = |c: u8| ((f32::from(c) / 31.0).powf(gamma) * 255.0).round() as u8;
+                (curve(r), curve(g), curve(b))
+            }
+        };
+
+        Rgba { r, g, b, a: 0xFF }
     }
 }
 
+/// A 5 bits channel to 8 bits, 0x1F is 0xFF
+const fn scale_channel(c: u8) -> u8 {
+    (c << 3) | (c >> 2)
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum Color {
     Rgba(Rgba),
@@ -144,15 +202,51 @@ impl Color {
         Color::Rgb555(rgb555)
     }
 
-    pub const fn into_rgba(self) -> Rgba {
+    /// The color on screen, the CGB colors go through `correction`
+    pub fn into_rgba(self, correction: ColorCorrection) -> Rgba {
         match self {
             Color::Rgba(rgba) => rgba,
-            Color::Rgb555(rgb555) => rgb555.into_rgba(),
+            Color::Rgb555(rgb555) => rgb555.into_rgba(correction),
         }
     }
 
-    pub const fn into_rgba_slice(self) -> [u8; 4] {
-        let rgba = self.into_rgba();
+    pub fn into_rgba_slice(self, correction: ColorCorrection) -> [u8; 4] {
+        let rgba = self.into_rgba(correction);
         [rgba.r, rgba.g, rgba.b, rgba.a]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn color_correction() {
+        let white = Color::RGB555_WHITE;
+        let blue = Rgb555 {
+            r: 0,
+            g: 0,
+            b: 0x10,
+        };
+        let rgb = |color: Rgb555, correction| {
+            let Rgba { r, g, b, .. } = color.into_rgba(correction);
+            (r, g, b)
+        };
+
+        assert_eq!(rgb(white, ColorCorrection::Raw), (0xFF, 0xFF, 0xFF));
+        assert_eq!(rgb(blue, ColorCorrection::Raw), (0, 0, 0x84));
+        assert_eq!(rgb(white, ColorCorrection::Cgb), (0xF8, 0xF8, 0xF8));
+        // the green and the red bleed into the blue
+        assert_eq!(rgb(blue, ColorCorrection::Cgb), (0x08, 0x20, 0x58));
+        assert_eq!(rgb(white, ColorCorrection::Gba), (0xFC, 0xEE, 0xF2));
+        assert!(rgb(blue, ColorCorrection::Gba).2 < 0x84);
+        assert_eq!(rgb(white, ColorCorrection::Gamma(2.2)), (0xFF, 0xFF, 0xFF));
+        assert_eq!(rgb(blue, ColorCorrection::Gamma(1.0)), (0, 0, 0x84));
+        assert_eq!(rgb(blue, ColorCorrection::Gamma(2.2)), (0, 0, 0x3C));
+
+        assert_eq!("GBA".parse(), Ok(ColorCorrection::Gba));
+        assert_eq!("2.2".parse(), Ok(ColorCorrection::Gamma(2.2)));
+        assert!("-1".parse::<ColorCorrection>().is_err());
+        assert!("lcd".parse::<ColorCorrection>().is_err());
+    }
+}
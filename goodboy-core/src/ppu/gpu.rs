@@ -1,20 +1,31 @@
+pub mod debug;
+mod fifo;
+pub mod layers;
+
 use crate::{
     gb_mode::GbMode,
     mmu::MemoryAccess,
     ppu::{
         color::ColorType,
         lcd::{LCDControl, LCDStatus},
-        palette::{PaletteKind, Palettes},
+        palette::{ColorSchemes, PaletteKind, Palettes},
         sprites, Color, Sprite,
     },
     vm::{Screen, SCREEN_HEIGHT, SCREEN_WIDTH},
 };
 
-use super::color::{ColorScheme, Rgb555};
+use super::color::{ColorCorrection, Rgb555};
+
+use fifo::PixelFifo;
+use layers::{Layers, PixelInfo};
 
 const OAM_SIZE: usize = 0xA0;
 const VRAM_SIZE: usize = 0x4000;
 
+/// Dots in a line, and in the OAM search starting it
+const LINE_DOTS: u32 = 456;
+const OAM_SEARCH_DOTS: u32 = 80;
+
 #[derive(PartialEq, PartialOrd, Clone, Copy)]
 enum GpuMode {
     HBlank,
@@ -40,6 +51,10 @@ pub struct Gpu {
     vram: Box<[u8; VRAM_SIZE]>,
     oam: [u8; OAM_SIZE],
     pub screen_buffer: Screen,
+    /// How every pixel of the screen buffer was drawn, if it is recorded
+    pixel_info: Option<Vec<PixelInfo>>,
+    /// The layers shown
+    layers: Layers,
 
     lcd_control: LCDControl,
     lcd_status: LCDStatus,
@@ -52,14 +67,24 @@ pub struct Gpu {
     scroll_y: u8,
     win_x: u8,
     win_y: u8,
+    /// The window line drawn next, only counts the lines where the window was drawn
+    window_line: u8,
+    /// LY matched WY during the frame, the window is drawn from then on
+    wy_triggered: bool,
+    /// On DMG, a window at WX=166 is drawn on the whole next line
+    wx166_glitch: bool,
 
     bg_palette: u8,
     object_palette: [u8; 2],
     palettes: Palettes,
 
-    bg_priorities: [u8; SCREEN_WIDTH],
-
     sprites: [Sprite; 40],
+    /// The background fetcher and the pixel FIFOs, drawing the line during mode 3
+    fifo: PixelFifo,
+
+    /// The STAT interrupt line, the OR of the enabled STAT sources. The LCD interrupt
+    /// is only requested when it goes high.
+    stat_line: bool,
 
     pub interrupt_vblank: bool,
     pub interrupt_lcd: bool,
@@ -78,7 +103,12 @@ pub struct Gpu {
     cgb_sppal_auto_inc: bool,
     cgb_sppal_addr: u8,
     cgb_sppal: [[Rgb555; 4]; 8],
+    /// OPRI bit 0 clear: the sprite first in OAM is drawn over the others, instead of
+    /// the leftmost one. Set by the CGB boot ROM for the DMG games.
+    oam_priority: bool,
+    color_correction: ColorCorrection,
 
+    /// An HBlank started during the last sync, the HDMA copies a block
     pub hblanking: bool,
 }
 
@@ -90,6 +120,8 @@ impl Gpu {
             vram: Box::new([0; VRAM_SIZE]),
             oam: [0; OAM_SIZE],
             screen_buffer: Box::new([0x00; SCREEN_WIDTH * SCREEN_HEIGHT * 4]),
+            pixel_info: None,
+            layers: Layers::default(),
 
             lcd_control: LCDControl::default(),
             lcd_status: LCDStatus::default(),
@@ -102,14 +134,18 @@ impl Gpu {
             scroll_y: 0,
             win_x: 0,
             win_y: 0,
+            window_line: 0,
+            wy_triggered: false,
+            wx166_glitch: false,
 
             bg_palette: 0,
             object_palette: [0, 0],
             palettes: Palettes::default(),
 
-            bg_priorities: [0; SCREEN_WIDTH],
             sprites: [Default::default(); 40],
+            fifo: Default::default(),
 
+            stat_line: false,
             interrupt_vblank: false,
             interrupt_lcd: false,
             interrupt: 0,
@@ -124,286 +160,181 @@ impl Gpu {
             cgb_sppal_auto_inc: false,
             cgb_sppal_addr: 0,
             cgb_sppal: [[Color::RGB555_WHITE; 4]; 8],
+            oam_priority: gb_mode == GbMode::Cgb,
+            color_correction: ColorCorrection::default(),
 
             hblanking: false,
         }
     }
 
     pub fn sync(&mut self, clocks: u32) {
+        self.hblanking = false;
         // Check if lcd is off
         if !self.lcd_control.lcd_on() {
             return;
         }
-        self.hblanking = false;
 
         let mut clocks = clocks;
 
         while clocks > 0 {
-            let ran_clocks = if clocks >= 80 { 80 } else { clocks };
+            // the pixel transfer runs dot by dot, the other modes until their end
+            let ran_clocks = match self.mode {
+                GpuMode::PixelTransfer => {
+                    if self.pixel_transfer_dot() {
+                        self.change_mode(GpuMode::HBlank);
+                    }
+                    1
+                }
+                _ => clocks.min(self.next_event()),
+            };
 
             self.clocks += ran_clocks;
             clocks -= ran_clocks;
 
-            if self.clocks >= 456 {
-                self.clocks -= 456;
+            if self.clocks >= LINE_DOTS {
+                self.clocks -= LINE_DOTS;
                 self.scan_line = (self.scan_line + 1) % 154;
-                self.interrupt_lyc();
+                if self.scan_line == 0 {
+                    self.start_frame();
+                }
 
-                if self.scan_line >= 144 && self.mode != GpuMode::VBlank {
+                if self.scan_line < 144 {
+                    self.change_mode(GpuMode::OAMSearch);
+                } else if self.mode != GpuMode::VBlank {
                     self.change_mode(GpuMode::VBlank);
                 }
+            } else if self.mode == GpuMode::OAMSearch && self.clocks >= OAM_SEARCH_DOTS {
+                self.change_mode(GpuMode::PixelTransfer);
             }
 
-            if self.scan_line < 144 {
-                if self.clocks <= 80 {
-                    if self.mode != GpuMode::OAMSearch {
-                        self.change_mode(GpuMode::OAMSearch);
-                    }
-                } else if self.clocks <= (80 + 172) {
-                    if self.mode != GpuMode::PixelTransfer {
-                        self.change_mode(GpuMode::PixelTransfer);
-                    }
-                } else {
-                    if self.mode != GpuMode::HBlank {
-                        self.change_mode(GpuMode::HBlank);
-                    }
-                }
-            }
+            self.update_stat_line();
         }
     }
 
-    fn change_mode(&mut self, mode: GpuMode) {
-        self.mode = mode;
+    /// The dots until the end of the mode, or until the LY=LYC comparison changes
+    fn next_event(&self) -> u32 {
+        let end = match self.mode {
+            GpuMode::OAMSearch => OAM_SEARCH_DOTS,
+            _ => LINE_DOTS,
+        };
+        let compare = match (self.scan_line, self.clocks) {
+            (_, 0..=3) => 4,
+            (153, 4..=11) => (self.clocks / 4 + 1) * 4,
+            _ => end,
+        };
 
-        if match self.mode {
-            GpuMode::HBlank => {
-                self.render_scan_line();
-                self.hblanking = true;
-                self.lcd_status.hblank_check()
-            }
-            GpuMode::VBlank => {
-                self.interrupt |= 0x01;
-                self.vblanked = true;
-                self.lcd_status.vblank_check()
-            }
-            GpuMode::OAMSearch => self.lcd_status.oam_check(),
-            GpuMode::PixelTransfer => false,
-        } {
-            self.interrupt |= 0x02;
-        }
+        end.min(compare) - self.clocks
     }
 
-    fn interrupt_lyc(&mut self) {
-        if self.lcd_status.scanline_check() && self.scan_line_check == self.scan_line {
-            self.interrupt |= 0x02;
+    /// The LY register, line 153 reads as 0 after its first 4 dots
+    fn ly(&self) -> u8 {
+        match (self.scan_line, self.clocks) {
+            (153, 4..) => 0,
+            (line, _) => line,
         }
     }
 
-    fn render_scan_line(&mut self) {
-        for x in 0..SCREEN_WIDTH {
-            self.set_color(x, Color::WHITE);
-            self.bg_priorities[x] = 2;
+    /// The LY value compared with LYC. The comparison is off for the first 4 dots of a
+    /// line, and on line 153 it compares 153 then 0 with a gap in between.
+    fn ly_compared(&self) -> Option<u8> {
+        match (self.scan_line, self.clocks) {
+            (0, _) => Some(0),
+            (_, 0..=3) => None,
+            (153, 4..=7) => Some(153),
+            (153, 8..=11) => None,
+            (153, _) => Some(0),
+            (line, _) => Some(line),
         }
-
-        self.render_bg();
-        self.render_sprites();
     }
 
-    fn render_bg(&mut self) {
-        let do_render = self.gb_mode == GbMode::Cgb || self.lcd_control.bg_enabled();
-
-        let win_y = if !self.lcd_control.win_on()
-            || (self.gb_mode != GbMode::Dmg && !self.lcd_control.bg_enabled())
-        {
-            -1
-        } else {
-            self.scan_line as i32 - self.win_y as i32
-        };
+    fn lyc_match(&self) -> bool {
+        self.ly_compared() == Some(self.scan_line_check)
+    }
 
-        // No window nor background to be drawn
-        if win_y < 0 && !do_render {
-            return;
+    /// The STAT line with the sources of `status` enabled
+    fn stat_sources(&self, status: LCDStatus) -> bool {
+        if !self.lcd_control.lcd_on() {
+            return false;
         }
 
-        let win_tile_y = (win_y as u16 >> 3) & 31;
-
-        let bg_y = self.scroll_y.wrapping_add(self.scan_line);
-        let bg_tile_y = (bg_y as u16 >> 3) & 31;
-
-        for x in 0..SCREEN_WIDTH {
-            let win_x = -((self.win_x as i32) - 7) + (x as i32);
-            let bg_x = self.scroll_x as u32 + x as u32;
-
-            let (tilemap_base, tile_y, tile_x, pixel_y, pixel_x) = if win_y >= 0 && win_x >= 0 {
-                (
-                    self.lcd_control.win_tilemap(),
-                    win_tile_y,
-                    (win_x as u16 >> 3),
-                    win_y as u16 & 0x07,
-                    win_x as u8 & 0x07,
-                )
-            } else if do_render {
-                (
-                    self.lcd_control.bg_tilemap(),
-                    bg_tile_y,
-                    (bg_x as u16 >> 3) & 31,
-                    bg_y as u16 & 0x07,
-                    bg_x as u8 & 0x07,
-                )
-            } else {
-                continue;
-            };
-
-            let tile_nr = self.mem_read(tilemap_base + tile_y * 32 + tile_x);
-
-            let (paletten, use_bank1, xflip, yflip, prio) = if self.gb_mode == GbMode::Cgb {
-                let f = self.read_bank1(tilemap_base + tile_y * 32 + tile_x) as usize;
-                (
-                    f & 0b111,
-                    (f & 0x08) == 0x08,
-                    (f & 0x20) == 0x20,
-                    (f & 0x40) == 0x40,
-                    (f & 0x80) == 0x80,
-                )
-            } else {
-                (0, false, false, false, false)
-            };
-
-            let tile_addr = self.lcd_control.tileset_base()
-                + (if self.lcd_control.tileset_base() == 0x8000 {
-                    tile_nr as u16
-                } else {
-                    (tile_nr as i8 as i16 + 128) as u16
-                }) * 16;
-
-            let a0 = if yflip {
-                tile_addr + 14 - pixel_y * 2
-            } else {
-                tile_addr + pixel_y * 2
-            };
-
-            let (b1, b2) = if use_bank1 {
-                (self.read_bank1(a0), self.read_bank1(a0 + 1))
-            } else {
-                (self.read_bank0(a0), self.read_bank0(a0 + 1))
-            };
-
-            let xbit = if xflip { pixel_x } else { 7 - pixel_x } as u32;
-            let colorn = if b1 & (1 << xbit) != 0 { 1 } else { 0 }
-                | if b2 & (1 << xbit) != 0 { 2 } else { 0 };
+        // the mode 2 source is also raised at the start of VBlank
+        let oam_search = self.mode == GpuMode::OAMSearch
+            || (self.mode == GpuMode::VBlank && self.scan_line == 144 && self.clocks == 0);
 
-            self.bg_priorities[x] = if colorn == 0 {
-                0
-            } else if prio {
-                1
-            } else {
-                2
-            };
+        (status.hblank_check() && self.mode == GpuMode::HBlank)
+            || (status.vblank_check() && self.mode == GpuMode::VBlank)
+            || (status.oam_check() && oam_search)
+            || (status.scanline_check() && self.lyc_match())
+    }
 
-            let color = if self.gb_mode != GbMode::Dmg {
-                Color::new_rgb555(self.cgb_bgpal[paletten][colorn])
-            } else {
-                self.palettes.get(PaletteKind::BG)[colorn].0
-            };
-            self.set_color(x, color);
+    /// Request the LCD interrupt on the rising edge of the STAT line, a source becoming
+    /// active while another one is already active doesn't raise a new interrupt
+    fn update_stat_line(&mut self) {
+        let stat_line = self.stat_sources(self.lcd_status);
+        if stat_line && !self.stat_line {
+            self.interrupt |= 0x02;
         }
+        self.stat_line = stat_line;
     }
 
-    fn render_sprites(&mut self) {
-        if !self.lcd_control.sprites_on() {
-            return;
-        }
+    fn change_mode(&mut self, mode: GpuMode) {
+        self.mode = mode;
 
-        let mut sprites_in_row: usize = 0;
-        let sprites = self.sprites.clone();
-        for sprite in sprites.iter().rev() {
-            // each row can only have 10 sprites
-            if sprites_in_row >= 10 {
-                break;
+        match self.mode {
+            GpuMode::HBlank => {
+                self.hblanking = true;
+                self.end_window_line();
             }
-
-            let scan_line = self.scan_line as i32;
-            let sprite_size = self.lcd_control.sprite_size() as i32;
-
-            // Check if the sprite is in the screen
-            if (scan_line < sprite.y || scan_line >= (sprite.y + sprite_size))
-                || (sprite.x < -7 || sprite.x >= SCREEN_WIDTH as i32)
-            {
-                continue;
+            GpuMode::VBlank => {
+                self.interrupt |= 0x01;
+                self.vblanked = true;
             }
-            // sprites_in_row += 1;
-
-            let tile_y = if sprite.flip_y {
-                sprite_size - 1 - (scan_line - sprite.y)
-            } else {
-                scan_line - sprite.y
-            } as u16;
-
-            let tile_number = sprite.tile_number & if sprite_size == 16 { 0xFE } else { 0xFF };
-            let tile_addr = 0x8000 + tile_number * 16 + tile_y * 2;
-
-            let (b1, b2) = if sprite.vram_bank == 1 && self.gb_mode == GbMode::Cgb {
-                (self.read_bank1(tile_addr), self.read_bank1(tile_addr + 1))
-            } else {
-                (self.read_bank0(tile_addr), self.read_bank0(tile_addr + 1))
-            };
-
-            'bits: for x in 0..8 {
-                if sprite.x + x < 0 || sprite.x + x >= (SCREEN_WIDTH as i32) {
-                    continue;
-                }
-
-                let xbit = 1 << (if sprite.flip_x { x } else { 7 - x });
-                let colorn =
-                    if b1 & xbit != 0 { 1 } else { 0 } | if b2 & xbit != 0 { 2 } else { 0 };
-
-                // the pixel is transparent
-                if colorn == 0 {
-                    continue;
-                }
-
-                let xpriority = self.bg_priorities[(sprite.x + x) as usize];
-                let bg_is_priority = (self.gb_mode == GbMode::Cgb && xpriority == 1)
-                    || (sprite.priority && xpriority != 0);
-
-                let color = if self.gb_mode == GbMode::Cgb {
-                    if self.lcd_control.bg_enabled() && bg_is_priority {
-                        continue 'bits;
-                    }
-                    Color::new_rgb555(self.cgb_sppal[sprite.paletten][colorn])
-                } else {
-                    if bg_is_priority {
-                        continue 'bits;
-                    }
-
-                    let palette = if !sprite.palette {
-                        PaletteKind::OBJ0
-                    } else {
-                        PaletteKind::OBJ1
-                    };
-                    self.palettes.get(palette)[colorn].0
-                };
-                self.set_color((sprite.x + x) as usize, color)
+            GpuMode::OAMSearch => self.check_wy(),
+            GpuMode::PixelTransfer => {
+                self.check_wy();
+                self.scan_oam();
+                self.start_pixel_transfer();
             }
         }
     }
 
-    fn read_bank0(&self, addr: u16) -> u8 {
-        if addr < 0x8000 || addr >= 0xA000 {
-            panic!("Reading a non-bank0 address: 0x{addr:04x}");
+    fn start_frame(&mut self) {
+        self.window_line = 0;
+        self.wy_triggered = false;
+        self.wx166_glitch = false;
+    }
+
+    /// Latch the window trigger when LY matches WY, a later change of WY doesn't
+    /// remove the window from the frame
+    fn check_wy(&mut self) {
+        if self.scan_line == self.win_y {
+            self.wy_triggered = true;
         }
-        self.vram[addr as usize & 0x1FFF]
     }
-    fn read_bank1(&self, addr: u16) -> u8 {
-        if addr < 0x8000 || addr >= 0xA000 {
-            panic!("Reading a non-bank1 address: 0x{addr:04x}");
+
+    fn end_window_line(&mut self) {
+        if self.fifo.window_drawn() {
+            self.window_line = self.window_line.wrapping_add(1);
         }
-        self.vram[0x2000 + (addr as usize & 0x1FFF)]
+
+        self.wx166_glitch = self.gb_mode == GbMode::Dmg
+            && self.win_x == 166
+            && self.wy_triggered
+            && self.lcd_control.win_on()
+            && self.lcd_control.bg_enabled();
     }
 
-    fn set_color(&mut self, x: usize, color: Color) {
+    fn set_color(&mut self, x: usize, color: Color, info: PixelInfo) {
         let y = self.scan_line as usize;
-        for (i, rgba) in color.into_rgba_slice().iter().enumerate() {
+        if let Some(pixel_info) = self.pixel_info.as_mut() {
+            pixel_info[y * SCREEN_WIDTH + x] = info;
+        }
+
+        for (i, rgba) in color
+            .into_rgba_slice(self.color_correction)
+            .iter()
+            .enumerate()
+        {
             self.screen_buffer[y * SCREEN_WIDTH * 4 + x * 4 + i] = *rgba;
         }
     }
@@ -426,26 +357,103 @@ impl Gpu {
 
     fn clear_screen(&mut self) {
         for pixels in self.screen_buffer.chunks_mut(4) {
-            pixels.copy_from_slice(&Color::WHITE.into_rgba_slice());
+            pixels.copy_from_slice(&Color::WHITE.into_rgba_slice(self.color_correction));
+        }
+        if let Some(pixel_info) = self.pixel_info.as_mut() {
+            pixel_info.fill(PixelInfo::default());
         }
         self.vblanked = true;
     }
 
-    pub fn set_color_scheme(&mut self, color_scheme: ColorScheme) {
-        self.palettes.set_color_scheme(color_scheme)
+    pub fn vram_bank(&self) -> usize {
+        self.vram_bank
+    }
+
+    /// Read a VRAM bank directly, `None` if the bank doesn't exist
+    pub fn peek_vram(&self, bank: usize, addr: u16) -> Option<u8> {
+        (bank < 2).then(|| self.vram[bank * 0x2000 + (addr as usize & 0x1FFF)])
+    }
+
+    /// Write a VRAM bank directly, returns false if the bank doesn't exist
+    pub fn poke_vram(&mut self, bank: usize, addr: u16, value: u8) -> bool {
+        if bank >= 2 {
+            return false;
+        }
+        self.vram[bank * 0x2000 + (addr as usize & 0x1FFF)] = value;
+        true
+    }
+
+    /// The PPU reads VRAM and the CGB palettes during mode 3, the CPU reads 0xFF and
+    /// its writes are ignored
+    fn vram_locked(&self) -> bool {
+        self.mode == GpuMode::PixelTransfer
+    }
+
+    /// The PPU reads OAM during modes 2 and 3
+    fn oam_locked(&self) -> bool {
+        matches!(self.mode, GpuMode::OAMSearch | GpuMode::PixelTransfer)
+    }
+
+    /// Read VRAM, OAM or a register without the lock of modes 2 and 3, for the debuggers
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0x9FFF => self.vram[(self.vram_bank * 0x2000) | (addr & 0x1FFF) as usize],
+            0xFE00..=0xFE9F => self.oam[(addr - 0xFE00) as usize],
+            0xff69 => palette_data(&self.cgb_bgpal, self.cgb_bgpal_addr),
+            0xff6b => palette_data(&self.cgb_sppal, self.cgb_sppal_addr),
+            _ => self.mem_read(addr),
+        }
+    }
+
+    /// Write OAM whatever the mode, for the OAM DMA and the debuggers
+    pub(crate) fn write_oam(&mut self, addr: u16, value: u8) {
+        let addr = (addr - 0xFE00) as usize;
+        self.oam[addr] = value;
+        sprites::update_sprites(&mut self.sprites, addr, value);
+    }
+
+    pub fn set_color_schemes(&mut self, color_schemes: ColorSchemes) {
+        self.palettes.set_color_schemes(color_schemes)
+    }
+
+    /// How the CGB colors are shown, from the next pixel drawn
+    pub fn set_color_correction(&mut self, correction: ColorCorrection) {
+        self.color_correction = correction;
+    }
+
+    pub fn color_correction(&self) -> ColorCorrection {
+        self.color_correction
+    }
+}
+
+/// The byte of the CGB palette RAM at `addr` (BCPS or OCPS), colors are little endian
+fn palette_data(palettes: &[[Rgb555; 4]; 8], addr: u8) -> u8 {
+    let paletten = (addr >> 3) as usize;
+    let colorn = ((addr >> 1) & 0x03) as usize;
+
+    let color = palettes[paletten][colorn];
+    if addr & 1 == 0 {
+        // even
+        color.r | ((color.g & 0x07) << 5)
+    } else {
+        // odd
+        ((color.g & 0x18) >> 3) | (color.b << 2)
     }
 }
 
 impl MemoryAccess for Gpu {
     fn mem_read(&self, addr: u16) -> u8 {
         match addr {
+            0x8000..=0x9FFF | 0xff69 | 0xff6b if self.vram_locked() => 0xFF,
+            0xFE00..=0xFE9F if self.oam_locked() => 0xFF,
+
             0x8000..=0x9FFF => self.vram[(self.vram_bank * 0x2000) | (addr & 0x1FFF) as usize],
             0xFE00..=0xFE9F => self.oam[(addr - 0xFE00) as usize],
 
             0xFF40 => self.lcd_control.bits(),
             0xFF41 => {
                 self.lcd_status.bits()
-                    | (if self.scan_line == self.scan_line_check {
+                    | (if self.lcd_control.lcd_on() && self.lyc_match() {
                         0x04
                     } else {
                         0
@@ -456,7 +464,7 @@ impl MemoryAccess for Gpu {
             0xFF42 => self.scroll_y,
             0xFF43 => self.scroll_x,
 
-            0xFF44 => self.scan_line,
+            0xFF44 => self.ly(),
             0xFF45 => self.scan_line_check,
 
             0xff46 => 0,
@@ -471,46 +479,25 @@ impl MemoryAccess for Gpu {
             0xff4f => self.vram_bank as u8,
 
             0xff68 => self.cgb_bgpal_addr | if self.cgb_bgpal_auto_inc { 0x80 } else { 0 },
-            0xff69 => {
-                let paletten = (self.cgb_bgpal_addr >> 3) as usize;
-                let colorn = ((self.cgb_bgpal_addr >> 1) & 0x03) as usize;
-
-                let color = self.cgb_bgpal[paletten][colorn];
-                if self.cgb_bgpal_addr & 1 == 0 {
-                    // even
-                    color.r | ((color.g & 0x07) << 5)
-                } else {
-                    // odd
-                    ((color.g & 0x18) >> 3) | (color.b << 2)
-                }
-            }
+            0xff69 => palette_data(&self.cgb_bgpal, self.cgb_bgpal_addr),
             0xff6a => self.cgb_sppal_addr | if self.cgb_sppal_auto_inc { 0x80 } else { 0 },
-            0xff6b => {
-                let paletten = (self.cgb_sppal_addr >> 3) as usize;
-                let colorn = 0x03 & (self.cgb_sppal_addr >> 1) as usize;
-
-                let color = self.cgb_sppal[paletten][colorn];
-                if self.cgb_sppal_addr & 1 == 0 {
-                    // even
-                    color.r | ((color.g & 0x07) << 5)
-                } else {
-                    // odd
-                    ((color.g & 0x18) >> 3) | (color.b << 2)
-                }
-            }
+            0xff6b => palette_data(&self.cgb_sppal, self.cgb_sppal_addr),
+            0xff6c => match self.gb_mode {
+                GbMode::Cgb => 0xFE | u8::from(!self.oam_priority),
+                GbMode::Dmg => 0xFF,
+            },
             _ => panic!("Invalid GPU read address: 0x{:04X}", addr),
         }
     }
     fn mem_write(&mut self, addr: u16, value: u8) {
         match addr {
+            0x8000..=0x9FFF if self.vram_locked() => (),
+            0xFE00..=0xFE9F if self.oam_locked() => (),
+
             0x8000..=0x9FFF => {
                 self.vram[(self.vram_bank * 0x2000) | (addr & 0x1FFF) as usize] = value
             }
-            0xFE00..=0xFE9F => {
-                let addr = (addr - 0xFE00) as usize;
-                self.oam[addr] = value;
-                sprites::update_sprites(&mut self.sprites, addr, value);
-            }
+            0xFE00..=0xFE9F => self.write_oam(addr, value),
 
             0xFF40 => {
                 let lcd_was_enable = self.lcd_control.lcd_on();
@@ -521,15 +508,34 @@ impl MemoryAccess for Gpu {
                     self.clocks = 0;
                     self.scan_line = 0;
                     self.clear_screen();
+                } else if !lcd_was_enable && self.lcd_control.lcd_on() {
+                    self.mode = GpuMode::OAMSearch;
+                    self.start_frame();
+                    self.check_wy();
                 }
+                self.update_stat_line();
+            }
+            0xFF41 => {
+                // on DMG the write enables every source for a cycle, an interrupt is
+                // requested if any is active
+                if self.gb_mode == GbMode::Dmg
+                    && !self.stat_line
+                    && self.stat_sources(LCDStatus::all())
+                {
+                    self.interrupt |= 0x02;
+                }
+                self.lcd_status = LCDStatus::from_bits_truncate(value);
+                self.update_stat_line();
             }
-            0xFF41 => self.lcd_status = LCDStatus::from_bits_truncate(value),
 
             0xFF42 => self.scroll_y = value,
             0xFF43 => self.scroll_x = value,
 
             0xFF44 => (), // READ-ONLY
-            0xFF45 => self.scan_line_check = value,
+            0xFF45 => {
+                self.scan_line_check = value;
+                self.update_stat_line();
+            }
 
             0xFF47 => {
                 self.bg_palette = value;
@@ -554,17 +560,20 @@ impl MemoryAccess for Gpu {
                 self.cgb_bgpal_auto_inc = value & 0x80 == 0x80;
             }
             0xff69 => {
-                let paletten = (self.cgb_bgpal_addr >> 3) as usize;
-                let colorn = 0x03 & (self.cgb_bgpal_addr >> 1) as usize;
+                // mode 3 drops the data, but the index is still incremented
+                if !self.vram_locked() {
+                    let paletten = (self.cgb_bgpal_addr >> 3) as usize;
+                    let colorn = 0x03 & (self.cgb_bgpal_addr >> 1) as usize;
 
-                let color = &mut self.cgb_bgpal[paletten][colorn];
+                    let color = &mut self.cgb_bgpal[paletten][colorn];
 
-                if self.cgb_bgpal_addr & 1 == 0 {
-                    color.r = value & 0x1F;
-                    color.g = (color.g & 0x18) | (value >> 5);
-                } else {
-                    color.g = (color.g & 0x07) | ((value & 0x03) << 3);
-                    color.b = (value >> 2) & 0x1F;
+                    if self.cgb_bgpal_addr & 1 == 0 {
+                        color.r = value & 0x1F;
+                        color.g = (color.g & 0x18) | (value >> 5);
+                    } else {
+                        color.g = (color.g & 0x07) | ((value & 0x03) << 3);
+                        color.b = (value >> 2) & 0x1F;
+                    }
                 }
 
                 self.cgb_bgpal_auto_inc
@@ -575,24 +584,157 @@ impl MemoryAccess for Gpu {
                 self.cgb_sppal_auto_inc = value >> 7 == 1;
             }
             0xff6b => {
-                let paletten = (self.cgb_sppal_addr >> 3) as usize;
-                let colorn = 0x03 & (self.cgb_sppal_addr >> 1) as usize;
+                // mode 3 drops the data, but the index is still incremented
+                if !self.vram_locked() {
+                    let paletten = (self.cgb_sppal_addr >> 3) as usize;
+                    let colorn = 0x03 & (self.cgb_sppal_addr >> 1) as usize;
 
-                let color = &mut self.cgb_sppal[paletten][colorn];
+                    let color = &mut self.cgb_sppal[paletten][colorn];
 
-                if self.cgb_sppal_addr & 1 == 0 {
-                    color.r = value & 0x1F;
-                    color.g = (color.g & 0x18) | (value >> 5);
-                } else {
-                    color.g = (color.g & 0x07) | ((value & 0x03) << 3);
-                    color.b = (value >> 2) & 0x1F;
+                    if self.cgb_sppal_addr & 1 == 0 {
+                        color.r = value & 0x1F;
+                        color.g = (color.g & 0x18) | (value >> 5);
+                    } else {
+                        color.g = (color.g & 0x07) | ((value & 0x03) << 3);
+                        color.b = (value >> 2) & 0x1F;
+                    }
                 }
 
                 self.cgb_sppal_auto_inc
                     .then(|| self.cgb_sppal_addr = (self.cgb_sppal_addr + 1) & 0x3F);
             }
+            0xff6c if self.gb_mode == GbMode::Cgb => self.oam_priority = value & 0x01 == 0,
+            0xff6c => (),
 
             _ => panic!("Invalid GPU write address: 0x{:04X}", addr),
         }
     }
 }
+
+/// A DMG PPU whose BGP and OBP0 map the color n to the shade n
+#[cfg(test)]
+pub(crate) fn dmg_gpu() -> Gpu {
+    let mut gpu = Gpu::new(GbMode::Dmg);
+    gpu.mem_write(0xFF47, 0b11_10_01_00);
+    gpu.mem_write(0xFF48, 0b11_10_01_00);
+    gpu
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The lines and dots at which the LCD interrupt was requested, for `lines` lines
+    fn stat_interrupts(gpu: &mut Gpu, lines: u32) -> Vec<(u8, u32)> {
+        gpu.interrupt = 0;
+        let mut interrupts = vec![];
+        for _ in 0..lines * LINE_DOTS {
+            gpu.sync(1);
+            if gpu.interrupt & 0x02 != 0 {
+                interrupts.push((gpu.scan_line, gpu.clocks));
+            }
+            gpu.interrupt = 0;
+        }
+        interrupts
+    }
+
+    #[test]
+    fn stat_rising_edge() {
+        let mut gpu = Gpu::new(GbMode::Cgb);
+        gpu.mem_write(0xFF40, 0x91);
+        // HBlank and LY=LYC sources
+        gpu.mem_write(0xFF41, 0x48);
+        gpu.mem_write(0xFF45, 1);
+
+        // the HBlank of line 1 is blocked by the LY=LYC source, already high
+        let hblank = OAM_SEARCH_DOTS + 172;
+        assert_eq!(
+            stat_interrupts(&mut gpu, 3),
+            [(0, hblank), (1, 4), (2, hblank)]
+        );
+
+        // HBlank starts later with the fine scroll
+        let mut gpu = Gpu::new(GbMode::Cgb);
+        gpu.mem_write(0xFF40, 0x91);
+        gpu.mem_write(0xFF41, 0x08);
+        gpu.mem_write(0xFF43, 5);
+        assert_eq!(stat_interrupts(&mut gpu, 1), [(0, hblank + 5)]);
+    }
+
+    #[test]
+    fn line_153() {
+        let mut gpu = Gpu::new(GbMode::Cgb);
+        gpu.mem_write(0xFF40, 0x91);
+        gpu.mem_write(0xFF41, 0x40);
+        gpu.mem_write(0xFF45, 153);
+        gpu.sync(153 * LINE_DOTS);
+
+        assert_eq!(gpu.mem_read(0xFF44), 153);
+        assert_eq!(stat_interrupts(&mut gpu, 1), [(153, 4)]);
+        assert_eq!(gpu.mem_read(0xFF44), 0);
+
+        // LY=0 matches during line 153 and stays high on line 0
+        gpu.mem_write(0xFF45, 0);
+        gpu.sync(153 * LINE_DOTS);
+        assert_eq!(stat_interrupts(&mut gpu, 2), [(153, 12)]);
+        assert_eq!(gpu.mem_read(0xFF41) & 0x04, 0);
+    }
+
+    #[test]
+    fn vram_oam_lock() {
+        let mut gpu = Gpu::new(GbMode::Cgb);
+        gpu.mem_write(0x8000, 0x11);
+        gpu.mem_write(0xFE00, 0x22);
+        gpu.mem_write(0xFF68, 0x00);
+        gpu.mem_write(0xFF69, 0x33);
+        gpu.mem_write(0xFF40, 0x91);
+
+        // mode 2 locks OAM
+        assert_eq!(gpu.mem_read(0xFE00), 0xFF);
+        gpu.mem_write(0xFE00, 0x44);
+        assert_eq!(gpu.mem_read(0x8000), 0x11);
+
+        // mode 3 locks VRAM and the palette data too
+        gpu.sync(OAM_SEARCH_DOTS);
+        assert_eq!(gpu.mem_read(0xFF41) & 0x03, 3);
+        assert_eq!(gpu.mem_read(0x8000), 0xFF);
+        assert_eq!(gpu.mem_read(0xFF69), 0xFF);
+        gpu.mem_write(0x8000, 0x55);
+        gpu.mem_write(0xFF69, 0x66);
+        // but not for the debuggers
+        assert_eq!(gpu.peek(0x8000), 0x11);
+        assert_eq!(gpu.peek(0xFE00), 0x22);
+        assert_eq!(gpu.peek(0xFF69), 0x33);
+
+        gpu.sync(LINE_DOTS - OAM_SEARCH_DOTS - 1);
+        assert_eq!(gpu.mem_read(0xFF41) & 0x03, 0);
+        assert_eq!(gpu.mem_read(0x8000), 0x11);
+        assert_eq!(gpu.mem_read(0xFE00), 0x22);
+        assert_eq!(gpu.mem_read(0xFF69), 0x33);
+    }
+
+    #[test]
+    fn palette_index_in_mode_3() {
+        let mut gpu = Gpu::new(GbMode::Cgb);
+        gpu.mem_write(0xFF68, 0x80);
+        gpu.mem_write(0xFF69, 0x33);
+        gpu.mem_write(0xFF68, 0x80);
+        gpu.mem_write(0xFF6A, 0x80);
+        gpu.mem_write(0xFF6B, 0x44);
+        gpu.mem_write(0xFF6A, 0x80);
+        gpu.mem_write(0xFF40, 0x91);
+
+        // the data writes are dropped, but BCPS and OCPS are incremented
+        gpu.sync(OAM_SEARCH_DOTS);
+        gpu.mem_write(0xFF69, 0x66);
+        gpu.mem_write(0xFF6B, 0x77);
+        assert_eq!(gpu.mem_read(0xFF68), 0x81);
+        assert_eq!(gpu.mem_read(0xFF6A), 0x81);
+
+        gpu.sync(LINE_DOTS - OAM_SEARCH_DOTS - 1);
+        gpu.mem_write(0xFF68, 0x00);
+        gpu.mem_write(0xFF6A, 0x00);
+        assert_eq!(gpu.mem_read(0xFF69), 0x33);
+        assert_eq!(gpu.mem_read(0xFF6B), 0x44);
+    }
+}
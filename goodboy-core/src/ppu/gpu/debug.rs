@@ -0,0 +1,320 @@
+use crate::{
+    gb_mode::GbMode,
+    ppu::{palette::PaletteKind, Color, Sprite},
+};
+
+use super::Gpu;
+
+/// Tiles per row in the tile sheet, a VRAM bank has 384 tiles so 24 rows
+const SHEET_TILES_PER_ROW: usize = 16;
+const SHEET_TILE_ROWS: usize = 24;
+
+/// The outline of the scroll viewport drawn over the tilemaps
+const VIEWPORT_COLOR: [u8; 4] = [0xFF, 0x00, 0x00, 0xFF];
+
+/// An RGBA image of the PPU state, in the same layout as the screen buffer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl DebugImage {
+    fn new(width: usize, height: usize) -> Self {
+        DebugImage {
+            width,
+            height,
+            pixels: vec![0; width * height * 4],
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        let i = (y * self.width + x) * 4;
+        [
+            self.pixels[i],
+            self.pixels[i + 1],
+            self.pixels[i + 2],
+            self.pixels[i + 3],
+        ]
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, rgba: [u8; 4]) {
+        let i = (y * self.width + x) * 4;
+        self.pixels[i..i + 4].copy_from_slice(&rgba);
+    }
+}
+
+/// One of the 40 OAM entries, with its tile drawn as it appears on screen
+#[derive(Debug, Clone)]
+pub struct OamSprite {
+    pub index: usize,
+    pub sprite: Sprite,
+    /// 8x8 or 8x16 depending on LCDC, the transparent pixels have a zero alpha
+    pub image: DebugImage,
+}
+
+/// The colors of every palette, as RGBA
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugPalettes {
+    /// BGP, OBP0 and OBP1 in the current color scheme
+    pub dmg: [[[u8; 4]; 4]; 3],
+    pub cgb_bg: [[[u8; 4]; 4]; 8],
+    pub cgb_obj: [[[u8; 4]; 4]; 8],
+}
+
+impl Gpu {
+    /// Every tile of both VRAM banks, 16x24 tiles per bank with bank 1 on the right,
+    /// drawn with the background palette (BGP, or the CGB palette 0)
+    pub fn tile_sheet(&self) -> DebugImage {
+        let bank_width = SHEET_TILES_PER_ROW * 8;
+        let mut image = DebugImage::new(bank_width * 2, SHEET_TILE_ROWS * 8);
+        let palette = self.bg_palette_colors(0);
+
+        for bank in 0..2 {
+            for tile in 0..SHEET_TILES_PER_ROW * SHEET_TILE_ROWS {
+                let (tile_x, tile_y) = (tile % SHEET_TILES_PER_ROW, tile / SHEET_TILES_PER_ROW);
+
+                for row in 0..8 {
+                    let colors = self.tile_row(bank, tile * 16, row);
+                    for (x, &colorn) in colors.iter().enumerate() {
+                        image.set_pixel(
+                            bank * bank_width + tile_x * 8 + x,
+                            tile_y * 8 + row,
+                            palette[colorn],
+                        );
+                    }
+                }
+            }
+        }
+
+        image
+    }
+
+    /// The 32x32 tiles of a tilemap (0 at 0x9800, 1 at 0x9C00), with the tile data
+    /// and CGB attributes currently selected, and the SCX/SCY viewport outlined
+    pub fn tilemap(&self, map: usize) -> DebugImage {
+        let mut image = DebugImage::new(256, 256);
+        let map_base = if map == 0 { 0x1800 } else { 0x1C00 };
+
+        for tile_y in 0..32 {
+            for tile_x in 0..32 {
+                let map_addr = map_base + tile_y * 32 + tile_x;
+                let tile_nr = self.vram[map_addr];
+                let tile_addr = match self.lcd_control.tileset_base() {
+                    0x8000 => tile_nr as usize * 16,
+                    _ => 0x1000usize.wrapping_add_signed(tile_nr as i8 as isize * 16),
+                };
+
+                let (paletten, bank, xflip, yflip) = if self.gb_mode == GbMode::Cgb {
+                    let f = self.vram[0x2000 + map_addr];
+                    (
+                        (f & 0x07) as usize,
+                        ((f & 0x08) == 0x08) as usize,
+                        (f & 0x20) == 0x20,
+                        (f & 0x40) == 0x40,
+                    )
+                } else {
+                    (0, 0, false, false)
+                };
+                let palette = self.bg_palette_colors(paletten);
+
+                for row in 0..8 {
+                    let colors = self.tile_row(bank, tile_addr, if yflip { 7 - row } else { row });
+                    for x in 0..8 {
+                        let colorn = colors[if xflip { 7 - x } else { x }];
+                        image.set_pixel(tile_x * 8 + x, tile_y * 8 + row, palette[colorn]);
+                    }
+                }
+            }
+        }
+
+        let (scx, scy) = (self.scroll_x as usize, self.scroll_y as usize);
+        for x in 0..160 {
+            image.set_pixel((scx + x) % 256, scy, VIEWPORT_COLOR);
+            image.set_pixel((scx + x) % 256, (scy + 143) % 256, VIEWPORT_COLOR);
+        }
+        for y in 0..144 {
+            image.set_pixel(scx, (scy + y) % 256, VIEWPORT_COLOR);
+            image.set_pixel((scx + 159) % 256, (scy + y) % 256, VIEWPORT_COLOR);
+        }
+
+        image
+    }
+
+    /// The 40 OAM entries in OAM order
+    pub fn oam_sprites(&self) -> Vec<OamSprite> {
+        let height = self.lcd_control.sprite_size() as usize;
+
+        self.sprites
+            .iter()
+            .enumerate()
+            .map(|(index, sprite)| {
+                let mut image = DebugImage::new(8, height);
+                let tile_number =
+                    sprite.tile_number as usize & if height == 16 { 0xFE } else { 0xFF };
+                let bank = if self.gb_mode == GbMode::Cgb {
+                    sprite.vram_bank
+                } else {
+                    0
+                };
+                let palette = self.obj_palette_colors(sprite);
+
+                for y in 0..height {
+                    let row = if sprite.flip_y { height - 1 - y } else { y };
+                    let colors = self.tile_row(bank, tile_number * 16 + (row / 8) * 16, row % 8);
+
+                    for x in 0..8 {
+                        let colorn = colors[if sprite.flip_x { 7 - x } else { x }];
+                        if colorn != 0 {
+                            image.set_pixel(x, y, palette[colorn]);
+                        }
+                    }
+                }
+
+                OamSprite {
+                    index,
+                    sprite: *sprite,
+                    image,
+                }
+            })
+            .collect()
+    }
+
+    pub fn debug_palettes(&self) -> DebugPalettes {
+        let dmg = [PaletteKind::BG, PaletteKind::OBJ0, PaletteKind::OBJ1].map(|kind| {
+            self.palettes
+                .get(kind)
+                .map(|(color, _)| color.into_rgba_slice(self.color_correction))
+        });
+        let cgb = |palettes: &[[_; 4]; 8]| {
+            palettes.map(|palette: [_; 4]| {
+                palette.map(|c| Color::new_rgb555(c).into_rgba_slice(self.color_correction))
+            })
+        };
+
+        DebugPalettes {
+            dmg,
+            cgb_bg: cgb(&self.cgb_bgpal),
+            cgb_obj: cgb(&self.cgb_sppal),
+        }
+    }
+
+    /// The palettes as 8x8 swatches, a palette per row: BGP, OBP0 and OBP1, then the
+    /// 8 CGB background palettes and the 8 CGB object palettes
+    pub fn palette_sheet(&self) -> DebugImage {
+        let palettes = self.debug_palettes();
+        let rows = palettes
+            .dmg
+            .iter()
+            .chain(&palettes.cgb_bg)
+            .chain(&palettes.cgb_obj)
+            .collect::<Vec<_>>();
+
+        let mut image = DebugImage::new(4 * 8, rows.len() * 8);
+        for (row, palette) in rows.iter().enumerate() {
+            for (i, &rgba) in palette.iter().enumerate() {
+                for y in 0..8 {
+                    for x in 0..8 {
+                        image.set_pixel(i * 8 + x, row * 8 + y, rgba);
+                    }
+                }
+            }
+        }
+
+        image
+    }
+
+    /// The color indexes of a tile row, `tile_addr` is relative to 0x8000
+    fn tile_row(&self, bank: usize, tile_addr: usize, row: usize) -> [usize; 8] {
+        let addr = bank * 0x2000 + tile_addr + row * 2;
+        let (b1, b2) = (self.vram[addr], self.vram[addr + 1]);
+
+        [0, 1, 2, 3, 4, 5, 6, 7].map(|x| {
+            let bit = 7 - x;
+            (((b2 >> bit) & 1) << 1 | ((b1 >> bit) & 1)) as usize
+        })
+    }
+
+    fn bg_palette_colors(&self, paletten: usize) -> [[u8; 4]; 4] {
+        match self.gb_mode {
+            GbMode::Dmg => self
+                .palettes
+                .get(PaletteKind::BG)
+                .map(|(color, _)| color.into_rgba_slice(self.color_correction)),
+            GbMode::Cgb => self.cgb_bgpal[paletten]
+                .map(|c| Color::new_rgb555(c).into_rgba_slice(self.color_correction)),
+        }
+    }
+
+    fn obj_palette_colors(&self, sprite: &Sprite) -> [[u8; 4]; 4] {
+        match self.gb_mode {
+            GbMode::Dmg => {
+                let kind = if sprite.palette {
+                    PaletteKind::OBJ1
+                } else {
+                    PaletteKind::OBJ0
+                };
+                self.palettes
+                    .get(kind)
+                    .map(|(color, _)| color.into_rgba_slice(self.color_correction))
+            }
+            GbMode::Cgb => self.cgb_sppal[sprite.paletten]
+                .map(|c| Color::new_rgb555(c).into_rgba_slice(self.color_correction)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mmu::MemoryAccess, ppu::gpu::dmg_gpu};
+
+    #[test]
+    fn tile_sheet_and_tilemap() {
+        let mut gpu = dmg_gpu();
+        // tile 1, first row: color 1 then color 2 then color 3
+        gpu.mem_write(0x8010, 0b1010_0000);
+        gpu.mem_write(0x8011, 0b0110_0000);
+        gpu.mem_write(0xFF40, 0x10);
+        gpu.mem_write(0x9800 + 33, 1);
+        gpu.mem_write(0xFF42, 200);
+        gpu.mem_write(0xFF43, 120);
+
+        let shades = gpu.debug_palettes().dmg[0];
+
+        let sheet = gpu.tile_sheet();
+        assert_eq!((sheet.width, sheet.height), (256, 192));
+        assert_eq!(sheet.pixel(8, 0), shades[1]);
+        assert_eq!(sheet.pixel(9, 0), shades[2]);
+        assert_eq!(sheet.pixel(10, 0), shades[3]);
+        assert_eq!(sheet.pixel(11, 0), shades[0]);
+
+        let map = gpu.tilemap(0);
+        assert_eq!(map.pixel(9, 8), shades[2]);
+        // the viewport wraps around the map
+        assert_eq!(map.pixel(120, 10), VIEWPORT_COLOR);
+        assert_eq!(map.pixel((120 + 159) % 256, 0), VIEWPORT_COLOR);
+        assert_eq!(map.pixel(121, 101), shades[0]);
+    }
+
+    #[test]
+    fn oam_sprites() {
+        let mut gpu = dmg_gpu();
+        gpu.mem_write(0x8020, 0xFF);
+        gpu.mem_write(0xFE04, 16 + 10);
+        gpu.mem_write(0xFE05, 8 + 20);
+        gpu.mem_write(0xFE06, 2);
+        gpu.mem_write(0xFE07, 0x40);
+
+        let sprites = gpu.oam_sprites();
+        assert_eq!(sprites.len(), 40);
+
+        let sprite = &sprites[1];
+        assert_eq!((sprite.sprite.x, sprite.sprite.y), (20, 10));
+        assert!(sprite.sprite.flip_y);
+        // the first row of the tile ends up at the bottom
+        assert_eq!(sprite.image.pixel(0, 7), gpu.debug_palettes().dmg[1][1]);
+        assert_eq!(sprite.image.pixel(0, 0)[3], 0);
+    }
+}
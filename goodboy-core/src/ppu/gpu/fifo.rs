@@ -0,0 +1,762 @@
+use crate::{
+    gb_mode::GbMode,
+    ppu::{palette::PaletteKind, Color},
+    vm::SCREEN_WIDTH,
+};
+
+use super::{
+    layers::{Layer, Layers, PixelInfo},
+    Gpu,
+};
+
+/// The first fetch of a line is done twice, the first one is thrown away
+const FIRST_FETCH_DOTS: u8 = 6;
+
+/// A sprite fetch stalls the pipeline for 6 dots, plus the end of the background fetch
+/// when it is the first sprite of a tile
+const SPRITE_FETCH_DOTS: u8 = 6;
+
+/// The sprites drawn on a single line
+const MAX_LINE_SPRITES: usize = 10;
+
+/// The hidden pixels of a window at WX=0, the window starts earlier with the fine
+/// scroll of SCX
+const WX0_HIDDEN_PIXELS: [u8; 8] = [7, 9, 10, 11, 12, 13, 14, 14];
+
+/// A background or window pixel
+#[derive(Debug, Clone, Copy, Default)]
+struct BgPixel {
+    color: u8,
+    /// The CGB palette
+    palette: u8,
+    /// The CGB attribute priority, over the sprites
+    priority: bool,
+    window: bool,
+}
+
+/// A sprite pixel, the color 0 is transparent
+#[derive(Debug, Clone, Copy, Default)]
+struct ObjPixel {
+    color: u8,
+    /// OBP0/OBP1 on DMG, the palette number on CGB
+    palette: u8,
+    /// Behind the background colors 1-3
+    behind_bg: bool,
+    oam_index: u8,
+}
+
+/// 8 pixels shifted out one by one
+#[derive(Debug, Clone, Copy, Default)]
+struct Fifo<T> {
+    pixels: [T; 8],
+    head: usize,
+    len: usize,
+}
+
+impl<T: Copy + Default> Fifo<T> {
+    fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn push(&mut self, pixel: T) {
+        debug_assert!(self.len < 8, "Pushing to a full FIFO");
+        self.pixels[(self.head + self.len) % 8] = pixel;
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let pixel = self.pixels[self.head];
+        self.head = (self.head + 1) % 8;
+        self.len -= 1;
+        Some(pixel)
+    }
+
+    fn get_mut(&mut self, i: usize) -> &mut T {
+        &mut self.pixels[(self.head + i) % 8]
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum FetcherStep {
+    #[default]
+    Tile,
+    DataLow,
+    DataHigh,
+    /// Wait for the background FIFO to be empty
+    Push,
+}
+
+/// Fetch the background and window tiles, a tile row every 6 dots
+#[derive(Debug, Clone, Copy, Default)]
+struct Fetcher {
+    step: FetcherStep,
+    /// Dots spent in the current step, the reads take 2 dots
+    dots: u8,
+    /// The tile column, relative to SCX or to the start of the window
+    tile_x: u8,
+    window: bool,
+
+    tile_nr: u8,
+    attributes: u8,
+    row: u16,
+    low: u8,
+    high: u8,
+}
+
+/// The state of the pixel transfer (mode 3)
+#[derive(Debug, Clone, Default)]
+pub(super) struct PixelFifo {
+    bg: Fifo<BgPixel>,
+    obj: Fifo<ObjPixel>,
+    fetcher: Fetcher,
+
+    /// The next pixel of the line
+    lx: u8,
+    /// The pixels still to drop, the fine scroll of SCX or of the window
+    discard: u8,
+    /// Dots the pixel pipeline waits, for the first fetch or a sprite fetch
+    stall: u8,
+
+    /// The OAM index of the sprites on the line, sorted by X
+    sprites: [u8; MAX_LINE_SPRITES],
+    sprite_count: usize,
+    next_sprite: usize,
+    /// The last tile that delayed a sprite fetch
+    penalty_tile: Option<(bool, u8)>,
+}
+
+impl PixelFifo {
+    /// The window was drawn on the line
+    pub(super) fn window_drawn(&self) -> bool {
+        self.fetcher.window
+    }
+}
+
+impl Gpu {
+    /// The OAM scan of mode 2: the first 10 sprites of OAM on the line, whatever their
+    /// X, are fetched from left to right. OAM is locked during the scan, so it is done
+    /// at once when the scan ends.
+    pub(super) fn scan_oam(&mut self) {
+        let fifo = &mut self.fifo;
+        let height = self.lcd_control.sprite_size() as i32;
+        let line = i32::from(self.scan_line);
+        let mut count = 0;
+        for (i, sprite) in self.sprites.iter().enumerate() {
+            if count == MAX_LINE_SPRITES {
+                break;
+            }
+            if (sprite.y..sprite.y + height).contains(&line) {
+                fifo.sprites[count] = i as u8;
+                count += 1;
+            }
+        }
+
+        // the sort is stable, the sprites at the same X stay in OAM order
+        let sprites = &self.sprites;
+        fifo.sprites[..count].sort_by_key(|&i| sprites[i as usize].x);
+        fifo.sprite_count = count;
+    }
+
+    /// Start fetching the background and the sprites selected by the OAM scan
+    pub(super) fn start_pixel_transfer(&mut self) {
+        let fifo = &mut self.fifo;
+        fifo.bg.clear();
+        fifo.obj.clear();
+        fifo.fetcher = Fetcher::default();
+        fifo.lx = 0;
+        fifo.discard = self.scroll_x & 0x07;
+        fifo.stall = FIRST_FETCH_DOTS;
+        fifo.penalty_tile = None;
+        fifo.next_sprite = 0;
+    }
+
+    /// Run the pixel transfer for a dot, returns true when the line is drawn
+    pub(super) fn pixel_transfer_dot(&mut self) -> bool {
+        if self.fifo.stall > 0 {
+            self.fifo.stall -= 1;
+            return false;
+        }
+
+        self.check_window();
+
+        if self.fifo.discard == 0 && self.lcd_control.sprites_on() {
+            while let Some(index) = self.next_line_sprite() {
+                self.fifo.stall += self.sprite_penalty();
+                self.fetch_sprite(index);
+                self.fifo.next_sprite += 1;
+            }
+            if self.fifo.stall > 0 {
+                self.fifo.stall -= 1;
+                return false;
+            }
+        }
+
+        self.step_fetcher();
+        self.shift_pixel();
+
+        usize::from(self.fifo.lx) == SCREEN_WIDTH
+    }
+
+    /// Restart the fetcher on the window when the line reaches WX, once WY was reached
+    fn check_window(&mut self) {
+        let window_on = self.lcd_control.win_on()
+            && (self.gb_mode == GbMode::Cgb || self.lcd_control.bg_enabled());
+        if self.fifo.fetcher.window || !window_on || !self.wy_triggered {
+            return;
+        }
+
+        // the pixels of the window left of the screen
+        let hidden = match self.win_x {
+            _ if self.wx166_glitch => 7,
+            0 => WX0_HIDDEN_PIXELS[usize::from(self.scroll_x & 0x07)],
+            166 if self.gb_mode == GbMode::Dmg => return,
+            wx if u16::from(self.fifo.lx) + 7 >= u16::from(wx) => 7u8.saturating_sub(wx),
+            _ => return,
+        };
+
+        let fifo = &mut self.fifo;
+        fifo.bg.clear();
+        fifo.fetcher = Fetcher {
+            window: true,
+            ..Default::default()
+        };
+        fifo.discard = hidden;
+    }
+
+    /// The next sprite reached by the line
+    fn next_line_sprite(&self) -> Option<usize> {
+        let fifo = &self.fifo;
+        if fifo.next_sprite == fifo.sprite_count {
+            return None;
+        }
+
+        let index = usize::from(fifo.sprites[fifo.next_sprite]);
+        (self.sprites[index].x <= i32::from(fifo.lx)).then_some(index)
+    }
+
+    /// The dots waited for a sprite fetch at the current pixel: the fetch itself, and
+    /// the end of the background tile fetch for the first sprite of a tile
+    fn sprite_penalty(&mut self) -> u8 {
+        let fifo = &mut self.fifo;
+        let x = if fifo.fetcher.window {
+            fifo.lx.wrapping_sub(self.win_x.wrapping_sub(7))
+        } else {
+            fifo.lx.wrapping_add(self.scroll_x)
+        };
+
+        let tile = (fifo.fetcher.window, x / 8);
+        if fifo.penalty_tile == Some(tile) {
+            return SPRITE_FETCH_DOTS;
+        }
+        fifo.penalty_tile = Some(tile);
+        SPRITE_FETCH_DOTS + 5u8.saturating_sub(x % 8)
+    }
+
+    /// Mix the row of a sprite with the sprite FIFO, the pixels left of the line are dropped
+    fn fetch_sprite(&mut self, index: usize) {
+        let sprite = self.sprites[index];
+        let height = self.lcd_control.sprite_size() as i32;
+
+        let row = i32::from(self.scan_line) - sprite.y;
+        let row = if sprite.flip_y { height - 1 - row } else { row } as usize;
+        let tile_number = sprite.tile_number as usize & if height == 16 { 0xFE } else { 0xFF };
+        let bank = match self.gb_mode {
+            GbMode::Cgb => sprite.vram_bank,
+            GbMode::Dmg => 0,
+        };
+        let addr = bank * 0x2000 + tile_number * 16 + row * 2;
+        let (low, high) = (self.vram[addr], self.vram[addr + 1]);
+
+        let palette = match self.gb_mode {
+            GbMode::Cgb => sprite.paletten as u8,
+            GbMode::Dmg => sprite.palette as u8,
+        };
+
+        let fifo = &mut self.fifo;
+        let first = i32::from(fifo.lx) - sprite.x;
+        for x in first.max(0)..8 {
+            let bit = if sprite.flip_x { x } else { 7 - x };
+            let pixel = ObjPixel {
+                color: ((high >> bit) & 1) << 1 | ((low >> bit) & 1),
+                palette,
+                behind_bg: sprite.priority,
+                oam_index: index as u8,
+            };
+
+            let slot = (x - first) as usize;
+            while fifo.obj.len <= slot {
+                fifo.obj.push(ObjPixel::default());
+            }
+
+            // by X priority the sprite fetched first, the leftmost, is drawn over the
+            // others, by OAM priority the first in OAM
+            let old = fifo.obj.get_mut(slot);
+            let replace = old.color == 0
+                || (self.oam_priority && pixel.color != 0 && pixel.oam_index < old.oam_index);
+            if replace {
+                *old = pixel;
+            }
+        }
+    }
+
+    fn step_fetcher(&mut self) {
+        let fetcher = &mut self.fifo.fetcher;
+
+        if fetcher.step == FetcherStep::Push {
+            // the row is pushed as soon as the FIFO is empty
+            if self.fifo.bg.is_empty() {
+                self.push_tile_row();
+            }
+            return;
+        }
+
+        fetcher.dots += 1;
+        if fetcher.dots < 2 {
+            return;
+        }
+        fetcher.dots = 0;
+
+        match fetcher.step {
+            FetcherStep::Tile => self.fetch_tile(),
+            FetcherStep::DataLow => {
+                self.fifo.fetcher.low = self.vram[self.tile_data_addr()];
+                self.fifo.fetcher.step = FetcherStep::DataHigh;
+            }
+            FetcherStep::DataHigh => {
+                self.fifo.fetcher.high = self.vram[self.tile_data_addr() + 1];
+                self.fifo.fetcher.step = FetcherStep::Push;
+            }
+            FetcherStep::Push => unreachable!(),
+        }
+    }
+
+    /// Read the tile number, and the CGB attributes, from the tilemap
+    fn fetch_tile(&mut self) {
+        let (tilemap, tile_x, y) = if self.fifo.fetcher.window {
+            let y = self.window_line;
+            (self.lcd_control.win_tilemap(), self.fifo.fetcher.tile_x, y)
+        } else {
+            let tile_x = (self.scroll_x >> 3).wrapping_add(self.fifo.fetcher.tile_x);
+            let y = self.scan_line.wrapping_add(self.scroll_y);
+            (self.lcd_control.bg_tilemap(), tile_x, y)
+        };
+
+        let map_addr =
+            (tilemap as usize & 0x1FFF) + usize::from(y >> 3) * 32 + usize::from(tile_x & 31);
+        let attributes = match self.gb_mode {
+            GbMode::Cgb => self.vram[0x2000 + map_addr],
+            GbMode::Dmg => 0,
+        };
+        let row = u16::from(y & 0x07);
+
+        let fetcher = &mut self.fifo.fetcher;
+        fetcher.tile_nr = self.vram[map_addr];
+        fetcher.attributes = attributes;
+        fetcher.row = if attributes & 0x40 != 0 { 7 - row } else { row };
+        fetcher.step = FetcherStep::DataLow;
+    }
+
+    /// The VRAM offset of the row fetched, in the tile data selected by LCDC
+    fn tile_data_addr(&self) -> usize {
+        let fetcher = &self.fifo.fetcher;
+        let tile = match self.lcd_control.tileset_base() {
+            0x8000 => usize::from(fetcher.tile_nr),
+            _ => (0x100 + isize::from(fetcher.tile_nr as i8)) as usize,
+        };
+        let bank = usize::from(fetcher.attributes & 0x08 != 0);
+
+        bank * 0x2000 + tile * 16 + usize::from(fetcher.row) * 2
+    }
+
+    fn push_tile_row(&mut self) {
+        let fifo = &mut self.fifo;
+        let fetcher = &mut fifo.fetcher;
+        let xflip = fetcher.attributes & 0x20 != 0;
+
+        for x in 0..8 {
+            let bit = if xflip { x } else { 7 - x };
+            fifo.bg.push(BgPixel {
+                color: ((fetcher.high >> bit) & 1) << 1 | ((fetcher.low >> bit) & 1),
+                palette: fetcher.attributes & 0x07,
+                priority: fetcher.attributes & 0x80 != 0,
+                window: fetcher.window,
+            });
+        }
+
+        fetcher.tile_x = fetcher.tile_x.wrapping_add(1);
+        fetcher.step = FetcherStep::Tile;
+    }
+
+    /// Draw the next pixel of the line, mixing the background and the sprites
+    fn shift_pixel(&mut self) {
+        let Some(bg) = self.fifo.bg.pop() else {
+            return;
+        };
+        if self.fifo.discard > 0 {
+            self.fifo.discard -= 1;
+            return;
+        }
+        let obj = self.fifo.obj.pop().unwrap_or_default();
+
+        let cgb = self.gb_mode == GbMode::Cgb;
+        let (bg_layer, bg_shown) = match bg.window {
+            false => (Layer::Background, self.layers.contains(Layers::BACKGROUND)),
+            true => (Layer::Window, self.layers.contains(Layers::WINDOW)),
+        };
+        // on DMG, LCDC bit 0 blanks the background and the window, on CGB it gives
+        // the priority to the sprites
+        let bg_color = if (!cgb && !self.lcd_control.bg_enabled()) || !bg_shown {
+            0
+        } else {
+            bg.color
+        };
+
+        let obj_visible = obj.color != 0
+            && self.lcd_control.sprites_on()
+            && self.layers.contains(Layers::SPRITES)
+            && (bg_color == 0
+                || (cgb && !self.lcd_control.bg_enabled())
+                || !(obj.behind_bg || (cgb && bg.priority)));
+
+        let color = match (cgb, obj_visible) {
+            (true, true) => {
+                Color::new_rgb555(self.cgb_sppal[usize::from(obj.palette)][usize::from(obj.color)])
+            }
+            (true, false) => {
+                Color::new_rgb555(self.cgb_bgpal[usize::from(bg.palette)][usize::from(bg_color)])
+            }
+            (false, true) => {
+                let kind = match obj.palette {
+                    0 => PaletteKind::OBJ0,
+                    _ => PaletteKind::OBJ1,
+                };
+                self.palettes.get(kind)[usize::from(obj.color)].0
+            }
+            (false, false) => self.palettes.get(PaletteKind::BG)[usize::from(bg_color)].0,
+        };
+
+        let info = match obj_visible {
+            true => PixelInfo {
+                layer: Layer::Sprites,
+                palette: obj.palette,
+                color: obj.color,
+            },
+            false => PixelInfo {
+                layer: bg_layer,
+                palette: bg.palette,
+                color: bg_color,
+            },
+        };
+        self.set_color(usize::from(self.fifo.lx), color, info);
+        self.fifo.lx += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mmu::MemoryAccess,
+        ppu::gpu::{dmg_gpu, LINE_DOTS, OAM_SEARCH_DOTS},
+    };
+
+    /// A DMG PPU with the LCD on and two tiles
+    fn tiles_gpu() -> Gpu {
+        let mut gpu = dmg_gpu();
+        // tile 1 is the color 1, tile 2 the color 3
+        for row in 0..8 {
+            gpu.mem_write(0x8010 + row * 2, 0xFF);
+            gpu.mem_write(0x8020 + row * 2, 0xFF);
+            gpu.mem_write(0x8021 + row * 2, 0xFF);
+        }
+        // LCD, sprites and background on, tile data at 0x8000
+        gpu.mem_write(0xFF40, 0x93);
+        gpu
+    }
+
+    fn add_sprite(gpu: &mut Gpu, index: u16, x: u8, attributes: u8) {
+        let addr = 0xFE00 + index * 4;
+        gpu.write_oam(addr, 16);
+        gpu.write_oam(addr + 1, x);
+        gpu.write_oam(addr + 2, 2);
+        gpu.write_oam(addr + 3, attributes);
+    }
+
+    /// The dots in mode 3 on the first line, the whole line is run
+    fn mode3_dots(gpu: &mut Gpu) -> u32 {
+        let mut dots = 0;
+        for _ in 0..LINE_DOTS {
+            gpu.sync(1);
+            if gpu.mem_read(0xFF41) & 0x03 == 3 {
+                dots += 1;
+            }
+        }
+        dots
+    }
+
+    fn shade_at(gpu: &Gpu, x: usize, y: usize) -> usize {
+        let i = (y * SCREEN_WIDTH + x) * 4;
+        let rgba = &gpu.screen_buffer[i..i + 4];
+        let palette = gpu.palettes.get(PaletteKind::BG);
+        (0..4)
+            .find(|&n| palette[n].0.into_rgba_slice(gpu.color_correction) == rgba)
+            .expect("Not a BGP color")
+    }
+
+    #[test]
+    fn mode3_length() {
+        assert_eq!(mode3_dots(&mut tiles_gpu()), 172);
+
+        let mut gpu = tiles_gpu();
+        gpu.mem_write(0xFF43, 3);
+        assert_eq!(mode3_dots(&mut gpu), 172 + 3);
+
+        let mut gpu = tiles_gpu();
+        add_sprite(&mut gpu, 0, 8, 0);
+        assert_eq!(mode3_dots(&mut gpu), 172 + 11);
+
+        // the second sprite of a tile doesn't wait for the background fetch
+        let mut gpu = tiles_gpu();
+        add_sprite(&mut gpu, 0, 8, 0);
+        add_sprite(&mut gpu, 1, 8, 0);
+        assert_eq!(mode3_dots(&mut gpu), 172 + 11 + 6);
+
+        let mut gpu = tiles_gpu();
+        gpu.mem_write(0xFF40, 0xB3);
+        gpu.mem_write(0xFF4B, 7 + 80);
+        assert_eq!(mode3_dots(&mut gpu), 172 + 6);
+    }
+
+    #[test]
+    fn scroll_and_sprite_priority() {
+        let mut gpu = tiles_gpu();
+        gpu.mem_write(0x9800, 1);
+        gpu.mem_write(0xFF43, 3);
+        // the color 3, behind the background colors 1-3, from x = 4 to 11
+        add_sprite(&mut gpu, 0, 8 + 4, 0x80);
+        mode3_dots(&mut gpu);
+
+        assert_eq!(shade_at(&gpu, 0, 0), 1);
+        assert_eq!(shade_at(&gpu, 4, 0), 1);
+        assert_eq!(shade_at(&gpu, 5, 0), 3);
+        assert_eq!(shade_at(&gpu, 11, 0), 3);
+        assert_eq!(shade_at(&gpu, 12, 0), 0);
+    }
+
+    #[test]
+    fn sprite_selection_and_priority() {
+        let line_gpu = |oam_priority: bool| {
+            let mut gpu = tiles_gpu();
+            gpu.oam_priority = oam_priority;
+            // OBP1 maps the color 3 to the shade 2
+            gpu.mem_write(0xFF49, 0b10_00_00_00);
+            // the first sprite of OAM is right of the second one
+            add_sprite(&mut gpu, 0, 8 + 4, 0x10);
+            add_sprite(&mut gpu, 1, 8 + 2, 0);
+            // the sprites off screen count in the 10 sprites of the line
+            for i in 2..10 {
+                add_sprite(&mut gpu, i, 0, 0);
+            }
+            add_sprite(&mut gpu, 10, 8 + 40, 0);
+            mode3_dots(&mut gpu);
+            gpu
+        };
+
+        // by X priority the leftmost sprite is drawn over the others
+        let gpu = line_gpu(false);
+        assert_eq!(shade_at(&gpu, 2, 0), 3);
+        assert_eq!(shade_at(&gpu, 4, 0), 3);
+        assert_eq!(shade_at(&gpu, 10, 0), 2);
+        assert_eq!(shade_at(&gpu, 40, 0), 0);
+
+        // by OAM priority the first sprite of OAM
+        let gpu = line_gpu(true);
+        assert_eq!(shade_at(&gpu, 2, 0), 3);
+        assert_eq!(shade_at(&gpu, 4, 0), 2);
+        assert_eq!(shade_at(&gpu, 40, 0), 0);
+    }
+
+    #[test]
+    fn opri() {
+        let mut gpu = Gpu::new(GbMode::Cgb);
+        assert_eq!(gpu.mem_read(0xFF6C), 0xFE);
+        gpu.mem_write(0xFF6C, 0x01);
+        assert_eq!(gpu.mem_read(0xFF6C), 0xFF);
+        assert!(!gpu.oam_priority);
+
+        let mut gpu = tiles_gpu();
+        gpu.mem_write(0xFF6C, 0x00);
+        assert_eq!(gpu.mem_read(0xFF6C), 0xFF);
+        assert!(!gpu.oam_priority);
+    }
+
+    #[test]
+    fn pixel_info_and_layers() {
+        let mut gpu = tiles_gpu();
+        gpu.set_pixel_info_enabled(true);
+        gpu.mem_write(0x9800, 1);
+        gpu.mem_write(0x9C00, 2);
+        // OBP1 maps the color 3 to the shade 1, the window from x = 80
+        add_sprite(&mut gpu, 0, 8 + 4, 0x10);
+        gpu.mem_write(0xFF49, 0b01_00_00_00);
+        gpu.mem_write(0xFF4B, 7 + 80);
+        gpu.mem_write(0xFF40, 0xF3);
+        gpu.sync(LINE_DOTS);
+
+        let info = |gpu: &Gpu, x: usize| gpu.pixel_info().unwrap()[x];
+        assert_eq!(
+            info(&gpu, 0),
+            PixelInfo {
+                layer: Layer::Background,
+                palette: 0,
+                color: 1
+            }
+        );
+        assert_eq!(
+            info(&gpu, 4),
+            PixelInfo {
+                layer: Layer::Sprites,
+                palette: 1,
+                color: 3
+            }
+        );
+        assert_eq!(shade_at(&gpu, 4, 0), 1);
+        assert_eq!(info(&gpu, 80).layer, Layer::Window);
+        assert_eq!(info(&gpu, 80).color, 3);
+
+        // the hidden layers draw their color 0
+        gpu.set_layers(Layers::BACKGROUND);
+        gpu.sync(LINE_DOTS);
+        assert_eq!(info(&gpu, 160 + 4).layer, Layer::Background);
+        assert_eq!(info(&gpu, 160 + 80).layer, Layer::Window);
+        assert_eq!(info(&gpu, 160 + 80).color, 0);
+        assert_eq!(shade_at(&gpu, 80, 1), 0);
+        assert_eq!(shade_at(&gpu, 0, 1), 1);
+
+        gpu.set_pixel_info_enabled(false);
+        assert!(gpu.pixel_info().is_none());
+    }
+
+    #[test]
+    fn mid_line_changes() {
+        let mut gpu = tiles_gpu();
+        for x in 0..16 {
+            gpu.mem_write(0x9800 + x, 1);
+        }
+
+        // SCX changes after 80 pixels, the next tile was already fetched
+        gpu.sync(OAM_SEARCH_DOTS + 12 + 80);
+        assert_eq!(gpu.fifo.lx, 80);
+        gpu.mem_write(0xFF43, 128);
+        gpu.sync(LINE_DOTS - OAM_SEARCH_DOTS - 12 - 80);
+
+        assert_eq!(shade_at(&gpu, 79, 0), 1);
+        assert_eq!(shade_at(&gpu, 87, 0), 1);
+        assert_eq!(shade_at(&gpu, 88, 0), 0);
+        assert_eq!(shade_at(&gpu, 127, 0), 0);
+        // the tilemap wraps around
+        assert_eq!(shade_at(&gpu, 128, 0), 1);
+
+        // the window covers the right half of the screen
+        let mut gpu = tiles_gpu();
+        gpu.mem_write(0x9C00, 1);
+        gpu.mem_write(0xFF40, 0xF3);
+        gpu.mem_write(0xFF4B, 7 + 80);
+        mode3_dots(&mut gpu);
+
+        assert_eq!(shade_at(&gpu, 79, 0), 0);
+        assert_eq!(shade_at(&gpu, 80, 0), 1);
+        assert_eq!(shade_at(&gpu, 87, 0), 1);
+        assert_eq!(shade_at(&gpu, 88, 0), 0);
+    }
+
+    #[test]
+    fn window_line_counter() {
+        let mut gpu = tiles_gpu();
+        // tile 3 has its row 2 in the color 3, the others in the color 0
+        gpu.mem_write(0x8034, 0xFF);
+        gpu.mem_write(0x8035, 0xFF);
+        for x in 0..20 {
+            gpu.mem_write(0x9C00 + x, 3);
+        }
+        gpu.mem_write(0xFF4B, 7);
+        gpu.mem_write(0xFF40, 0xF3);
+        gpu.sync(2 * LINE_DOTS);
+
+        // the window row 2 is drawn on the line 4, after the window was hidden
+        gpu.mem_write(0xFF40, 0x93);
+        gpu.sync(2 * LINE_DOTS);
+        gpu.mem_write(0xFF40, 0xF3);
+        gpu.sync(LINE_DOTS);
+
+        assert_eq!(shade_at(&gpu, 0, 2), 0);
+        assert_eq!(shade_at(&gpu, 0, 4), 3);
+    }
+
+    #[test]
+    fn wy_trigger() {
+        let mut gpu = tiles_gpu();
+        for x in 0..20 {
+            gpu.mem_write(0x9C00 + x, 1);
+        }
+        gpu.mem_write(0xFF4B, 7);
+        gpu.mem_write(0xFF4A, 1);
+        // WY was 0 when the LCD was turned on
+        gpu.mem_write(0xFF40, 0x00);
+        gpu.mem_write(0xFF40, 0xF3);
+        gpu.sync(2 * LINE_DOTS);
+
+        // moving WY after the trigger keeps the window
+        gpu.mem_write(0xFF4A, 100);
+        gpu.sync(LINE_DOTS);
+        assert_eq!(shade_at(&gpu, 0, 0), 0);
+        assert_eq!(shade_at(&gpu, 0, 1), 1);
+        assert_eq!(shade_at(&gpu, 0, 2), 1);
+
+        // WY passed before it was written
+        gpu.sync(LINE_DOTS * (154 - 3 + 4));
+        gpu.mem_write(0xFF4A, 2);
+        gpu.sync(LINE_DOTS);
+        assert_eq!(shade_at(&gpu, 0, 4), 0);
+    }
+
+    #[test]
+    fn window_edge_cases() {
+        let window_gpu = |wx: u8, scx: u8| {
+            let mut gpu = tiles_gpu();
+            gpu.mem_write(0x9C00, 1);
+            gpu.mem_write(0x9C01, 2);
+            gpu.mem_write(0xFF43, scx);
+            gpu.mem_write(0xFF4B, wx);
+            gpu.mem_write(0xFF40, 0xF3);
+            gpu.sync(2 * LINE_DOTS);
+            gpu
+        };
+
+        // WX=0 hides 7 pixels of the window, more with the fine scroll
+        let gpu = window_gpu(0, 0);
+        assert_eq!(shade_at(&gpu, 0, 0), 1);
+        assert_eq!(shade_at(&gpu, 1, 0), 3);
+        let gpu = window_gpu(0, 3);
+        assert_eq!(shade_at(&gpu, 0, 0), 3);
+
+        let gpu = window_gpu(3, 0);
+        assert_eq!(shade_at(&gpu, 3, 0), 1);
+        assert_eq!(shade_at(&gpu, 5, 0), 3);
+
+        // WX=166 draws the window on the whole next line
+        let gpu = window_gpu(166, 0);
+        assert_eq!(shade_at(&gpu, 159, 0), 0);
+        assert_eq!(shade_at(&gpu, 0, 1), 1);
+        assert_eq!(shade_at(&gpu, 1, 1), 3);
+    }
+}
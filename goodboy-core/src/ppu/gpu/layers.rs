@@ -0,0 +1,62 @@
+use super::Gpu;
+
+bitflags::bitflags! {
+    /// The layers drawn on screen. A hidden background or window is drawn with its
+    /// color 0, so the sprites behind it show.
+    pub struct Layers: u8 {
+        const BACKGROUND = 1 << 0;
+        const WINDOW     = 1 << 1;
+        const SPRITES    = 1 << 2;
+    }
+}
+
+impl Default for Layers {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+/// The layer that drew a pixel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layer {
+    #[default]
+    Background,
+    Window,
+    Sprites,
+}
+
+/// How a pixel was drawn, before the palette is applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PixelInfo {
+    pub layer: Layer,
+    /// The CGB palette number, or OBP0/OBP1 for the DMG sprites (0 for BGP)
+    pub palette: u8,
+    /// The 2 bits color index in the palette
+    pub color: u8,
+}
+
+impl Gpu {
+    /// Record how every pixel is drawn alongside the screen buffer, see
+    /// [`Gpu::pixel_info`]
+    pub fn set_pixel_info_enabled(&mut self, enabled: bool) {
+        self.pixel_info = enabled.then(|| vec![PixelInfo::default(); self.screen_pixels()]);
+    }
+
+    /// The [`PixelInfo`] of the screen pixels, row by row, when it is recorded
+    pub fn pixel_info(&self) -> Option<&[PixelInfo]> {
+        self.pixel_info.as_deref()
+    }
+
+    /// Show or hide the background, the window and the sprites, from the next pixel
+    pub fn set_layers(&mut self, layers: Layers) {
+        self.layers = layers;
+    }
+
+    pub fn layers(&self) -> Layers {
+        self.layers
+    }
+
+    fn screen_pixels(&self) -> usize {
+        self.screen_buffer.len() / 4
+    }
+}
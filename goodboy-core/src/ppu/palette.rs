@@ -4,9 +4,38 @@ use super::color::{Color, ColorScheme, ColorType};
 
 pub type Palette = [(Color, ColorType); 4];
 
+/// The color schemes of the background and of the two object palettes
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ColorSchemes {
+    pub bg: ColorScheme,
+    pub obj0: ColorScheme,
+    pub obj1: ColorScheme,
+}
+
+impl ColorSchemes {
+    pub fn get(&self, palette: PaletteKind) -> &ColorScheme {
+        match palette {
+            PaletteKind::BG => &self.bg,
+            PaletteKind::OBJ0 => &self.obj0,
+            PaletteKind::OBJ1 => &self.obj1,
+        }
+    }
+}
+
+/// The same scheme for all the palettes
+impl From<ColorScheme> for ColorSchemes {
+    fn from(scheme: ColorScheme) -> Self {
+        ColorSchemes {
+            bg: scheme,
+            obj0: scheme,
+            obj1: scheme,
+        }
+    }
+}
+
 /// Wraps the three GB palettes (background, object0 and object1)
 pub struct Palettes {
-    color_scheme: ColorScheme,
+    color_schemes: ColorSchemes,
 
     bg: Palette,
     obj0: Palette,
@@ -24,7 +53,8 @@ impl Palettes {
     }
 
     pub fn update(&mut self, palette: PaletteKind, color_index: usize, color_type: ColorType) {
-        let palette_color = (self.color_scheme.get(color_type), color_type);
+        let color_scheme = self.color_schemes.get(palette);
+        let palette_color = (color_scheme.get(color_type), color_type);
 
         match palette {
             PaletteKind::BG => self.bg[color_index] = palette_color,
@@ -33,18 +63,18 @@ impl Palettes {
         }
     }
 
-    pub fn set_color_scheme(&mut self, color_scheme: ColorScheme) {
+    pub fn set_color_schemes(&mut self, color_schemes: ColorSchemes) {
         for (color, ref color_type) in self.bg.iter_mut() {
-            *color = color_scheme.get(*color_type)
+            *color = color_schemes.bg.get(*color_type)
         }
         for (color, ref color_type) in self.obj0.iter_mut() {
-            *color = color_scheme.get(*color_type)
+            *color = color_schemes.obj0.get(*color_type)
         }
         for (color, ref color_type) in self.obj1.iter_mut() {
-            *color = color_scheme.get(*color_type)
+            *color = color_schemes.obj1.get(*color_type)
         }
 
-        self.color_scheme = color_scheme;
+        self.color_schemes = color_schemes;
     }
 }
 
@@ -62,7 +92,7 @@ impl Default for Palettes {
         let obj1_palette = [(colors.white, ColorType::White); 4];
 
         Palettes {
-            color_scheme: colors,
+            color_schemes: colors.into(),
 
             bg: bg_palette,
             obj0: obj0_palette,
@@ -99,3 +129,47 @@ impl From<PaletteKind> for usize {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::ColorCorrection;
+
+    #[test]
+    fn separate_color_schemes() {
+        let rgba = |palettes: &Palettes, kind, index: usize| {
+            palettes.get(kind)[index]
+                .0
+                .into_rgba_slice(ColorCorrection::Raw)
+        };
+
+        let mut palettes = Palettes::default();
+        palettes.update(PaletteKind::OBJ0, 1, ColorType::Black);
+        palettes.update(PaletteKind::OBJ1, 1, ColorType::Black);
+
+        palettes.set_color_schemes(ColorSchemes {
+            bg: ColorScheme::GREEN,
+            obj0: ColorScheme::RED,
+            obj1: ColorScheme::GRAY,
+        });
+        assert_eq!(
+            rgba(&palettes, PaletteKind::BG, 0),
+            [0xbe, 0xeb, 0x71, 0xFF]
+        );
+        assert_eq!(
+            rgba(&palettes, PaletteKind::OBJ0, 1),
+            [0x2c, 0x1e, 0x74, 0xFF]
+        );
+        assert_eq!(
+            rgba(&palettes, PaletteKind::OBJ1, 1),
+            [0x21, 0x1E, 0x20, 0xFF]
+        );
+
+        // the new colors use the scheme of their palette
+        palettes.update(PaletteKind::OBJ1, 2, ColorType::White);
+        assert_eq!(
+            rgba(&palettes, PaletteKind::OBJ1, 2),
+            [0xE9, 0xEF, 0xEC, 0xFF]
+        );
+    }
+}
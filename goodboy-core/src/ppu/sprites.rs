@@ -1,4 +1,4 @@
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy)]
 pub struct Sprite {
     pub x: i32,
     pub y: i32,
@@ -16,6 +16,23 @@ pub struct Sprite {
     pub vram_bank: usize,
 }
 
+/// A zeroed OAM entry, above the screen
+impl Default for Sprite {
+    fn default() -> Self {
+        Sprite {
+            x: -8,
+            y: -16,
+            tile_number: 0,
+            priority: false,
+            flip_x: false,
+            flip_y: false,
+            palette: false,
+            paletten: 0,
+            vram_bank: 0,
+        }
+    }
+}
+
 pub fn update_sprites(sprites: &mut [Sprite], sprite_addr: usize, sprite_value: u8) {
     let i = sprite_addr >> 2;
     match sprite_addr & 0x03 {
@@ -0,0 +1,162 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolError {
+    pub line: usize,
+    pub content: String,
+}
+
+impl Display for SymbolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}: expected `bank:addr label`, found {:?}",
+            self.line, self.content
+        )
+    }
+}
+
+impl std::error::Error for SymbolError {}
+
+/// Labels from a RGBDS symbol file (`.sym`), mapping `bank:addr` to a name
+#[derive(Debug, Default, Clone)]
+pub struct Symbols {
+    labels: BTreeMap<(usize, u16), String>,
+    addresses: HashMap<String, (usize, u16)>,
+}
+
+impl Symbols {
+    /// Parse a symbol file, where each line looks like `01:4000 Label`. Comments start
+    /// with `;`.
+    pub fn parse(sym: &str) -> Result<Symbols, SymbolError> {
+        let mut symbols = Symbols::default();
+
+        for (i, line) in sym.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let parse = || {
+                let (location, name) = line.split_once(char::is_whitespace)?;
+                let (bank, addr) = location.split_once(':')?;
+                let bank = usize::from_str_radix(bank, 16).ok()?;
+                let addr = u16::from_str_radix(addr, 16).ok()?;
+                Some((bank, addr, name.trim()))
+            };
+
+            let (bank, addr, name) = parse().ok_or_else(|| SymbolError {
+                line: i + 1,
+                content: line.to_string(),
+            })?;
+            symbols.insert(bank, addr, name);
+        }
+
+        Ok(symbols)
+    }
+
+    pub fn insert(&mut self, bank: usize, addr: u16, name: &str) {
+        // keep the first label when there are many at the same address
+        self.labels
+            .entry((bank, addr))
+            .or_insert_with(|| name.to_string());
+        self.addresses.insert(name.to_string(), (bank, addr));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    /// The label at `bank:addr`
+    pub fn name(&self, bank: usize, addr: u16) -> Option<&str> {
+        self.labels.get(&(bank, addr)).map(String::as_str)
+    }
+
+    /// The location of a label
+    pub fn address(&self, name: &str) -> Option<(usize, u16)> {
+        self.addresses.get(name).copied()
+    }
+
+    /// Describe `bank:addr` as the closest label before it, e.g. `Main+$12`
+    pub fn locate(&self, bank: usize, addr: u16) -> Option<String> {
+        let (&(_, label_addr), name) = self
+            .labels
+            .range((bank, 0)..=(bank, addr))
+            .next_back()
+            .filter(|((_, label_addr), _)| region(*label_addr) == region(addr))?;
+
+        match addr - label_addr {
+            0 => Some(name.clone()),
+            offset => Some(format!("{name}+${offset:X}")),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, u16, &str)> {
+        self.labels
+            .iter()
+            .map(|(&(bank, addr), name)| (bank, addr, name.as_str()))
+    }
+}
+
+/// The memory region of an address, a label doesn't extend past its region
+fn region(addr: u16) -> u8 {
+    match addr {
+        0x0000..=0x3FFF => 0,
+        0x4000..=0x7FFF => 1,
+        0x8000..=0x9FFF => 2,
+        0xA000..=0xBFFF => 3,
+        0xC000..=0xCFFF => 4,
+        0xD000..=0xDFFF => 5,
+        0xFF80..=0xFFFE => 7,
+        _ => 6,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYM: &str = "\
+; File generated by rgblink
+00:0150 Start
+00:0160 Start.loop
+01:4000 Bank1Func
+02:4000 Bank2Func
+00:c000 wCounter
+";
+
+    #[test]
+    fn parse_and_lookup() {
+        let symbols = Symbols::parse(SYM).unwrap();
+
+        assert_eq!(symbols.len(), 5);
+        assert_eq!(symbols.name(2, 0x4000), Some("Bank2Func"));
+        assert_eq!(symbols.address("Start.loop"), Some((0, 0x0160)));
+        assert_eq!(symbols.address("Missing"), None);
+    }
+
+    #[test]
+    fn locate_addresses() {
+        let symbols = Symbols::parse(SYM).unwrap();
+
+        assert_eq!(symbols.locate(0, 0x0150).as_deref(), Some("Start"));
+        assert_eq!(symbols.locate(0, 0x0163).as_deref(), Some("Start.loop+$3"));
+        assert_eq!(symbols.locate(1, 0x4010).as_deref(), Some("Bank1Func+$10"));
+        // there is no label before it in the region
+        assert_eq!(symbols.locate(0, 0x0100), None);
+        assert_eq!(symbols.locate(0, 0x8000), None);
+    }
+
+    #[test]
+    fn invalid_line() {
+        let error = Symbols::parse("00:0150 Start\n0150 Oops").unwrap_err();
+        assert_eq!(error.line, 2);
+    }
+}
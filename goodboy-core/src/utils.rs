@@ -37,3 +37,26 @@ impl From<u16> for UnsignedValue {
         Self::U16(v)
     }
 }
+
+lazy_static::lazy_static! {
+    static ref CRC32_TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        for (n, entry) in table.iter_mut().enumerate() {
+            *entry = (0..8).fold(n as u32, |c, _| {
+                if c & 1 == 1 {
+                    0xEDB88320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                }
+            });
+        }
+        table
+    };
+}
+
+/// Compute the CRC32 (IEEE) checksum of `data`
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, byte| {
+        CRC32_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
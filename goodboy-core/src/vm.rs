@@ -1,13 +1,34 @@
 use crate::{
-    cpu::Cpu,
+    cheats::{Cheat, CheatError},
+    cpu::{
+        disassembler::{self, DecodedInstruction},
+        instruction::Instruction,
+        profiler::Profiler,
+        trace::Tracer,
+        Cpu,
+    },
+    debugger::{Breakpoint, StopReason, Watchpoint},
     io::JoypadButton,
-    mmu::{cartridge::Cartridge, Bus},
-    ppu::ColorScheme,
+    mmu::{
+        cartridge::Cartridge,
+        cdl::CodeDataLogger,
+        patch::{Patch, PatchError},
+        Bus,
+    },
+    ppu::{
+        ColorCorrection, ColorScheme, ColorSchemes, DebugImage, DebugPalettes, Layers, OamSprite,
+        PixelInfo,
+    },
+    symbols::Symbols,
 };
 
 pub const SCREEN_WIDTH: usize = 160;
 pub const SCREEN_HEIGHT: usize = 144;
 
+/// `step_over` and `step_out` give up after one second of emulated time, so a
+/// function that never returns can't hang the caller
+const STEP_CLOCKS_LIMIT: u32 = 70224 * 60;
+
 pub type Screen = Box<[u8; SCREEN_WIDTH * SCREEN_HEIGHT * 4]>;
 
 pub struct Vm {
@@ -22,6 +43,17 @@ impl Vm {
         Self { cpu: Cpu::new(bus) }
     }
 
+    /// Create a new VM from a ROM buffer and the patches to apply to it
+    pub fn with_patches(rom_buffer: &[u8], patches: &[Patch]) -> Result<Self, PatchError> {
+        log::info!(
+            "Creating a new VM from file buffer and {} patches",
+            patches.len()
+        );
+
+        let cartridge = Cartridge::with_patches(rom_buffer, patches)?;
+        Ok(Self::from_cartridge(cartridge))
+    }
+
     pub fn from_cartridge(cartridge: Cartridge) -> Self {
         log::info!("Creating a new VM from cartridge");
 
@@ -33,13 +65,28 @@ impl Vm {
         self.cpu.run()
     }
 
+    /// Execute a single instruction like `tick`, also reporting a watchpoint hit or a
+    /// breakpoint at the next instruction
+    pub fn tick_checked(&mut self) -> (u32, Option<StopReason>) {
+        let clocks = self.cpu.run();
+        let debugger = &self.cpu.bus.debugger;
+
+        let stop = match debugger.take_watch_hit() {
+            Some(hit) => Some(StopReason::Watchpoint(hit)),
+            None => debugger
+                .check_breakpoints(&self.cpu)
+                .map(StopReason::Breakpoint),
+        };
+        (clocks, stop)
+    }
+
     pub fn check_vblank(&mut self) -> bool {
         let vblanked = self.cpu.bus.gpu.vblanked;
         self.cpu.bus.gpu.vblanked = false;
         vblanked
     }
 
-    pub fn game_title<'a>(&'a self) -> &'a str {
+    pub fn game_title(&self) -> &str {
         let cartridge = &self.cpu.bus.cartridge;
         cartridge.rom_name()
     }
@@ -48,6 +95,49 @@ impl Vm {
         self.cpu.bus.gpu.screen_buffer.clone()
     }
 
+    /// Record the layer, the palette and the color index of every pixel along the screen
+    pub fn set_pixel_info_enabled(&mut self, enabled: bool) {
+        self.cpu.bus.gpu.set_pixel_info_enabled(enabled);
+    }
+
+    /// How the pixels of the screen were drawn, complete after a VBlank, see
+    /// [`Gpu::pixel_info`](crate::ppu::Gpu::pixel_info)
+    pub fn pixel_info(&self) -> Option<&[PixelInfo]> {
+        self.cpu.bus.gpu.pixel_info()
+    }
+
+    /// Show or hide the background, the window and the sprites
+    pub fn set_layers(&mut self, layers: Layers) {
+        log::info!("Showing the layers: {layers:?}");
+        self.cpu.bus.gpu.set_layers(layers);
+    }
+
+    pub fn layers(&self) -> Layers {
+        self.cpu.bus.gpu.layers()
+    }
+
+    /// The tiles of both VRAM banks, see [`Gpu::tile_sheet`](crate::ppu::Gpu::tile_sheet)
+    pub fn tile_sheet(&self) -> DebugImage {
+        self.cpu.bus.gpu.tile_sheet()
+    }
+
+    /// A tilemap with the scroll viewport, see [`Gpu::tilemap`](crate::ppu::Gpu::tilemap)
+    pub fn tilemap(&self, map: usize) -> DebugImage {
+        self.cpu.bus.gpu.tilemap(map)
+    }
+
+    pub fn oam_sprites(&self) -> Vec<OamSprite> {
+        self.cpu.bus.gpu.oam_sprites()
+    }
+
+    pub fn debug_palettes(&self) -> DebugPalettes {
+        self.cpu.bus.gpu.debug_palettes()
+    }
+
+    pub fn palette_sheet(&self) -> DebugImage {
+        self.cpu.bus.gpu.palette_sheet()
+    }
+
     pub fn press_button(&mut self, button: JoypadButton) {
         log::info!("Button pressed: {button:?}");
         self.cpu.bus.joypad.press_button(button);
@@ -58,8 +148,252 @@ impl Vm {
         self.cpu.bus.joypad.release_button(button);
     }
 
+    /// Parse and add a Game Genie or GameShark code, returning its index
+    pub fn add_cheat(&mut self, name: &str, code: &str) -> Result<usize, CheatError> {
+        let cheat = Cheat::parse(name, code)?;
+        Ok(self.add_cheat_entry(cheat))
+    }
+
+    /// Add an already parsed cheat, returning its index
+    pub fn add_cheat_entry(&mut self, cheat: Cheat) -> usize {
+        self.cpu.bus.cheats.add(cheat)
+    }
+
+    pub fn remove_cheat(&mut self, index: usize) -> Option<Cheat> {
+        self.cpu.bus.cheats.remove(index)
+    }
+
+    /// Enable or disable a cheat, returns false if there is no cheat at `index`
+    pub fn set_cheat_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        self.cpu.bus.cheats.set_enabled(index, enabled)
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        self.cpu.bus.cheats.list()
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        log::info!("Adding breakpoint: {breakpoint:X?}");
+        self.cpu.bus.debugger.add_breakpoint(breakpoint)
+    }
+
+    pub fn remove_breakpoint(&mut self, index: usize) -> Option<Breakpoint> {
+        self.cpu.bus.debugger.remove_breakpoint(index)
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        self.cpu.bus.debugger.breakpoints()
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        log::info!("Adding watchpoint: {watchpoint:X?}");
+        self.cpu.bus.debugger.add_watchpoint(watchpoint)
+    }
+
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<Watchpoint> {
+        self.cpu.bus.debugger.remove_watchpoint(index)
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        self.cpu.bus.debugger.watchpoints()
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    /// Read a byte as the CPU sees it, without triggering the watchpoints
+    pub fn read_memory(&self, addr: u16) -> u8 {
+        self.cpu.bus.read(addr)
+    }
+
+    /// Read any ROM, VRAM, cartridge RAM or WRAM bank without side effects, see
+    /// [`Bus::peek`](crate::mmu::Bus::peek)
+    pub fn peek(&self, bank: usize, addr: u16) -> Option<u8> {
+        self.cpu.bus.peek(bank, addr)
+    }
+
+    /// Write any ROM, VRAM, cartridge RAM or WRAM bank without side effects, see
+    /// [`Bus::poke`](crate::mmu::Bus::poke)
+    pub fn poke(&mut self, bank: usize, addr: u16, value: u8) -> bool {
+        self.cpu.bus.poke(bank, addr, value)
+    }
+
+    /// The bank mapped at `addr`: the ROM, cartridge RAM, VRAM or WRAM bank, 0 elsewhere
+    pub fn bank_at(&self, addr: u16) -> usize {
+        self.cpu.bus.bank_at(addr)
+    }
+
+    /// Start or stop logging every instruction executed
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.cpu.set_tracer(tracer)
+    }
+
+    /// Start or stop attributing the executed cycles to the functions, returns the
+    /// previous profiler. Its report uses [`Vm::symbols`] to name the functions.
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) -> Option<Profiler> {
+        self.cpu.set_profiler(profiler)
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.cpu.profiler()
+    }
+
+    /// A Code/Data Logger sized for the current game, see [`Vm::set_cdl`]
+    pub fn new_cdl(&self) -> CodeDataLogger {
+        CodeDataLogger::for_cartridge(&self.cpu.bus.cartridge)
+    }
+
+    /// Start or stop logging how the game uses every ROM and RAM byte, returns the
+    /// previous logger
+    pub fn set_cdl(&mut self, cdl: Option<CodeDataLogger>) -> Option<CodeDataLogger> {
+        self.cpu.bus.set_cdl(cdl)
+    }
+
+    pub fn cdl(&self) -> Option<&CodeDataLogger> {
+        self.cpu.bus.cdl()
+    }
+
+    /// Use the labels of a RGBDS symbol file in the debugger, disassembly and traces
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        log::info!("Loaded {} symbols", symbols.len());
+        self.cpu.bus.debugger.set_symbols(symbols)
+    }
+
+    pub fn symbols(&self) -> &Symbols {
+        self.cpu.bus.debugger.symbols()
+    }
+
+    /// Format an instruction with the labels of the banks currently mapped
+    pub fn format_instruction(&self, instruction: &DecodedInstruction) -> String {
+        instruction.format_with(|addr| self.cpu.bus.symbol_at(addr).map(String::from))
+    }
+
+    /// Describe `addr` as `bank:addr`, followed by the closest label if there is one
+    pub fn describe_addr(&self, addr: u16) -> String {
+        self.cpu.bus.describe_addr(addr)
+    }
+
+    /// Decode `count` instructions starting at `addr`, in the banks currently mapped
+    pub fn disassemble(&self, addr: u16, count: usize) -> Vec<DecodedInstruction> {
+        let bus = &self.cpu.bus;
+        let mut addr = addr;
+
+        (0..count)
+            .map(|_| {
+                let bytes = [0, 1, 2].map(|i| bus.read(addr.wrapping_add(i)));
+                let instruction = disassembler::decode(&bytes, addr, bus.bank_at(addr));
+                addr = instruction.next_addr();
+                instruction
+            })
+            .collect()
+    }
+
+    /// Decode `count` instructions starting at `addr` in `bank`, which may not be mapped.
+    /// The bytes outside the bank read as 0xFF.
+    pub fn disassemble_bank(
+        &self,
+        bank: usize,
+        addr: u16,
+        count: usize,
+    ) -> Vec<DecodedInstruction> {
+        let bus = &self.cpu.bus;
+        let mut addr = addr;
+
+        (0..count)
+            .map(|_| {
+                let bytes = [0, 1, 2].map(|i| bus.peek(bank, addr.wrapping_add(i)).unwrap_or(0xFF));
+                let instruction = disassembler::decode(&bytes, addr, bank);
+                addr = instruction.next_addr();
+                instruction
+            })
+            .collect()
+    }
+
+    /// Execute a single instruction
+    pub fn step(&mut self) -> StopReason {
+        self.cpu.run();
+
+        match self.cpu.bus.debugger.take_watch_hit() {
+            Some(hit) => StopReason::Watchpoint(hit),
+            None => StopReason::Step,
+        }
+    }
+
+    /// Execute a single instruction, running the whole subroutine if it is a `CALL` or `RST`
+    pub fn step_over(&mut self) -> StopReason {
+        let opcode = Cpu::decode(self.cpu.bus.read(self.cpu.pc), false);
+
+        match opcode {
+            Some(opcode)
+                if matches!(
+                    opcode.instruction,
+                    Instruction::CALL(_) | Instruction::RST(_)
+                ) =>
+            {
+                let (sp, ret) = (self.cpu.sp, self.cpu.pc.wrapping_add(opcode.length as u16));
+                self.run_while(STEP_CLOCKS_LIMIT, |cpu| cpu.pc != ret || cpu.sp < sp)
+                    .unwrap_or(StopReason::Step)
+            }
+            _ => self.step(),
+        }
+    }
+
+    /// Run until the current subroutine returns
+    pub fn step_out(&mut self) -> StopReason {
+        // returning pops the return address from the stack
+        let sp = self.cpu.sp;
+        self.run_while(STEP_CLOCKS_LIMIT, |cpu| cpu.sp <= sp)
+            .unwrap_or(StopReason::Step)
+    }
+
+    /// Run for `clocks` or until a breakpoint or a watchpoint is hit
+    pub fn run_until(&mut self, clocks: u32) -> StopReason {
+        self.run_while(clocks, |_| true)
+            .unwrap_or(StopReason::ClocksElapsed)
+    }
+
+    /// Execute instructions while `running` returns true, checking the breakpoints and
+    /// the watchpoints. The breakpoints at the first instruction are ignored so the
+    /// execution can resume from them. Returns `None` when `running` returned false.
+    fn run_while(
+        &mut self,
+        clocks: u32,
+        mut running: impl FnMut(&Cpu) -> bool,
+    ) -> Option<StopReason> {
+        let mut elapsed = 0;
+
+        loop {
+            elapsed += self.cpu.run();
+
+            if let Some(hit) = self.cpu.bus.debugger.take_watch_hit() {
+                return Some(StopReason::Watchpoint(hit));
+            }
+            if !running(&self.cpu) {
+                return None;
+            }
+            if let Some(index) = self.cpu.bus.debugger.check_breakpoints(&self.cpu) {
+                return Some(StopReason::Breakpoint(index));
+            }
+            if elapsed >= clocks {
+                return Some(StopReason::ClocksElapsed);
+            }
+        }
+    }
+
     pub fn set_color_scheme(&mut self, color_scheme: ColorScheme) {
-        log::info!("Setting color scheme: {color_scheme:?}");
-        self.cpu.bus.gpu.set_color_scheme(color_scheme);
+        self.set_color_schemes(color_scheme.into());
+    }
+
+    /// Use different colors for the background and the two object palettes
+    pub fn set_color_schemes(&mut self, color_schemes: ColorSchemes) {
+        log::info!("Setting color schemes: {color_schemes:?}");
+        self.cpu.bus.gpu.set_color_schemes(color_schemes);
+    }
+
+    /// Show the CGB colors as on a CGB, a GBA, or with the raw values
+    pub fn set_color_correction(&mut self, correction: ColorCorrection) {
+        log::info!("Setting color correction: {correction:?}");
+        self.cpu.bus.gpu.set_color_correction(correction);
     }
 }
@@ -1,6 +1,6 @@
 use std::{rc::Rc, sync::mpsc};
 
-use goodboy_core::vm::{SCREEN_HEIGHT as HEIGHT, SCREEN_WIDTH as WIDTH};
+use goodboy_core::vm::{Screen, SCREEN_HEIGHT as HEIGHT, SCREEN_WIDTH as WIDTH};
 use pixels::{Pixels, SurfaceTexture};
 use winit::{
     dpi::LogicalSize,
@@ -9,9 +9,10 @@ use winit::{
     window::{Window, WindowBuilder},
 };
 
-#[cfg(target_arch = "wasm32")]
-use crate::io::IoEvent;
-use crate::io::IoHandler;
+use crate::bindings::{Bindings, Hotkey};
+use crate::color_schemes::ColorSchemeList;
+use crate::filter::{Filter, FilterPipeline};
+use crate::io::{IoEvent, IoHandler};
 use crate::utils::Fps;
 use crate::GameBoy;
 
@@ -19,6 +20,9 @@ pub struct App {
     window: Rc<Window>,
     event_loop: EventLoop<()>,
     gameboy: GameBoy,
+    filters: FilterPipeline,
+    io_handler: IoHandler,
+    io_rx: mpsc::Receiver<IoEvent>,
 }
 
 impl App {
@@ -34,17 +38,43 @@ impl App {
         }?;
 
         window.set_visible(true);
+        let (io_handler, io_rx) = IoHandler::new();
 
         Ok(Self {
             window: Rc::new(window),
             event_loop,
             gameboy,
+            filters: FilterPipeline::new(Filter::default(), false),
+            io_handler,
+            io_rx,
         })
     }
 
+    /// The keys of the joypad buttons and of the hotkeys
+    pub fn set_bindings(&mut self, bindings: Bindings) {
+        self.io_handler.set_bindings(bindings);
+    }
+
+    /// The DMG color schemes cycled with Tab, starting with the current one
+    pub fn set_color_schemes(&mut self, color_schemes: ColorSchemeList) {
+        self.io_handler.set_color_schemes(color_schemes);
+    }
+
+    /// Apply the patch found next to the games opened with Esc
+    pub fn set_auto_patch(&mut self, auto_patch: bool) {
+        self.io_handler.set_auto_patch(auto_patch);
+    }
+
+    /// Draw the screen through `filter`, blended with the previous screen with `ghosting`
+    pub fn set_filter(&mut self, filter: Filter, ghosting: bool) {
+        self.filters.set_filter(filter);
+        self.filters.set_ghosting(ghosting);
+    }
+
     #[allow(unused_mut)]
     pub async fn run(mut self) {
-        let (mut io_handler, io_rx) = IoHandler::new();
+        let (mut io_handler, io_rx) = (self.io_handler, self.io_rx);
+        io_handler.send_color_scheme();
         let (title_sender, title_receiver) = mpsc::channel();
 
         #[cfg(target_arch = "wasm32")]
@@ -63,6 +93,8 @@ impl App {
             pixels
         };
 
+        resize_buffer(&self.window, &mut pixels, &self.filters);
+
         #[cfg(target_arch = "wasm32")]
         let (mut game_title, screen_rx) = (Option::<String>::None, self.gameboy.screen_rx.take().unwrap());
         #[cfg(not(target_arch = "wasm32"))]
@@ -84,6 +116,7 @@ impl App {
             event_loop,
             #[cfg(target_arch = "wasm32")]
             mut gameboy,
+            mut filters,
             ..
         } = self;
 
@@ -129,7 +162,11 @@ impl App {
                                     let _ = gameboy.vm.insert(Vm::from_cartridge(cart));
                                     break;
                                 }
-                                // IoEvent::SetColorScheme(color_scheme) => vm.set_color_scheme(color_scheme),
+                                IoEvent::SetColorScheme(color_schemes) => {
+                                    if let Some(vm) = gameboy.vm.as_mut() {
+                                        vm.set_color_schemes(color_schemes);
+                                    }
+                                }
                                 // IoEvent::SwitchSpeedNext => {
                                 //     time_cycle.nth(0);
                                 // }
@@ -160,6 +197,8 @@ impl App {
                     ..
                 } => {
                     log::info!("Exit event received");
+                    stop_emulation(&io_handler, &screen_rx);
+
                     *control_flow = ControlFlow::Exit;
                     return;
                 }
@@ -169,12 +208,13 @@ impl App {
 
                     match screen_rx.try_recv() {
                         Ok(screen) => {
-                            frame.copy_from_slice(screen.as_slice());
+                            filters.push_screen(screen.as_slice());
+                            filters.render(frame);
 
                             if let Some(ref title) = game_title {
                                 window.set_title(&format!("{title} - {fps}"));
                             } else {
-                                window.set_title(&format!("GoodBoy"));
+                                window.set_title("GoodBoy");
                             }
                         }
                         Err(mpsc::TryRecvError::Disconnected) => {
@@ -204,6 +244,43 @@ impl App {
                     game_title = Some(title)
                 }
 
+                if io_handler.hotkey_pressed(Hotkey::Exit) {
+                    log::info!("Exit hotkey pressed");
+                    stop_emulation(&io_handler, &screen_rx);
+
+                    *control_flow = ControlFlow::Exit;
+                    return;
+                }
+
+                let filter_hotkeys = [
+                    Hotkey::NextFilter,
+                    Hotkey::PrevFilter,
+                    Hotkey::ToggleGhosting,
+                ];
+                let filter_changed = filter_hotkeys
+                    .into_iter()
+                    .any(|hotkey| io_handler.hotkey_pressed(hotkey));
+
+                if filter_changed {
+                    if io_handler.hotkey_pressed(Hotkey::ToggleGhosting) {
+                        filters.set_ghosting(!filters.ghosting());
+                    }
+                    if io_handler.hotkey_pressed(Hotkey::NextFilter) {
+                        filters.set_filter(filters.filter().next());
+                    }
+                    if io_handler.hotkey_pressed(Hotkey::PrevFilter) {
+                        filters.set_filter(filters.filter().prev());
+                    }
+                    log::info!(
+                        "Filter {}, ghosting {}",
+                        filters.filter(),
+                        filters.ghosting()
+                    );
+
+                    resize_buffer(&window, &mut pixels, &filters);
+                    filters.render(pixels.get_frame());
+                }
+
                 // Resize the window
                 if let Some(size) = io_handler.window_resized() {
                     pixels.resize_surface(size.width, size.height);
@@ -214,3 +291,30 @@ impl App {
         });
     }
 }
+
+/// Let the emulation thread save the game and write the reports before exiting
+#[allow(unused_variables)]
+fn stop_emulation(io_handler: &IoHandler, screen_rx: &mpsc::Receiver<Screen>) {
+    #[cfg(not(target_arch = "wasm32"))]
+    if io_handler.sender.send(IoEvent::Exit).is_ok() {
+        let timeout = std::time::Duration::from_secs(1);
+        while screen_rx.recv_timeout(timeout).is_ok() {}
+    }
+}
+
+/// Fit the pixel buffer to the output of the filter, the window can't be smaller
+fn resize_buffer(window: &Window, pixels: &mut Pixels, filters: &FilterPipeline) {
+    let (width, height) = filters.buffer_size();
+    pixels.resize_buffer(width, height);
+
+    let min_size = LogicalSize::new(width, height);
+    window.set_min_inner_size(Some(min_size));
+
+    let size = window.inner_size().to_logical::<u32>(window.scale_factor());
+    if size.width < width || size.height < height {
+        window.set_inner_size(min_size);
+
+        let size = min_size.to_physical::<u32>(window.scale_factor());
+        pixels.resize_surface(size.width, size.height);
+    }
+}
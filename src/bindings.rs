@@ -0,0 +1,363 @@
+use std::{collections::HashMap, path::PathBuf, str::FromStr};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{io, path::Path};
+
+use goodboy_core::io::JoypadButton;
+use serde::{
+    de::{self, value::StrDeserializer, IntoDeserializer},
+    Deserialize,
+};
+use winit::event::VirtualKeyCode;
+use winit_input_helper::WinitInputHelper;
+
+const BUTTONS: [(JoypadButton, &str); 8] = [
+    (JoypadButton::A, "a"),
+    (JoypadButton::B, "b"),
+    (JoypadButton::Start, "start"),
+    (JoypadButton::Select, "select"),
+    (JoypadButton::Left, "left"),
+    (JoypadButton::Right, "right"),
+    (JoypadButton::Up, "up"),
+    (JoypadButton::Down, "down"),
+];
+
+/// The actions of the frontend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+    OpenGame,
+    NextColorScheme,
+    PrevColorScheme,
+    NextSpeed,
+    PrevSpeed,
+    ToggleBackground,
+    ToggleWindow,
+    ToggleSprites,
+    NextFilter,
+    PrevFilter,
+    ToggleGhosting,
+    ReloadBindings,
+    Exit,
+}
+
+const HOTKEYS: [(Hotkey, &str); 13] = [
+    (Hotkey::OpenGame, "open_game"),
+    (Hotkey::NextColorScheme, "next_color_scheme"),
+    (Hotkey::PrevColorScheme, "prev_color_scheme"),
+    (Hotkey::NextSpeed, "next_speed"),
+    (Hotkey::PrevSpeed, "prev_speed"),
+    (Hotkey::ToggleBackground, "toggle_background"),
+    (Hotkey::ToggleWindow, "toggle_window"),
+    (Hotkey::ToggleSprites, "toggle_sprites"),
+    (Hotkey::NextFilter, "next_filter"),
+    (Hotkey::PrevFilter, "prev_filter"),
+    (Hotkey::ToggleGhosting, "toggle_ghosting"),
+    (Hotkey::ReloadBindings, "reload_bindings"),
+    (Hotkey::Exit, "exit"),
+];
+
+/// A key with the modifiers that must be held, like `Ctrl+Shift+Tab`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyCombo {
+    pub key: VirtualKeyCode,
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+}
+
+impl KeyCombo {
+    const fn new(key: VirtualKeyCode) -> Self {
+        KeyCombo {
+            key,
+            ctrl: false,
+            shift: false,
+            alt: false,
+        }
+    }
+
+    const fn ctrl(self) -> Self {
+        KeyCombo { ctrl: true, ..self }
+    }
+
+    const fn shift(self) -> Self {
+        KeyCombo {
+            shift: true,
+            ..self
+        }
+    }
+
+    /// The key was pressed with exactly these modifiers
+    fn pressed(&self, input: &WinitInputHelper) -> bool {
+        input.key_pressed(self.key)
+            && input.held_control() == self.ctrl
+            && input.held_shift() == self.shift
+            && input.held_alt() == self.alt
+    }
+}
+
+impl FromStr for KeyCombo {
+    type Err = String;
+
+    /// The modifiers then the winit name of the key, `Z`, `Key1`, `Ctrl+Return`...
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('+').map(str::trim).rev();
+        let key = parts.next().unwrap_or_default();
+        let deserializer: StrDeserializer<de::value::Error> = key.into_deserializer();
+        let key =
+            VirtualKeyCode::deserialize(deserializer).map_err(|_| format!("Unknown key {key}"))?;
+
+        let mut combo = KeyCombo::new(key);
+        for modifier in parts {
+            match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => combo.ctrl = true,
+                "shift" => combo.shift = true,
+                "alt" => combo.alt = true,
+                _ => return Err(format!("Unknown modifier {modifier} in {s}")),
+            }
+        }
+        Ok(combo)
+    }
+}
+
+/// The keys of the joypad buttons and of the hotkeys
+#[derive(Debug, Clone)]
+pub struct Bindings {
+    buttons: Vec<(VirtualKeyCode, JoypadButton)>,
+    hotkeys: Vec<(KeyCombo, Hotkey)>,
+    /// The file the bindings were read from, to reload them
+    path: Option<PathBuf>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        use VirtualKeyCode as Key;
+
+        Bindings {
+            buttons: vec![
+                (Key::Z, JoypadButton::A),
+                (Key::X, JoypadButton::B),
+                (Key::Return, JoypadButton::Start),
+                (Key::Space, JoypadButton::Select),
+                (Key::Left, JoypadButton::Left),
+                (Key::Right, JoypadButton::Right),
+                (Key::Up, JoypadButton::Up),
+                (Key::Down, JoypadButton::Down),
+            ],
+            hotkeys: vec![
+                (KeyCombo::new(Key::Escape), Hotkey::OpenGame),
+                (KeyCombo::new(Key::Tab), Hotkey::NextColorScheme),
+                (KeyCombo::new(Key::Tab).shift(), Hotkey::PrevColorScheme),
+                (KeyCombo::new(Key::Tab).ctrl(), Hotkey::NextSpeed),
+                (KeyCombo::new(Key::Tab).ctrl().shift(), Hotkey::PrevSpeed),
+                (KeyCombo::new(Key::Key1), Hotkey::ToggleBackground),
+                (KeyCombo::new(Key::Key2), Hotkey::ToggleWindow),
+                (KeyCombo::new(Key::Key3), Hotkey::ToggleSprites),
+                (KeyCombo::new(Key::F), Hotkey::NextFilter),
+                (KeyCombo::new(Key::F).shift(), Hotkey::PrevFilter),
+                (KeyCombo::new(Key::G), Hotkey::ToggleGhosting),
+                (KeyCombo::new(Key::R).ctrl(), Hotkey::ReloadBindings),
+                (KeyCombo::new(Key::Q).ctrl(), Hotkey::Exit),
+            ],
+            path: None,
+        }
+    }
+}
+
+/// The bindings file, the listed buttons and hotkeys replace their default keys
+///
+/// ```toml
+/// [buttons]
+/// a = ["K", "Z"]
+/// b = ["J", "X"]
+///
+/// [hotkeys]
+/// next_filter = ["Ctrl+F"]
+/// ```
+#[derive(Deserialize)]
+struct BindingsFile {
+    #[serde(default)]
+    buttons: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    hotkeys: HashMap<String, Vec<String>>,
+}
+
+impl Bindings {
+    /// Read the bindings of a TOML file, the reload hotkey reads it again
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+
+        let mut bindings = Self::parse(&text)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{path:?}: {e}")))?;
+        bindings.path = Some(path.to_path_buf());
+        Ok(bindings)
+    }
+
+    /// Read the bindings file again, the bindings are kept if it is invalid
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn reload(&mut self) -> io::Result<()> {
+        let path = self
+            .path
+            .as_ref()
+            .ok_or_else(|| io::Error::other("No bindings file"))?;
+
+        *self = Self::load_file(path)?;
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let file: BindingsFile = toml::from_str(text).map_err(|e| e.to_string())?;
+        let mut bindings = Self::default();
+
+        for (name, keys) in file.buttons {
+            let (button, _) = BUTTONS
+                .into_iter()
+                .find(|(_, button_name)| *button_name == name)
+                .ok_or_else(|| format!("Unknown button {name}"))?;
+
+            bindings.buttons.retain(|&(_, b)| b != button);
+            for key in keys {
+                match key.parse::<KeyCombo>()? {
+                    KeyCombo {
+                        key,
+                        ctrl: false,
+                        shift: false,
+                        alt: false,
+                    } => bindings.buttons.push((key, button)),
+                    _ => return Err(format!("The button {name} can't have modifiers")),
+                }
+            }
+        }
+
+        for (name, keys) in file.hotkeys {
+            let (hotkey, _) = HOTKEYS
+                .into_iter()
+                .find(|(_, hotkey_name)| *hotkey_name == name)
+                .ok_or_else(|| format!("Unknown hotkey {name}"))?;
+
+            bindings.hotkeys.retain(|&(_, h)| h != hotkey);
+            for key in keys {
+                bindings.hotkeys.push((key.parse()?, hotkey));
+            }
+        }
+
+        Ok(bindings)
+    }
+
+    pub fn hotkey_pressed(&self, input: &WinitInputHelper, hotkey: Hotkey) -> bool {
+        self.hotkeys
+            .iter()
+            .any(|(combo, h)| *h == hotkey && combo.pressed(input))
+    }
+
+    /// The buttons with a key pressed, whatever the modifiers held
+    pub fn pressed_buttons<'a>(
+        &'a self,
+        input: &'a WinitInputHelper,
+    ) -> impl Iterator<Item = JoypadButton> + 'a {
+        self.buttons
+            .iter()
+            .filter(|(key, _)| input.key_pressed(*key))
+            .map(|&(_, button)| button)
+    }
+
+    /// The buttons with a key released and no other key held
+    pub fn released_buttons<'a>(
+        &'a self,
+        input: &'a WinitInputHelper,
+    ) -> impl Iterator<Item = JoypadButton> + 'a {
+        self.buttons
+            .iter()
+            .filter(|(key, _)| input.key_released(*key))
+            .map(|&(_, button)| button)
+            .filter(|&button| {
+                !self
+                    .buttons
+                    .iter()
+                    .any(|&(key, b)| b == button && input.key_held(key))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use VirtualKeyCode as Key;
+
+    #[test]
+    fn parse_key_combos() {
+        assert_eq!(
+            "Ctrl+Shift+Tab".parse(),
+            Ok(KeyCombo::new(Key::Tab).ctrl().shift())
+        );
+        assert_eq!(
+            "alt + Key1".parse(),
+            Ok(KeyCombo {
+                alt: true,
+                ..KeyCombo::new(Key::Key1)
+            })
+        );
+        assert_eq!(
+            "Super+Z".parse::<KeyCombo>(),
+            Err("Unknown modifier Super in Super+Z".to_string())
+        );
+        assert_eq!(
+            "Ctrl+Foo".parse::<KeyCombo>(),
+            Err("Unknown key Foo".to_string())
+        );
+    }
+
+    #[test]
+    fn buttons_replace_their_defaults() {
+        let bindings = Bindings::parse("[buttons]\na = [\"K\", \"Z\"]\nb = [\"J\"]").unwrap();
+
+        let keys = |button| {
+            bindings
+                .buttons
+                .iter()
+                .filter(|&&(_, b)| b == button)
+                .map(|&(key, _)| key)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(keys(JoypadButton::A), [Key::K, Key::Z]);
+        assert_eq!(keys(JoypadButton::B), [Key::J]);
+        assert_eq!(keys(JoypadButton::Start), [Key::Return]);
+    }
+
+    #[test]
+    fn hotkeys_replace_their_defaults() {
+        let bindings =
+            Bindings::parse("[hotkeys]\nprev_speed = [\"Ctrl+Shift+Tab\", \"F2\"]").unwrap();
+
+        let combos = bindings
+            .hotkeys
+            .iter()
+            .filter(|&&(_, h)| h == Hotkey::PrevSpeed)
+            .map(|&(combo, _)| combo)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            combos,
+            [
+                KeyCombo::new(Key::Tab).ctrl().shift(),
+                KeyCombo::new(Key::F2)
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_bindings() {
+        assert_eq!(
+            Bindings::parse("[buttons]\na = [\"Ctrl+Z\"]").unwrap_err(),
+            "The button a can't have modifiers"
+        );
+        assert_eq!(
+            Bindings::parse("[buttons]\nturbo = [\"T\"]").unwrap_err(),
+            "Unknown button turbo"
+        );
+        assert_eq!(
+            Bindings::parse("[hotkeys]\nrewind = [\"Back\"]").unwrap_err(),
+            "Unknown hotkey rewind"
+        );
+    }
+}
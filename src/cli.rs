@@ -0,0 +1,180 @@
+use std::path::{Path, PathBuf};
+
+use goodboy_core::{cpu::trace::TraceOptions, ppu::ColorCorrection};
+
+use crate::filter::Filter;
+
+pub const USAGE: &str = "\
+Usage: goodboy [OPTIONS] [ROM]
+
+Options:
+    --patch <FILE>    Apply an IPS, UPS or BPS patch to the ROM (can be repeated)
+    --auto-patch      Apply the first of <ROM>.ips, <ROM>.ups and <ROM>.bps found next to the
+                      ROM, also to the games opened with Esc
+    --cheats <FILE>   Load a list of Game Genie and GameShark codes
+    --color-correction <MODE>
+                      Show the CGB colors as on a cgb (default), a gba, the raw colors, or
+                      the raw colors through a gamma like 2.2
+    --filter <NAME>   Draw the screen through a filter: none (default), lcd, scanlines, scale2x,
+                      scale3x, hq2x or xbr
+    --ghosting        Blend every frame with the previous one, like the slow DMG LCD
+    --bindings <FILE> Read the keys of the buttons and of the hotkeys from a TOML file
+    --color-schemes <FILE>
+                      Load DMG color schemes from a TOML or JSON list, or a .pal palette
+    --color-scheme <NAME>
+                      Start with a color scheme: blue (default), blue-alt, green, red, gray, or
+                      one of --color-schemes
+    --sym <FILE>      Load the labels of a RGBDS symbol file (default: <ROM>.sym if it exists)
+    --trace <FILE>    Log every instruction in the gameboy-doctor format
+    --trace-ly        Add the current scan line to the trace
+    --trace-cycles    Add the elapsed clocks to the trace
+    --trace-disasm    Add the disassembled instruction to the trace
+    --profile <FILE>  Write the cycles spent per function to FILE, and FILE.folded for flamegraphs
+    --cdl <FILE>      Log the ROM and RAM bytes used as code and data to FILE, in BizHawk's
+                      CDL format, an existing FILE is continued
+    --debug           Pause the game and read debugger commands from the terminal
+    --disassemble <BANK>
+                      Print a ROM bank as RGBDS assembly and exit
+    -h, --help        Print this message";
+
+const PATCH_EXTENSIONS: [&str; 3] = ["ips", "ups", "bps"];
+
+/// Command line options of the native frontend
+#[derive(Debug, Default)]
+pub struct Options {
+    pub rom: Option<PathBuf>,
+    pub patches: Vec<PathBuf>,
+    pub auto_patch: bool,
+    pub bindings: Option<PathBuf>,
+    pub cdl: Option<PathBuf>,
+    pub cheats: Option<PathBuf>,
+    pub color_correction: Option<ColorCorrection>,
+    pub color_scheme: Option<String>,
+    pub color_schemes: Option<PathBuf>,
+    pub debug: bool,
+    pub disassemble: Option<usize>,
+    pub filter: Filter,
+    pub ghosting: bool,
+    pub profile: Option<PathBuf>,
+    pub symbols: Option<PathBuf>,
+    pub trace: Option<PathBuf>,
+    pub trace_options: TraceOptions,
+}
+
+impl Options {
+    /// Parse the options from the program arguments (without the program name)
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--patch" => {
+                    let path = args.next().ok_or("Missing the file for --patch")?;
+                    options.patches.push(path.into());
+                }
+                "--auto-patch" => options.auto_patch = true,
+                "--bindings" => {
+                    let path = args.next().ok_or("Missing the file for --bindings")?;
+                    options.bindings = Some(path.into());
+                }
+                "--cheats" => {
+                    let path = args.next().ok_or("Missing the file for --cheats")?;
+                    options.cheats = Some(path.into());
+                }
+                "--cdl" => {
+                    let path = args.next().ok_or("Missing the file for --cdl")?;
+                    options.cdl = Some(path.into());
+                }
+                "--color-correction" => {
+                    let mode = args
+                        .next()
+                        .ok_or("Missing the mode for --color-correction")?;
+                    options.color_correction = Some(mode.parse()?);
+                }
+                "--color-scheme" => {
+                    let name = args.next().ok_or("Missing the name for --color-scheme")?;
+                    options.color_scheme = Some(name);
+                }
+                "--color-schemes" => {
+                    let path = args.next().ok_or("Missing the file for --color-schemes")?;
+                    options.color_schemes = Some(path.into());
+                }
+                "--debug" => options.debug = true,
+                "--filter" => {
+                    let name = args.next().ok_or("Missing the name for --filter")?;
+                    options.filter = name.parse()?;
+                }
+                "--ghosting" => options.ghosting = true,
+                "--profile" => {
+                    let path = args.next().ok_or("Missing the file for --profile")?;
+                    options.profile = Some(path.into());
+                }
+                "--sym" => {
+                    let path = args.next().ok_or("Missing the file for --sym")?;
+                    options.symbols = Some(path.into());
+                }
+                "--trace" => {
+                    let path = args.next().ok_or("Missing the file for --trace")?;
+                    options.trace = Some(path.into());
+                }
+                "--trace-ly" => options.trace_options.ly = true,
+                "--trace-cycles" => options.trace_options.cycles = true,
+                "--trace-disasm" => options.trace_options.disassembly = true,
+                "--disassemble" => {
+                    let bank = args.next().ok_or("Missing the bank for --disassemble")?;
+                    let bank = parse_number(&bank).ok_or(format!("Invalid bank {bank}"))?;
+                    options.disassemble = Some(bank);
+                }
+                "-h" | "--help" => return Err(String::new()),
+                flag if flag.starts_with('-') => return Err(format!("Unknown option {flag}")),
+                _ if options.rom.is_some() => return Err(format!("Unexpected argument {arg}")),
+                _ => options.rom = Some(arg.into()),
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// The patches to apply to the ROM, in order
+    pub fn patch_files(&self) -> Vec<PathBuf> {
+        let mut patches = self.patches.clone();
+
+        if let (true, Some(rom)) = (self.auto_patch, self.rom.as_ref()) {
+            patches.extend(find_patch(rom));
+        }
+
+        patches
+    }
+
+    /// The symbol file given on the command line, or the one next to the ROM
+    pub fn symbol_file(&self) -> Option<PathBuf> {
+        self.symbols.clone().or_else(|| {
+            let path = self.rom.as_ref()?.with_extension("sym");
+            path.is_file().then_some(path)
+        })
+    }
+}
+
+/// Parse a decimal or hexadecimal (`0x` or `$` prefixed) number
+pub fn parse_number(s: &str) -> Option<usize> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix('$')) {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Find the patch next to `rom`, the first of `game.ips`, `game.gb.ips`, then the same
+/// for UPS and BPS
+pub fn find_patch(rom: &Path) -> Option<PathBuf> {
+    PATCH_EXTENSIONS
+        .iter()
+        .flat_map(|ext| {
+            let mut full_name = rom.as_os_str().to_owned();
+            full_name.push(format!(".{ext}"));
+
+            [rom.with_extension(ext), PathBuf::from(full_name)]
+        })
+        .find(|path| path.is_file())
+        .inspect(|path| log::info!("Found patch {path:?}"))
+}
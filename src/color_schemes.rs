@@ -0,0 +1,391 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::{io, path::Path};
+
+use goodboy_core::ppu::{color::Color, ColorScheme, ColorSchemes};
+use serde::Deserialize;
+
+/// A color scheme that can be picked by name
+#[derive(Debug, Clone)]
+pub struct NamedScheme {
+    pub name: String,
+    pub schemes: ColorSchemes,
+}
+
+impl NamedScheme {
+    fn new(name: &str, schemes: impl Into<ColorSchemes>) -> Self {
+        NamedScheme {
+            name: name.to_string(),
+            schemes: schemes.into(),
+        }
+    }
+}
+
+/// The color schemes cycled through while playing
+#[derive(Debug, Clone)]
+pub struct ColorSchemeList {
+    schemes: Vec<NamedScheme>,
+    current: usize,
+}
+
+impl Default for ColorSchemeList {
+    fn default() -> Self {
+        ColorSchemeList {
+            schemes: vec![
+                NamedScheme::new("blue", ColorScheme::BLUE),
+                NamedScheme::new("blue-alt", ColorScheme::BLUE_ALT),
+                NamedScheme::new("green", ColorScheme::GREEN),
+                NamedScheme::new("red", ColorScheme::RED),
+                NamedScheme::new("gray", ColorScheme::GRAY),
+            ],
+            current: 0,
+        }
+    }
+}
+
+impl ColorSchemeList {
+    pub fn current(&self) -> &NamedScheme {
+        &self.schemes[self.current]
+    }
+
+    pub fn select_next(&mut self) -> &NamedScheme {
+        self.current = (self.current + 1) % self.schemes.len();
+        self.current()
+    }
+
+    pub fn select_prev(&mut self) -> &NamedScheme {
+        self.current = (self.current + self.schemes.len() - 1) % self.schemes.len();
+        self.current()
+    }
+
+    /// Make the scheme named `name` the current one, returns false if there is none
+    pub fn select(&mut self, name: &str) -> bool {
+        match self.schemes.iter().position(|scheme| scheme.name == name) {
+            Some(index) => {
+                self.current = index;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Add the schemes after the built-in ones, a scheme replaces the one with the same name
+    pub fn extend(&mut self, schemes: impl IntoIterator<Item = NamedScheme>) {
+        for scheme in schemes {
+            match self.schemes.iter_mut().find(|s| s.name == scheme.name) {
+                Some(old) => *old = scheme,
+                None => self.schemes.push(scheme),
+            }
+        }
+    }
+}
+
+/// A list of color schemes, like
+///
+/// ```toml
+/// [[schemes]]
+/// name = "pocket"
+/// colors = ["#c4cfa1", "#8b956d", "#4d533c", "#1f1f1f"]
+///
+/// [[schemes]]
+/// name = "sgb"
+/// bg = ["#f7e7c6", "#d68e49", "#a63725", "#331e50"]
+/// obj0 = ["#ffffff", "#ff8484", "#943a3a", "#000000"]
+///
+/// [[schemes]]
+/// name = "bgb"
+/// pal = "bgb.pal"
+/// ```
+///
+/// The colors go from the lightest to the darkest
+#[derive(Deserialize)]
+struct SchemesFile {
+    #[serde(default)]
+    schemes: Vec<SchemeEntry>,
+}
+
+#[derive(Deserialize)]
+struct SchemeEntry {
+    name: String,
+    /// The colors of all the palettes
+    colors: Option<[String; 4]>,
+    /// A .pal file for all the palettes, relative to the list
+    pal: Option<String>,
+    /// The colors of a single palette, instead of `colors` or `pal`
+    bg: Option<[String; 4]>,
+    obj0: Option<[String; 4]>,
+    obj1: Option<[String; 4]>,
+}
+
+/// Read the color schemes of a TOML or JSON list, or the single scheme of a .pal file
+#[cfg(not(target_arch = "wasm32"))]
+pub fn load_file(path: impl AsRef<Path>) -> io::Result<Vec<NamedScheme>> {
+    let path = path.as_ref();
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, format!("{path:?}: {e}"));
+    let extension = path.extension().and_then(|ext| ext.to_str());
+
+    if extension == Some("pal") {
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        let schemes = parse_pal(&std::fs::read(path)?).map_err(invalid)?;
+        return Ok(vec![NamedScheme::new(&name, schemes)]);
+    }
+
+    let text = std::fs::read_to_string(path)?;
+    let file: SchemesFile = match extension {
+        Some("json") => serde_json::from_str(&text).map_err(|e| invalid(e.to_string()))?,
+        _ => toml::from_str(&text).map_err(|e| invalid(e.to_string()))?,
+    };
+
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    file.schemes
+        .into_iter()
+        .map(|entry| {
+            let pal = match entry.pal {
+                Some(ref pal) => Some(parse_pal(&std::fs::read(dir.join(pal))?).map_err(invalid)?),
+                None => None,
+            };
+            entry.into_scheme(pal).map_err(invalid)
+        })
+        .collect()
+}
+
+impl SchemeEntry {
+    fn into_scheme(self, pal: Option<ColorSchemes>) -> Result<NamedScheme, String> {
+        let scheme = |colors: Option<[String; 4]>| -> Result<Option<ColorScheme>, String> {
+            colors.map(|colors| parse_scheme(&colors)).transpose()
+        };
+
+        let (colors, bg, obj0, obj1) = (
+            scheme(self.colors)?,
+            scheme(self.bg)?,
+            scheme(self.obj0)?,
+            scheme(self.obj1)?,
+        );
+        let base = pal
+            .or_else(|| colors.map(ColorSchemes::from))
+            .or_else(|| bg.map(ColorSchemes::from))
+            .ok_or_else(|| format!("The scheme {} has no colors", self.name))?;
+
+        let schemes = ColorSchemes {
+            bg: bg.unwrap_or(base.bg),
+            obj0: obj0.unwrap_or(base.obj0),
+            obj1: obj1.unwrap_or(base.obj1),
+        };
+        Ok(NamedScheme::new(&self.name, schemes))
+    }
+}
+
+/// Four colors from the lightest to the darkest
+fn parse_scheme(colors: &[String; 4]) -> Result<ColorScheme, String> {
+    let mut rgb = [0; 4];
+    for (rgb, color) in rgb.iter_mut().zip(colors) {
+        let hex = color
+            .strip_prefix('#')
+            .or_else(|| color.strip_prefix("0x"))
+            .unwrap_or(color);
+        *rgb = match u32::from_str_radix(hex, 16) {
+            Ok(value) if hex.len() == 6 => value,
+            _ => return Err(format!("Invalid color {color}")),
+        };
+    }
+    Ok(scheme_from_rgb(rgb))
+}
+
+fn scheme_from_rgb([white, light_gray, dark_gray, black]: [u32; 4]) -> ColorScheme {
+    ColorScheme::new(
+        Color::new_rgb(black),
+        Color::new_rgb(dark_gray),
+        Color::new_rgb(light_gray),
+        Color::new_rgb(white),
+    )
+}
+
+/// A JASC-PAL text palette, a RIFF palette, or RGB triplets, from the lightest to the
+/// darkest color. 12 colors or more are the BG, OBJ0 and OBJ1 palettes, 4 are used for
+/// all of them
+fn parse_pal(data: &[u8]) -> Result<ColorSchemes, String> {
+    let colors = if data.starts_with(b"RIFF") {
+        parse_riff_pal(data)?
+    } else if data.starts_with(b"JASC-PAL") {
+        let text = std::str::from_utf8(data).map_err(|e| e.to_string())?;
+        text.lines()
+            .skip(3)
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let channels = line
+                    .split_whitespace()
+                    .map(|c| c.parse::<u8>())
+                    .collect::<Result<Vec<_>, _>>();
+                match channels.as_deref() {
+                    Ok([r, g, b, ..]) => Ok(u32::from_be_bytes([0, *r, *g, *b])),
+                    _ => Err(format!("Invalid color {line}")),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?
+    } else if data.len().is_multiple_of(3) {
+        data.chunks_exact(3)
+            .map(|rgb| u32::from_be_bytes([0, rgb[0], rgb[1], rgb[2]]))
+            .collect()
+    } else {
+        return Err("Unknown palette format".to_string());
+    };
+
+    let scheme =
+        |i: usize| scheme_from_rgb([colors[i], colors[i + 1], colors[i + 2], colors[i + 3]]);
+    match colors.len() {
+        12.. => Ok(ColorSchemes {
+            bg: scheme(0),
+            obj0: scheme(4),
+            obj1: scheme(8),
+        }),
+        4.. => Ok(scheme(0).into()),
+        count => Err(format!(
+            "The palette has {count} colors, 4 or 12 are needed"
+        )),
+    }
+}
+
+/// The colors of a Microsoft RIFF palette, a `data` chunk holding a LOGPALETTE: the
+/// version, the number of colors, then the colors as R, G, B and flags bytes
+fn parse_riff_pal(data: &[u8]) -> Result<Vec<u32>, String> {
+    let invalid = || "Invalid RIFF palette".to_string();
+    if data.get(8..12) != Some(b"PAL ") {
+        return Err("The RIFF file is not a palette".to_string());
+    }
+
+    let mut chunks = data.get(12..).ok_or_else(invalid)?;
+    while chunks.len() >= 8 {
+        let (id, size) = (&chunks[..4], &chunks[4..8]);
+        let size = u32::from_le_bytes(size.try_into().unwrap()) as usize;
+        let chunk = chunks.get(8..8 + size).ok_or_else(invalid)?;
+
+        if id == b"data" {
+            let count = usize::from(u16::from_le_bytes(
+                chunk.get(2..4).ok_or_else(invalid)?.try_into().unwrap(),
+            ));
+            let entries = chunk.get(4..4 + count * 4).ok_or_else(invalid)?;
+            return Ok(entries
+                .chunks_exact(4)
+                .map(|rgbf| u32::from_be_bytes([0, rgbf[0], rgbf[1], rgbf[2]]))
+                .collect());
+        }
+
+        // the chunks are padded to an even size
+        chunks = chunks.get(8 + size + size % 2..).unwrap_or_default();
+    }
+    Err(invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use goodboy_core::ppu::color::Rgba;
+
+    use super::*;
+
+    /// The colors of `scheme` from the lightest to the darkest
+    fn rgb(scheme: ColorScheme) -> [u32; 4] {
+        [
+            scheme.white,
+            scheme.light_gray,
+            scheme.dark_gray,
+            scheme.black,
+        ]
+        .map(|color| match color {
+            Color::Rgba(Rgba { r, g, b, .. }) => u32::from_be_bytes([0, r, g, b]),
+            Color::Rgb555(_) => panic!("Not a DMG color"),
+        })
+    }
+
+    fn strings(colors: [&str; 4]) -> [String; 4] {
+        colors.map(str::to_string)
+    }
+
+    const POCKET: [u32; 4] = [0xC4CFA1, 0x8B956D, 0x4D533C, 0x1F1F1F];
+
+    #[test]
+    fn parse_hex_colors() {
+        let scheme = parse_scheme(&strings(["#c4cfa1", "0x8B956D", "4d533c", "#1F1F1F"])).unwrap();
+        assert_eq!(rgb(scheme), POCKET);
+
+        for invalid in ["#fff", "#c4cfa1ff", "pocket"] {
+            let colors = strings(["#c4cfa1", "#8b956d", "#4d533c", invalid]);
+            assert_eq!(
+                parse_scheme(&colors).unwrap_err(),
+                format!("Invalid color {invalid}")
+            );
+        }
+    }
+
+    #[test]
+    fn parse_pal_formats() {
+        let jasc =
+            "JASC-PAL\r\n0100\r\n4\r\n196 207 161\r\n139 149 109\r\n77 83 60\r\n31 31 31\r\n";
+        assert_eq!(rgb(parse_pal(jasc.as_bytes()).unwrap().obj1), POCKET);
+
+        let triplets = POCKET
+            .iter()
+            .flat_map(|color| color.to_be_bytes()[1..].to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(rgb(parse_pal(&triplets).unwrap().bg), POCKET);
+
+        let mut riff = b"RIFF\0\0\0\0PAL data".to_vec();
+        riff.extend(20u32.to_le_bytes());
+        riff.extend([0x00, 0x03, 0x04, 0x00]);
+        for color in POCKET {
+            let [_, r, g, b] = color.to_be_bytes();
+            riff.extend([r, g, b, 0]);
+        }
+        assert_eq!(rgb(parse_pal(&riff).unwrap().obj0), POCKET);
+
+        let schemes = parse_pal(&[triplets.clone(), vec![0; 24]].concat()).unwrap();
+        assert_eq!(rgb(schemes.bg), POCKET);
+        assert_eq!(rgb(schemes.obj0), [0; 4]);
+
+        assert!(parse_pal(b"RIFF\0\0\0\0WAVEfmt ").is_err());
+        assert!(parse_pal(&triplets[..9]).is_err());
+        assert!(parse_pal(&triplets[..11]).is_err());
+    }
+
+    #[test]
+    fn scheme_entries() {
+        type Colors<'a> = Option<[&'a str; 4]>;
+        let entry = |colors: Colors, pal: Option<&str>, bg: Colors, obj0: Colors| SchemeEntry {
+            name: "pocket".to_string(),
+            colors: colors.map(strings),
+            pal: pal.map(str::to_string),
+            bg: bg.map(strings),
+            obj0: obj0.map(strings),
+            obj1: None,
+        };
+        let pocket = ["#c4cfa1", "#8b956d", "#4d533c", "#1f1f1f"];
+        let white = ["#ffffff", "#ffffff", "#ffffff", "#ffffff"];
+
+        let scheme = entry(Some(pocket), None, None, Some(white))
+            .into_scheme(None)
+            .unwrap();
+        assert_eq!(scheme.name, "pocket");
+        assert_eq!(rgb(scheme.schemes.bg), POCKET);
+        assert_eq!(rgb(scheme.schemes.obj0), [0xFFFFFF; 4]);
+        assert_eq!(rgb(scheme.schemes.obj1), POCKET);
+
+        // a single palette is used for the others
+        let scheme = entry(None, None, Some(white), None)
+            .into_scheme(None)
+            .unwrap();
+        assert_eq!(rgb(scheme.schemes.obj1), [0xFFFFFF; 4]);
+
+        // the .pal file replaces `colors`, `bg` still overrides it
+        let pal = ColorSchemes::from(scheme_from_rgb(POCKET));
+        let scheme = entry(Some(white), Some("pocket.pal"), Some(white), None)
+            .into_scheme(Some(pal))
+            .unwrap();
+        assert_eq!(rgb(scheme.schemes.bg), [0xFFFFFF; 4]);
+        assert_eq!(rgb(scheme.schemes.obj0), POCKET);
+
+        assert_eq!(
+            entry(None, None, None, Some(white))
+                .into_scheme(None)
+                .unwrap_err(),
+            "The scheme pocket has no colors"
+        );
+    }
+}
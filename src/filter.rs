@@ -0,0 +1,467 @@
+use std::{fmt, str::FromStr};
+
+use goodboy_core::vm::{SCREEN_HEIGHT as HEIGHT, SCREEN_WIDTH as WIDTH};
+
+type Rgba = [u8; 4];
+
+/// The brightness of the gaps of the LCD grid and of the scanlines, in 1/256
+const GRID_BRIGHTNESS: u32 = 180;
+const SCANLINE_BRIGHTNESS: u32 = 128;
+
+/// How the screen is drawn in the window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Filter {
+    /// The pixels as they are
+    #[default]
+    None,
+    /// The dot matrix of the DMG LCD, a dark gap between the pixels
+    LcdGrid,
+    /// A dark line under every line, like a CRT
+    Scanlines,
+    Scale2x,
+    Scale3x,
+    Hq2x,
+    /// The edge detection of xBR, level 1 at 2x
+    Xbr,
+}
+
+impl Filter {
+    pub const ALL: [Filter; 7] = [
+        Filter::None,
+        Filter::LcdGrid,
+        Filter::Scanlines,
+        Filter::Scale2x,
+        Filter::Scale3x,
+        Filter::Hq2x,
+        Filter::Xbr,
+    ];
+
+    /// The output pixels per screen pixel, in each direction
+    pub fn scale(self) -> usize {
+        match self {
+            Filter::None => 1,
+            Filter::Scale2x | Filter::Hq2x | Filter::Xbr => 2,
+            Filter::LcdGrid | Filter::Scanlines | Filter::Scale3x => 3,
+        }
+    }
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+
+    pub fn prev(self) -> Self {
+        Self::ALL[(self as usize + Self::ALL.len() - 1) % Self::ALL.len()]
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Filter::None => "none",
+            Filter::LcdGrid => "lcd",
+            Filter::Scanlines => "scanlines",
+            Filter::Scale2x => "scale2x",
+            Filter::Scale3x => "scale3x",
+            Filter::Hq2x => "hq2x",
+            Filter::Xbr => "xbr",
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|filter| filter.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("Unknown filter {s}"))
+    }
+}
+
+/// Draw the screens received from the emulation through a filter, optionally blended
+/// with the previous screen
+pub struct FilterPipeline {
+    filter: Filter,
+    /// Blend every screen with the previous one, the slow LCD of the DMG shows the
+    /// sprites drawn every other frame as transparent
+    ghosting: bool,
+    screen: Vec<u8>,
+    previous: Vec<u8>,
+    /// The screen blended with the previous one, when ghosting
+    blended: Vec<u8>,
+}
+
+impl FilterPipeline {
+    pub fn new(filter: Filter, ghosting: bool) -> Self {
+        FilterPipeline {
+            filter,
+            ghosting,
+            screen: vec![0; WIDTH * HEIGHT * 4],
+            previous: vec![0; WIDTH * HEIGHT * 4],
+            blended: vec![0; WIDTH * HEIGHT * 4],
+        }
+    }
+
+    pub fn filter(&self) -> Filter {
+        self.filter
+    }
+
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = filter;
+    }
+
+    pub fn ghosting(&self) -> bool {
+        self.ghosting
+    }
+
+    pub fn set_ghosting(&mut self, ghosting: bool) {
+        self.ghosting = ghosting;
+    }
+
+    /// The size of the frame drawn by [`FilterPipeline::render`]
+    pub fn buffer_size(&self) -> (u32, u32) {
+        let scale = self.filter.scale();
+        ((WIDTH * scale) as u32, (HEIGHT * scale) as u32)
+    }
+
+    /// Keep a new screen from the emulation
+    pub fn push_screen(&mut self, screen: &[u8]) {
+        std::mem::swap(&mut self.screen, &mut self.previous);
+        self.screen.copy_from_slice(screen);
+    }
+
+    /// Draw the last screen in `frame`, of [`FilterPipeline::buffer_size`]
+    pub fn render(&mut self, frame: &mut [u8]) {
+        let screen = if self.ghosting {
+            for ((blended, &a), &b) in self
+                .blended
+                .iter_mut()
+                .zip(&self.screen)
+                .zip(&self.previous)
+            {
+                *blended = ((u16::from(a) + u16::from(b)) / 2) as u8;
+            }
+            Screen(&self.blended)
+        } else {
+            Screen(&self.screen)
+        };
+
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                match self.filter {
+                    Filter::None => put_block(frame, x, y, [screen.at(x, y, 0, 0)]),
+                    Filter::LcdGrid => put_block(frame, x, y, lcd_grid(&screen, x, y)),
+                    Filter::Scanlines => put_block(frame, x, y, scanlines(&screen, x, y)),
+                    Filter::Scale2x => put_block(frame, x, y, scale2x(&screen, x, y)),
+                    Filter::Scale3x => put_block(frame, x, y, scale3x(&screen, x, y)),
+                    Filter::Hq2x => put_block(frame, x, y, hq2x(&screen, x, y)),
+                    Filter::Xbr => put_block(frame, x, y, xbr(&screen, x, y)),
+                }
+            }
+        }
+    }
+}
+
+/// Draw the output pixels of the screen pixel at `x`, `y`, a square block given row by row
+fn put_block<const N: usize>(frame: &mut [u8], x: usize, y: usize, block: [Rgba; N]) {
+    let scale = N.isqrt();
+    let out_width = WIDTH * scale;
+
+    for (i, rgba) in block.into_iter().enumerate() {
+        let (bx, by) = (i % scale, i / scale);
+        let offset = ((y * scale + by) * out_width + x * scale + bx) * 4;
+        frame[offset..offset + 4].copy_from_slice(&rgba);
+    }
+}
+
+/// The screen pixels, the edges are repeated past the borders
+struct Screen<'a>(&'a [u8]);
+
+impl Screen<'_> {
+    fn at(&self, x: usize, y: usize, dx: isize, dy: isize) -> Rgba {
+        let x = x.saturating_add_signed(dx).min(WIDTH - 1);
+        let y = y.saturating_add_signed(dy).min(HEIGHT - 1);
+        let i = (y * WIDTH + x) * 4;
+        [self.0[i], self.0[i + 1], self.0[i + 2], self.0[i + 3]]
+    }
+}
+
+fn darken(rgba: Rgba, brightness: u32) -> Rgba {
+    let [r, g, b, a] = rgba;
+    let [r, g, b] = [r, g, b].map(|c| (u32::from(c) * brightness / 256) as u8);
+    [r, g, b, a]
+}
+
+/// The weighted average of `colors`
+fn mix(colors: &[(Rgba, u32)]) -> Rgba {
+    let total = colors.iter().map(|&(_, weight)| weight).sum::<u32>();
+    let mut rgba = [0; 4];
+    for (i, channel) in rgba.iter_mut().enumerate() {
+        let sum = colors
+            .iter()
+            .map(|&(color, weight)| u32::from(color[i]) * weight)
+            .sum::<u32>();
+        *channel = (sum / total) as u8;
+    }
+    rgba
+}
+
+fn yuv(rgba: Rgba) -> [i32; 3] {
+    let [r, g, b, _] = rgba.map(i32::from);
+    let y = (r * 299 + g * 587 + b * 114) / 1000;
+    [y, (b - y) * 492 / 1000, (r - y) * 877 / 1000]
+}
+
+/// The colors are far apart by the thresholds of hqx
+fn yuv_differ(a: Rgba, b: Rgba) -> bool {
+    let ([ya, ua, va], [yb, ub, vb]) = (yuv(a), yuv(b));
+    (ya - yb).abs() > 48 || (ua - ub).abs() > 7 || (va - vb).abs() > 6
+}
+
+/// The distance between two colors used by xBR
+fn xbr_distance(a: Rgba, b: Rgba) -> i32 {
+    let ([ya, ua, va], [yb, ub, vb]) = (yuv(a), yuv(b));
+    (ya - yb).abs() * 48 + (ua - ub).abs() * 7 + (va - vb).abs() * 6
+}
+
+fn lcd_grid(screen: &Screen, x: usize, y: usize) -> [Rgba; 9] {
+    let e = screen.at(x, y, 0, 0);
+    std::array::from_fn(|i| match (i % 3, i / 3) {
+        (2, _) | (_, 2) => darken(e, GRID_BRIGHTNESS),
+        _ => e,
+    })
+}
+
+fn scanlines(screen: &Screen, x: usize, y: usize) -> [Rgba; 9] {
+    let e = screen.at(x, y, 0, 0);
+    std::array::from_fn(|i| match i / 3 {
+        2 => darken(e, SCANLINE_BRIGHTNESS),
+        _ => e,
+    })
+}
+
+/// Scale2x (AdvMAME2x), copies the neighbors along the diagonal edges
+fn scale2x(screen: &Screen, x: usize, y: usize) -> [Rgba; 4] {
+    let p = |dx, dy| screen.at(x, y, dx, dy);
+    let (b, d, e, f, h) = (p(0, -1), p(-1, 0), p(0, 0), p(1, 0), p(0, 1));
+
+    if b == h || d == f {
+        return [e; 4];
+    }
+    [
+        if d == b { d } else { e },
+        if b == f { f } else { e },
+        if d == h { d } else { e },
+        if h == f { f } else { e },
+    ]
+}
+
+/// Scale3x (AdvMAME3x)
+#[rustfmt::skip]
+fn scale3x(screen: &Screen, x: usize, y: usize) -> [Rgba; 9] {
+    let p = |dx, dy| screen.at(x, y, dx, dy);
+    let (a, b, c) = (p(-1, -1), p(0, -1), p(1, -1));
+    let (d, e, f) = (p(-1, 0), p(0, 0), p(1, 0));
+    let (g, h, i) = (p(-1, 1), p(0, 1), p(1, 1));
+
+    if b == h || d == f {
+        return [e; 9];
+    }
+    [
+        if d == b { d } else { e },
+        if (d == b && e != c) || (b == f && e != a) { b } else { e },
+        if b == f { f } else { e },
+        if (d == b && e != g) || (d == h && e != a) { d } else { e },
+        e,
+        if (b == f && e != i) || (h == f && e != c) { f } else { e },
+        if d == h { d } else { e },
+        if (d == h && e != i) || (h == f && e != g) { h } else { e },
+        if h == f { f } else { e },
+    ]
+}
+
+/// hq2x, each quarter of the pixel is interpolated by the pattern of the neighbors that
+/// differ by the YUV thresholds. The 256 cases of the original table are written as the
+/// rules of the top left quarter, like FFmpeg's hqx filter, the other quarters are flipped.
+#[rustfmt::skip]
+fn hq2x(screen: &Screen, x: usize, y: usize) -> [Rgba; 4] {
+    [(1, 1), (-1, 1), (1, -1), (-1, -1)].map(|(fx, fy)| {
+        let w: [Rgba; 9] = std::array::from_fn(|i| {
+            let (u, v) = (i as isize % 3 - 1, i as isize / 3 - 1);
+            screen.at(x, y, u * fx, v * fy)
+        });
+        let [w0, w1, _, w3, w4, w5, _, w7, _] = w;
+
+        // a bit per neighbor, the center excluded
+        let k = [0, 1, 2, 3, 5, 6, 7, 8]
+            .into_iter()
+            .enumerate()
+            .filter(|&(_, n)| yuv_differ(w4, w[n]))
+            .fold(0u8, |k, (bit, _)| k | 1 << bit);
+        let p = |patterns: &[(u8, u8)]| patterns.iter().any(|&(mask, value)| k & mask == value);
+
+        if p(&[(0xBF, 0x37), (0xDB, 0x13)]) && yuv_differ(w1, w5) {
+            mix(&[(w4, 3), (w3, 1)])
+        } else if p(&[(0xDB, 0x49), (0xEF, 0x6D)]) && yuv_differ(w7, w3) {
+            mix(&[(w4, 3), (w1, 1)])
+        } else if p(&[(0x0B, 0x0B), (0xFE, 0x4A), (0xFE, 0x1A)]) && yuv_differ(w3, w1) {
+            w4
+        } else if p(&[
+            (0x6F, 0x2A), (0x5B, 0x0A), (0xBF, 0x3A), (0xDF, 0x5A), (0x9F, 0x8A),
+            (0xCF, 0x8A), (0xEF, 0x4E), (0x3F, 0x0E), (0xFB, 0x5A), (0xBB, 0x8A),
+            (0x7F, 0x5A), (0xAF, 0x8A), (0xEB, 0x8A),
+        ]) && yuv_differ(w3, w1)
+        {
+            mix(&[(w4, 3), (w0, 1)])
+        } else if p(&[(0x0B, 0x08)]) {
+            mix(&[(w4, 2), (w0, 1), (w1, 1)])
+        } else if p(&[(0x0B, 0x02)]) {
+            mix(&[(w4, 2), (w0, 1), (w3, 1)])
+        } else if p(&[(0x2F, 0x2F)]) {
+            mix(&[(w4, 14), (w3, 1), (w1, 1)])
+        } else if p(&[(0xBF, 0x37), (0xDB, 0x13)]) {
+            mix(&[(w4, 5), (w1, 2), (w3, 1)])
+        } else if p(&[(0xDB, 0x49), (0xEF, 0x6D)]) {
+            mix(&[(w4, 5), (w3, 2), (w1, 1)])
+        } else if p(&[(0x1B, 0x03), (0x4F, 0x43), (0x8B, 0x83), (0x6B, 0x43)]) {
+            mix(&[(w4, 3), (w3, 1)])
+        } else if p(&[(0x4B, 0x09), (0x8B, 0x89), (0x1F, 0x19), (0x3B, 0x19)]) {
+            mix(&[(w4, 3), (w1, 1)])
+        } else if p(&[(0x7E, 0x2A), (0xEF, 0xAB), (0xBF, 0x8F), (0x7E, 0x0E)]) {
+            mix(&[(w4, 2), (w3, 3), (w1, 3)])
+        } else if p(&[
+            (0xFB, 0x6A), (0x6F, 0x6E), (0x3F, 0x3E), (0xFB, 0xFA), (0xDF, 0xDE), (0xDF, 0x1E),
+        ]) {
+            mix(&[(w4, 3), (w0, 1)])
+        } else if p(&[
+            (0x0A, 0x00), (0x4F, 0x4B), (0x9F, 0x1B), (0x2F, 0x0B), (0xBE, 0x0A), (0xEE, 0x0A),
+            (0x7E, 0x0A), (0xEB, 0x4B), (0x3B, 0x1B),
+        ]) {
+            mix(&[(w4, 2), (w3, 1), (w1, 1)])
+        } else {
+            mix(&[(w4, 6), (w3, 1), (w1, 1)])
+        }
+    })
+}
+
+/// xBR level 1, each quarter of the pixel is blended with the color across the edge
+/// when the edge along the corner is smoother than the one across it
+fn xbr(screen: &Screen, x: usize, y: usize) -> [Rgba; 4] {
+    [(-1, -1), (1, -1), (-1, 1), (1, 1)].map(|(dx, dy)| {
+        // the neighborhood turned so the corner is at the bottom right
+        let p = |u: isize, v: isize| screen.at(x, y, u * dx, v * dy);
+        let (e, f, h, i) = (p(0, 0), p(1, 0), p(0, 1), p(1, 1));
+        let d = xbr_distance;
+
+        let along = d(e, p(1, -1)) + d(e, p(-1, 1)) + d(i, p(2, 0)) + d(i, p(0, 2)) + 4 * d(h, f);
+        let across = d(h, p(-1, 0)) + d(h, p(1, 2)) + d(f, p(2, 1)) + d(f, p(0, -1)) + 4 * d(e, i);
+
+        if along < across {
+            let new = if d(e, f) <= d(e, h) { f } else { h };
+            mix(&[(e, 1), (new, 1)])
+        } else {
+            e
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const W: Rgba = [0xFF, 0xFF, 0xFF, 0xFF];
+    const B: Rgba = [0x00, 0x00, 0x00, 0xFF];
+
+    /// A white screen, black below the diagonal edge `x + y = 2`
+    fn diagonal_edge() -> Vec<u8> {
+        (0..WIDTH * HEIGHT)
+            .flat_map(|i| match (i % WIDTH) + (i / WIDTH) {
+                0..=1 => W,
+                _ => B,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn scale2x_diagonal_edge() {
+        let pixels = diagonal_edge();
+        let screen = Screen(&pixels);
+
+        // the corner on the edge takes the color of its neighbors
+        assert_eq!(scale2x(&screen, 1, 1), [W, B, B, B]);
+        assert_eq!(scale2x(&screen, 1, 0), [W, W, W, B]);
+        assert_eq!(scale2x(&screen, 5, 5), [B; 4]);
+    }
+
+    #[test]
+    fn scale3x_diagonal_edge() {
+        let pixels = diagonal_edge();
+        let screen = Screen(&pixels);
+
+        #[rustfmt::skip]
+        assert_eq!(scale3x(&screen, 1, 1), [
+            W, B, B,
+            B, B, B,
+            B, B, B,
+        ]);
+        #[rustfmt::skip]
+        assert_eq!(scale3x(&screen, 1, 0), [
+            W, W, W,
+            W, W, B,
+            W, W, B,
+        ]);
+        assert_eq!(scale3x(&screen, 5, 5), [B; 9]);
+    }
+
+    #[test]
+    fn hq2x_diagonal_edge() {
+        let pixels = diagonal_edge();
+        let screen = Screen(&pixels);
+        let gray = |c| [c, c, c, 0xFF];
+
+        // the corner on the edge is blended, the sides of the edge are shaded
+        assert_eq!(hq2x(&screen, 1, 1), [gray(0x7F), B, B, B]);
+        assert_eq!(hq2x(&screen, 1, 0), [W, gray(0xBF), W, gray(0x3F)]);
+        assert_eq!(hq2x(&screen, 0, 1), [W, W, gray(0xBF), gray(0x3F)]);
+        assert_eq!(hq2x(&screen, 5, 5), [B; 4]);
+    }
+
+    #[test]
+    fn xbr_diagonal_edge() {
+        let pixels = diagonal_edge();
+        let screen = Screen(&pixels);
+        let gray = [0x7F, 0x7F, 0x7F, 0xFF];
+
+        // the corners across the edge are blended half and half
+        assert_eq!(xbr(&screen, 1, 1), [gray, B, B, B]);
+        assert_eq!(xbr(&screen, 1, 0), [W, W, W, gray]);
+        assert_eq!(xbr(&screen, 5, 5), [B; 4]);
+    }
+
+    #[test]
+    fn render_blocks_and_ghosting() {
+        let mut pipeline = FilterPipeline::new(Filter::Scale2x, false);
+        pipeline.push_screen(&diagonal_edge());
+
+        let (width, height) = pipeline.buffer_size();
+        let mut frame = vec![0; width as usize * height as usize * 4];
+        pipeline.render(&mut frame);
+
+        let out = |x: usize, y: usize| -> Rgba {
+            let i = (y * width as usize + x) * 4;
+            frame[i..i + 4].try_into().unwrap()
+        };
+        assert_eq!([out(2, 2), out(3, 2), out(2, 3), out(3, 3)], [W, B, B, B]);
+
+        pipeline.set_filter(Filter::None);
+        pipeline.set_ghosting(true);
+        pipeline.push_screen(&vec![0; WIDTH * HEIGHT * 4]);
+        pipeline.render(&mut frame);
+        assert_eq!(frame[..4], [0x7F; 4]);
+    }
+}
@@ -1,16 +1,50 @@
-#[cfg(not(target_arch = "wasm32"))]
-use std::path::Path;
 use std::sync::mpsc::{self, Receiver, SyncSender};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
 
 use goodboy_core::vm::{Screen, Vm};
+#[cfg(not(target_arch = "wasm32"))]
+use goodboy_core::{
+    cheats::Cheat,
+    cpu::{
+        profiler::Profiler,
+        trace::{TraceOptions, Tracer},
+    },
+    mmu::patch::Patch,
+    ppu::{ColorCorrection, ColorSchemes},
+    symbols::Symbols,
+};
 
 #[cfg(not(target_arch = "wasm32"))]
-use crate::io::IoEvent;
+use crate::{io::IoEvent, repl::Repl};
 
 pub struct GameBoy {
     pub vm: Option<Vm>,
     pub screen_tx: SyncSender<Screen>,
     pub screen_rx: Option<Receiver<Screen>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    repl: Option<Repl>,
+    /// Where to write the profiler report when the emulation stops
+    #[cfg(not(target_arch = "wasm32"))]
+    profile_path: Option<PathBuf>,
+    /// Where to write the Code/Data Log when the emulation stops
+    #[cfg(not(target_arch = "wasm32"))]
+    cdl_path: Option<PathBuf>,
+    /// How the CGB colors are shown, kept when another game is loaded
+    #[cfg(not(target_arch = "wasm32"))]
+    color_correction: ColorCorrection,
+    /// The DMG colors, kept when another game is loaded
+    #[cfg(not(target_arch = "wasm32"))]
+    color_schemes: ColorSchemes,
+}
+
+impl Default for GameBoy {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl GameBoy {
@@ -21,27 +55,206 @@ impl GameBoy {
             vm: None,
             screen_tx,
             screen_rx: Some(screen_rx),
+            #[cfg(not(target_arch = "wasm32"))]
+            repl: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            profile_path: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            cdl_path: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            color_correction: ColorCorrection::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            color_schemes: ColorSchemes::default(),
         }
     }
     pub fn game_title(&self) -> Option<String> {
-        self.vm
-            .as_ref()
-            .map_or(None, |vm| Some(vm.game_title().to_string()))
+        self.vm.as_ref().map(|vm| vm.game_title().to_string())
     }
 
     #[cfg(not(target_arch = "wasm32"))]
     pub fn load_game(&mut self, game_data: &[u8]) {
         let new_vm = Vm::new(game_data);
-        let _ = self.vm.insert(new_vm);
+        self.insert_vm(new_vm);
+    }
+
+    /// Replace the current game, keeping the color settings. The cheats, symbols, trace,
+    /// profiler and CDL belong to the game loaded at startup, they don't carry over. The
+    /// profile and the CDL are written before the game is replaced.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn insert_vm(&mut self, vm: Vm) {
+        if let Err(e) = self.write_profile() {
+            log::error!("Could not write the profile: {e}");
+        }
+        if let Err(e) = self.write_cdl() {
+            log::error!("Could not write the Code/Data Log: {e}");
+        }
+        self.profile_path = None;
+        self.cdl_path = None;
+
+        let vm = self.vm.insert(vm);
+        vm.set_color_correction(self.color_correction);
+        vm.set_color_schemes(self.color_schemes);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn vm_mut(&mut self) -> io::Result<&mut Vm> {
+        self.vm
+            .as_mut()
+            .ok_or_else(|| io::Error::other("No game loaded"))
+    }
+
+    /// Show the CGB colors as on a CGB, a GBA, or with the raw values
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn set_color_correction(&mut self, correction: ColorCorrection) {
+        self.color_correction = correction;
+        if let Some(vm) = self.vm.as_mut() {
+            vm.set_color_correction(correction);
+        }
     }
 
     #[cfg(not(target_arch = "wasm32"))]
-    pub fn load_game_file(&mut self, path: impl AsRef<Path>) -> std::io::Result<()> {
+    pub fn load_game_file(
+        &mut self,
+        path: impl AsRef<Path>,
+        patch_paths: &[impl AsRef<Path>],
+    ) -> io::Result<()> {
         let game_data = std::fs::read(path)?;
-        self.load_game(&game_data);
+        let patches = Self::read_patch_files(patch_paths)?;
+
+        let new_vm = Vm::with_patches(&game_data, &patches)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.insert_vm(new_vm);
+        Ok(())
+    }
+
+    /// Read IPS, UPS or BPS patches, in order
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read_patch_files(paths: &[impl AsRef<Path>]) -> io::Result<Vec<Patch>> {
+        paths
+            .iter()
+            .map(|path| {
+                let path = path.as_ref();
+                log::info!("Loading patch {path:?}");
+
+                Patch::new(std::fs::read(path)?).map_err(|e| {
+                    io::Error::new(io::ErrorKind::InvalidData, format!("{path:?}: {e}"))
+                })
+            })
+            .collect()
+    }
+
+    /// Load a cheat list into the current game, returns the number of cheats loaded
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_cheats_file(&mut self, path: impl AsRef<Path>) -> io::Result<usize> {
+        let vm = self.vm_mut()?;
+
+        let list = std::fs::read_to_string(path)?;
+        let cheats =
+            Cheat::parse_list(&list).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let count = cheats.len();
+        for cheat in cheats {
+            vm.add_cheat_entry(cheat);
+        }
+        Ok(count)
+    }
+
+    /// Read a RGBDS symbol file
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read_symbols_file(path: impl AsRef<Path>) -> io::Result<Symbols> {
+        let sym = std::fs::read_to_string(path)?;
+        Symbols::parse(&sym).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Use the labels of a RGBDS symbol file to debug the current game
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_symbols_file(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let vm = self.vm_mut()?;
+
+        vm.set_symbols(Self::read_symbols_file(path)?);
+        Ok(())
+    }
+
+    /// Log every instruction executed by the current game to a file
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn start_trace(&mut self, path: impl AsRef<Path>, options: TraceOptions) -> io::Result<()> {
+        let vm = self.vm_mut()?;
+
+        vm.set_tracer(Some(Tracer::to_file(path, options)?));
+        Ok(())
+    }
+
+    /// Profile the current game, the report is written to `path` and the folded stacks
+    /// to `path.folded` when the emulation stops
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn start_profile(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let vm = self.vm_mut()?;
+
+        vm.set_profiler(Some(Profiler::new()));
+        self.profile_path = Some(path.as_ref().to_path_buf());
+        Ok(())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn write_profile(&self) -> io::Result<()> {
+        let (Some(path), Some(vm)) = (self.profile_path.as_ref(), self.vm.as_ref()) else {
+            return Ok(());
+        };
+        let Some(profiler) = vm.profiler() else {
+            return Ok(());
+        };
+
+        let mut folded_path = path.as_os_str().to_owned();
+        folded_path.push(".folded");
+
+        std::fs::write(path, profiler.report(vm.symbols()))?;
+        std::fs::write(folded_path, profiler.folded(vm.symbols()))?;
+        log::info!("Wrote the profile to {path:?}");
+        Ok(())
+    }
+
+    /// Log how the current game uses the ROM and the RAM, the log is written to `path`
+    /// in BizHawk's CDL format when the emulation stops. An existing log at `path` is
+    /// continued.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn start_cdl(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let vm = self.vm_mut()?;
+
+        let path = path.as_ref();
+        let mut cdl = vm.new_cdl();
+        if path.is_file() {
+            let log = std::fs::read(path)?;
+            cdl.load_file(&log).map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("{path:?}: {e}"))
+            })?;
+            log::info!("Continuing the log {path:?}");
+        }
+
+        vm.set_cdl(Some(cdl));
+        self.cdl_path = Some(path.to_path_buf());
         Ok(())
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
+    fn write_cdl(&self) -> io::Result<()> {
+        let (Some(path), Some(vm)) = (self.cdl_path.as_ref(), self.vm.as_ref()) else {
+            return Ok(());
+        };
+        let Some(cdl) = vm.cdl() else {
+            return Ok(());
+        };
+
+        std::fs::write(path, cdl.to_file())?;
+        log::info!("Wrote the Code/Data Log to {path:?}");
+        Ok(())
+    }
+
+    /// Pause the game and drive it with the debugger commands typed on stdin
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn start_repl(&mut self) {
+        let _ = self.repl.insert(Repl::spawn());
+    }
+
     #[cfg(not(target_arch = "wasm32"))]
     pub fn run(mut self, io_rx: Receiver<IoEvent>) -> Receiver<Screen> {
         let screen_rx = self.screen_rx.take().unwrap();
@@ -68,7 +281,17 @@ impl GameBoy {
 
             if let Some(vm) = self.vm.as_mut() {
                 while clocks < total_clocks {
-                    clocks += vm.tick();
+                    clocks += match self.repl.as_mut() {
+                        Some(repl) if repl.paused() => break,
+                        Some(repl) => {
+                            let (elapsed, stop) = vm.tick_checked();
+                            if let Some(reason) = stop {
+                                repl.stop(vm, reason);
+                            }
+                            elapsed
+                        }
+                        None => vm.tick(),
+                    };
 
                     if vm.check_vblank() {
                         if let Err(mpsc::TrySendError::Disconnected(..)) =
@@ -78,30 +301,44 @@ impl GameBoy {
                         }
                     }
                 }
-                clocks -= total_clocks;
+                clocks = clocks.saturating_sub(total_clocks);
             }
 
             loop {
                 match io_rx.try_recv() {
                     Ok(event) => match event {
                         IoEvent::ButtonPressed(button) => {
-                            self.vm.as_mut().map(|vm| vm.press_button(button));
+                            if let Some(vm) = self.vm.as_mut() {
+                                vm.press_button(button)
+                            }
                         }
                         IoEvent::ButtonReleased(button) => {
-                            self.vm.as_mut().map(|vm| vm.release_button(button));
+                            if let Some(vm) = self.vm.as_mut() {
+                                vm.release_button(button)
+                            }
                         }
                         IoEvent::InsertCartridge(cart) => {
-                            let _ = self.vm.insert(Vm::from_cartridge(cart));
+                            self.insert_vm(Vm::from_cartridge(cart));
                             clocks = 0;
                             break;
                         }
-                        // IoEvent::SetColorScheme(color_scheme) => vm.set_color_scheme(color_scheme),
+                        IoEvent::SetColorScheme(color_schemes) => {
+                            self.color_schemes = color_schemes;
+                            if let Some(vm) = self.vm.as_mut() {
+                                vm.set_color_schemes(color_schemes);
+                            }
+                        }
                         IoEvent::SwitchSpeedNext => {
                             time_cycle.nth(0);
                         }
                         IoEvent::SwitchSpeedPrev => {
                             time_cycle.nth(2);
                         }
+                        IoEvent::ToggleLayer(layer) => {
+                            if let Some(vm) = self.vm.as_mut() {
+                                vm.set_layers(vm.layers() ^ layer)
+                            }
+                        }
                         IoEvent::Exit => break 'vm,
                         // _ => {}
                     },
@@ -110,7 +347,18 @@ impl GameBoy {
                 }
             }
 
+            if let Some(repl) = self.repl.as_mut() {
+                repl.process(self.vm.as_mut());
+            }
+
             timer.await.ok();
         }
+
+        if let Err(e) = self.write_profile() {
+            log::error!("Could not write the profile: {e}");
+        }
+        if let Err(e) = self.write_cdl() {
+            log::error!("Could not write the Code/Data Log: {e}");
+        }
     }
 }
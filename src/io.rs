@@ -1,24 +1,35 @@
 #![allow(dead_code)]
 
+#[cfg(not(target_arch = "wasm32"))]
+use std::{io, path::Path};
 use std::{
     ops::{Deref, DerefMut},
     sync::mpsc,
 };
 
-use goodboy_core::{io::JoypadButton, mmu::cartridge::Cartridge};
-use winit::event::VirtualKeyCode;
+use goodboy_core::{
+    io::JoypadButton,
+    mmu::cartridge::Cartridge,
+    ppu::{ColorSchemes, Layers},
+};
 use winit_input_helper::WinitInputHelper;
 
+use crate::bindings::{Bindings, Hotkey};
+use crate::color_schemes::ColorSchemeList;
 use crate::utils;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{cli, GameBoy};
 
 #[allow(dead_code)]
 pub enum IoEvent {
     ButtonPressed(JoypadButton),
     ButtonReleased(JoypadButton),
     InsertCartridge(Cartridge),
-    // SetColorScheme(ColorScheme),
+    SetColorScheme(ColorSchemes),
     SwitchSpeedNext,
     SwitchSpeedPrev,
+    /// Show or hide the background, the window or the sprites
+    ToggleLayer(Layers),
     Exit,
 }
 
@@ -26,6 +37,12 @@ pub enum IoEvent {
 pub struct IoHandler {
     input: WinitInputHelper,
     pub sender: mpsc::Sender<IoEvent>,
+    /// The keys of the buttons and the hotkeys
+    bindings: Bindings,
+    /// The DMG colors cycled with Tab
+    color_schemes: ColorSchemeList,
+    /// Apply the patch found next to the games opened with Esc
+    auto_patch: bool,
 }
 
 impl IoHandler {
@@ -37,77 +54,86 @@ impl IoHandler {
             Self {
                 input,
                 sender: io_tx,
+                bindings: Bindings::default(),
+                color_schemes: ColorSchemeList::default(),
+                auto_patch: false,
             },
             io_rx,
         )
     }
 
-    pub fn handle_input(&self, title_sender: &mpsc::Sender<String>) {
-        let Self {
-            input,
-            sender: io_tx,
-        } = self.clone();
+    pub fn set_bindings(&mut self, bindings: Bindings) {
+        self.bindings = bindings;
+    }
 
-        let send_keys = move || -> Result<(), mpsc::SendError<IoEvent>> {
-            if input.key_pressed(VirtualKeyCode::Escape) {
-                self::insert_cartridge(io_tx.clone(), title_sender.clone());
-            }
-            if input.key_pressed(VirtualKeyCode::Tab) {
-                io_tx.send(if !input.held_shift() {
-                    IoEvent::SwitchSpeedNext
-                } else {
-                    IoEvent::SwitchSpeedPrev
-                })?
-            }
+    pub fn set_color_schemes(&mut self, color_schemes: ColorSchemeList) {
+        self.color_schemes = color_schemes;
+    }
 
-            if input.key_pressed(VirtualKeyCode::Right) {
-                io_tx.send(IoEvent::ButtonPressed(JoypadButton::Right))?;
-            }
-            if input.key_pressed(VirtualKeyCode::Left) {
-                io_tx.send(IoEvent::ButtonPressed(JoypadButton::Left))?;
-            }
-            if input.key_pressed(VirtualKeyCode::Up) {
-                io_tx.send(IoEvent::ButtonPressed(JoypadButton::Up))?;
-            }
-            if input.key_pressed(VirtualKeyCode::Down) {
-                io_tx.send(IoEvent::ButtonPressed(JoypadButton::Down))?;
-            }
-            if input.key_pressed(VirtualKeyCode::Z) {
-                io_tx.send(IoEvent::ButtonPressed(JoypadButton::A))?;
-            }
-            if input.key_pressed(VirtualKeyCode::X) {
-                io_tx.send(IoEvent::ButtonPressed(JoypadButton::B))?;
-            }
-            if input.key_pressed(VirtualKeyCode::Space) {
-                io_tx.send(IoEvent::ButtonPressed(JoypadButton::Select))?;
-            }
-            if input.key_pressed(VirtualKeyCode::Return) {
-                io_tx.send(IoEvent::ButtonPressed(JoypadButton::Start))?;
-            }
+    pub fn set_auto_patch(&mut self, auto_patch: bool) {
+        self.auto_patch = auto_patch;
+    }
 
-            if input.key_released(VirtualKeyCode::Right) {
-                io_tx.send(IoEvent::ButtonReleased(JoypadButton::Right))?;
-            }
-            if input.key_released(VirtualKeyCode::Left) {
-                io_tx.send(IoEvent::ButtonReleased(JoypadButton::Left))?;
+    /// Send the current color scheme to the emulation
+    pub fn send_color_scheme(&self) {
+        let scheme = self.color_schemes.current();
+        log::info!("Color scheme {}", scheme.name);
+        self.sender
+            .send(IoEvent::SetColorScheme(scheme.schemes))
+            .ok();
+    }
+
+    /// The hotkey was pressed with its modifiers
+    pub fn hotkey_pressed(&self, hotkey: Hotkey) -> bool {
+        self.bindings.hotkey_pressed(&self.input, hotkey)
+    }
+
+    pub fn handle_input(&mut self, title_sender: &mpsc::Sender<String>) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.hotkey_pressed(Hotkey::ReloadBindings) {
+            match self.bindings.reload() {
+                Ok(()) => log::info!("Reloaded the key bindings"),
+                Err(e) => log::error!("Could not reload the key bindings: {e}"),
             }
-            if input.key_released(VirtualKeyCode::Up) {
-                io_tx.send(IoEvent::ButtonReleased(JoypadButton::Up))?;
+        }
+
+        if self.hotkey_pressed(Hotkey::NextColorScheme) {
+            self.color_schemes.select_next();
+            self.send_color_scheme();
+        }
+        if self.hotkey_pressed(Hotkey::PrevColorScheme) {
+            self.color_schemes.select_prev();
+            self.send_color_scheme();
+        }
+
+        let send_keys = || -> Result<(), mpsc::SendError<IoEvent>> {
+            let io_tx = &self.sender;
+
+            if self.hotkey_pressed(Hotkey::OpenGame) {
+                self::insert_cartridge(io_tx.clone(), title_sender.clone(), self.auto_patch);
             }
-            if input.key_released(VirtualKeyCode::Down) {
-                io_tx.send(IoEvent::ButtonReleased(JoypadButton::Down))?;
+            if self.hotkey_pressed(Hotkey::NextSpeed) {
+                io_tx.send(IoEvent::SwitchSpeedNext)?;
             }
-            if input.key_released(VirtualKeyCode::Z) {
-                io_tx.send(IoEvent::ButtonReleased(JoypadButton::A))?;
+            if self.hotkey_pressed(Hotkey::PrevSpeed) {
+                io_tx.send(IoEvent::SwitchSpeedPrev)?;
             }
-            if input.key_released(VirtualKeyCode::X) {
-                io_tx.send(IoEvent::ButtonReleased(JoypadButton::B))?;
+
+            for (hotkey, layer) in [
+                (Hotkey::ToggleBackground, Layers::BACKGROUND),
+                (Hotkey::ToggleWindow, Layers::WINDOW),
+                (Hotkey::ToggleSprites, Layers::SPRITES),
+            ] {
+                if self.hotkey_pressed(hotkey) {
+                    io_tx.send(IoEvent::ToggleLayer(layer))?;
+                }
             }
-            if input.key_released(VirtualKeyCode::Space) {
-                io_tx.send(IoEvent::ButtonReleased(JoypadButton::Select))?;
+
+            for button in self.bindings.pressed_buttons(&self.input) {
+                io_tx.send(IoEvent::ButtonPressed(button))?;
             }
-            if input.key_released(VirtualKeyCode::Return) {
-                io_tx.send(IoEvent::ButtonReleased(JoypadButton::Start))?;
+            for button in self.bindings.released_buttons(&self.input) {
+                io_tx.send(IoEvent::ButtonReleased(button))?;
             }
             Ok(())
         };
@@ -116,7 +142,13 @@ impl IoHandler {
     }
 }
 
-pub fn insert_cartridge(io_ev_sender: mpsc::Sender<IoEvent>, title_sender: mpsc::Sender<String>) {
+/// Open a game with a file dialog, `auto_patch` applies the patch found next to it
+#[cfg_attr(target_arch = "wasm32", allow(unused_variables))]
+pub fn insert_cartridge(
+    io_ev_sender: mpsc::Sender<IoEvent>,
+    title_sender: mpsc::Sender<String>,
+    auto_patch: bool,
+) {
     let dialog = rfd::AsyncFileDialog::new()
         .add_filter("ROM", &["gb", "gbc"])
         .pick_file();
@@ -131,7 +163,20 @@ pub fn insert_cartridge(io_ev_sender: mpsc::Sender<IoEvent>, title_sender: mpsc:
                 log::info!("Loading file: {file:?}");
                 let buffer = file.read().await;
 
+                #[cfg(not(target_arch = "wasm32"))]
+                let cartridge = match auto_patch {
+                    true => match patched_cartridge(file.path(), &buffer) {
+                        Ok(cartridge) => cartridge,
+                        Err(e) => {
+                            log::error!("Could not patch the game: {e}");
+                            return;
+                        }
+                    },
+                    false => Cartridge::new(&buffer),
+                };
+                #[cfg(target_arch = "wasm32")]
                 let cartridge = Cartridge::new(&buffer);
+
                 title_sender.send(cartridge.rom_name().to_string()).ok();
 
                 if io_tx.send(IoEvent::InsertCartridge(cartridge)).is_err() {
@@ -144,6 +189,14 @@ pub fn insert_cartridge(io_ev_sender: mpsc::Sender<IoEvent>, title_sender: mpsc:
     });
 }
 
+/// Apply the patch found next to the ROM
+#[cfg(not(target_arch = "wasm32"))]
+fn patched_cartridge(rom_path: &Path, rom: &[u8]) -> io::Result<Cartridge> {
+    let patches = GameBoy::read_patch_files(cli::find_patch(rom_path).as_slice())?;
+    Cartridge::with_patches(rom, &patches)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
 impl Deref for IoHandler {
     type Target = WinitInputHelper;
 
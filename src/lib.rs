@@ -1,6 +1,13 @@
 mod app;
+pub mod bindings;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod cli;
+pub mod color_schemes;
+pub mod filter;
 mod gameboy;
 mod io;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod repl;
 mod utils;
 #[cfg(target_arch = "wasm32")]
 mod web;
@@ -1,22 +1,134 @@
 #[cfg(not(target_arch = "wasm32"))]
 pub fn main() {
-    use goodboy::{App, GameBoy};
-    use std::env;
+    use goodboy::{
+        bindings::Bindings,
+        cli::{Options, USAGE},
+        color_schemes::{self, ColorSchemeList},
+        App, GameBoy,
+    };
+    use goodboy_core::{cpu::disassembler, symbols::Symbols};
+    use std::{env, process};
 
     env_logger::init();
 
+    let options = Options::parse(env::args().skip(1)).unwrap_or_else(|e| {
+        if !e.is_empty() {
+            eprintln!("{e}\n");
+        }
+        eprintln!("{USAGE}");
+        process::exit(1)
+    });
+
+    if let Some(bank) = options.disassemble {
+        let path = options.rom.as_ref().unwrap_or_else(|| {
+            eprintln!("Missing the ROM to disassemble\n\n{USAGE}");
+            process::exit(1)
+        });
+        let rom = std::fs::read(path)
+            .unwrap_or_else(|e| panic!("Could not read the file \"{}\": {e:?}", path.display()));
+
+        let symbols = match options.symbol_file() {
+            Some(path) => GameBoy::read_symbols_file(&path).unwrap_or_else(|e| {
+                panic!("Could not load the symbols \"{}\": {e}", path.display())
+            }),
+            None => Symbols::default(),
+        };
+
+        match disassembler::rgbds_bank(&rom, bank, &symbols) {
+            Some(source) => print!("{source}"),
+            None => {
+                eprintln!("The ROM has no bank {bank}");
+                process::exit(1)
+            }
+        }
+        return;
+    }
+
     let mut gameboy = GameBoy::new();
 
-    let args = env::args();
-    if let Some(path) = args.skip(1).next() {
-        match gameboy.load_game_file(&path) {
+    if let Some(ref path) = options.rom {
+        match gameboy.load_game_file(path, &options.patch_files()) {
             Ok(()) => {}
             Err(e) => {
-                panic!("Could not read the file \"{path}\": {e:?}")
+                panic!("Could not read the file \"{}\": {e:?}", path.display())
             }
         }
     }
 
-    let app = App::new(gameboy).unwrap();
+    if let Some(correction) = options.color_correction {
+        gameboy.set_color_correction(correction);
+    }
+
+    if let Some(ref path) = options.symbol_file() {
+        if let Err(e) = gameboy.load_symbols_file(path) {
+            panic!("Could not load the symbols \"{}\": {e}", path.display())
+        }
+    }
+
+    if let Some(ref path) = options.cheats {
+        match gameboy.load_cheats_file(path) {
+            Ok(count) => log::info!("Loaded {count} cheats from {path:?}"),
+            Err(e) => {
+                panic!("Could not load the cheats \"{}\": {e}", path.display())
+            }
+        }
+    }
+
+    if let Some(ref path) = options.trace {
+        if let Err(e) = gameboy.start_trace(path, options.trace_options) {
+            panic!("Could not start the trace \"{}\": {e}", path.display())
+        }
+    }
+
+    if let Some(ref path) = options.profile {
+        if let Err(e) = gameboy.start_profile(path) {
+            panic!("Could not start the profiler \"{}\": {e}", path.display())
+        }
+    }
+
+    if let Some(ref path) = options.cdl {
+        if let Err(e) = gameboy.start_cdl(path) {
+            panic!("Could not start the CDL \"{}\": {e}", path.display())
+        }
+    }
+
+    if options.debug {
+        gameboy.start_repl();
+    }
+
+    let mut schemes = ColorSchemeList::default();
+
+    if let Some(ref path) = options.color_schemes {
+        match color_schemes::load_file(path) {
+            Ok(list) => schemes.extend(list),
+            Err(e) => panic!(
+                "Could not load the color schemes \"{}\": {e}",
+                path.display()
+            ),
+        }
+    }
+
+    if let Some(ref name) = options.color_scheme {
+        if !schemes.select(name) {
+            eprintln!("Unknown color scheme {name}");
+            process::exit(1)
+        }
+    }
+
+    let bindings = match options.bindings {
+        Some(ref path) => Bindings::load_file(path).unwrap_or_else(|e| {
+            panic!(
+                "Could not load the key bindings \"{}\": {e}",
+                path.display()
+            )
+        }),
+        None => Bindings::default(),
+    };
+
+    let mut app = App::new(gameboy).unwrap();
+    app.set_bindings(bindings);
+    app.set_filter(options.filter, options.ghosting);
+    app.set_auto_patch(options.auto_patch);
+    app.set_color_schemes(schemes);
     pollster::block_on(app.run());
 }
@@ -0,0 +1,629 @@
+use std::{
+    fmt::Display,
+    io::{self, BufRead, Write},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+
+use goodboy_core::{
+    cpu::{disassembler::DecodedInstruction, register::Flags},
+    debugger::{
+        BreakCondition, Breakpoint, Comparison, Register, StopReason, WatchKind, Watchpoint,
+    },
+    vm::Vm,
+};
+
+use crate::cli::parse_number;
+
+const PROMPT: &str = "(goodboy) ";
+
+pub const HELP: &str = "\
+Commands:
+    c, continue             Resume the emulation
+    p, pause                Pause the emulation
+    s, step [N]             Execute N instructions (default: 1, at most 1000000)
+    n, next                 Execute an instruction, running over CALL and RST
+    finish                  Run until the current function returns
+    b, break LOC [if REG OP VALUE]
+                            Stop before the instruction at LOC, e.g. `b $0150 if a == $10`
+    w, watch ADDR[-ADDR] [r|w|rw]
+                            Stop after an access to the range (default: w)
+    d, delete N             Remove the breakpoint N
+    unwatch N               Remove the watchpoint N
+    i, info                 List the breakpoints and watchpoints
+    r, regs                 Print the CPU registers
+    x LOC [LEN]             Hex dump LEN bytes (default: 64), of any bank with `bank:addr`
+    banks                   Print the ROM, RAM, VRAM and WRAM banks mapped
+    l, dis [LOC] [N]        Disassemble N instructions at LOC (default: around PC)
+    h, help                 Print this message
+
+Numbers are decimal unless prefixed with `$` or `0x`. A LOC is an address, a
+`bank:addr` pair in hexadecimal (`01:4000`) or a label of the symbol file.
+An empty line repeats the last command.";
+
+/// An address typed in the debugger
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    Addr(u16),
+    Banked(usize, u16),
+    /// A label of the symbol file, resolved by the emulation thread
+    Symbol(String),
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Location::Addr(addr) => write!(f, "${addr:04X}"),
+            Location::Banked(bank, addr) => write!(f, "{bank:02X}:{addr:04X}"),
+            Location::Symbol(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DebugCommand {
+    Continue,
+    Pause,
+    Step(usize),
+    Next,
+    Finish,
+    Break(Location, Option<BreakCondition>),
+    Watch(Watchpoint),
+    Delete(usize),
+    Unwatch(usize),
+    Info,
+    Registers,
+    Dump(Location, usize),
+    Banks,
+    Disassemble(Option<Location>, usize),
+    Help,
+}
+
+impl DebugCommand {
+    /// Parse a line typed in the debugger, `None` if it is empty
+    pub fn parse(line: &str) -> Result<Option<DebugCommand>, String> {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+
+        let command = match words.as_slice() {
+            [] => return Ok(None),
+            ["c" | "continue"] => DebugCommand::Continue,
+            ["p" | "pause"] => DebugCommand::Pause,
+            ["s" | "step"] => DebugCommand::Step(1),
+            ["s" | "step", count] => match number(count)? {
+                count if count > MAX_STEPS => return Err(format!("At most {MAX_STEPS} steps")),
+                count => DebugCommand::Step(count),
+            },
+            ["n" | "next"] => DebugCommand::Next,
+            ["finish"] => DebugCommand::Finish,
+            ["b" | "break", location] => DebugCommand::Break(location_arg(location)?, None),
+            ["b" | "break", location, "if", register, comparison, value] => {
+                let condition = BreakCondition {
+                    register: register_arg(register)?,
+                    comparison: comparison_arg(comparison)?,
+                    value: number(value)?,
+                };
+                DebugCommand::Break(location_arg(location)?, Some(condition))
+            }
+            ["w" | "watch", range] => DebugCommand::Watch(watchpoint_arg(range, "w")?),
+            ["w" | "watch", range, kind] => DebugCommand::Watch(watchpoint_arg(range, kind)?),
+            ["d" | "delete", index] => DebugCommand::Delete(number(index)?),
+            ["unwatch", index] => DebugCommand::Unwatch(number(index)?),
+            ["i" | "info"] => DebugCommand::Info,
+            ["r" | "regs"] => DebugCommand::Registers,
+            ["x", location] => DebugCommand::Dump(location_arg(location)?, 64),
+            ["x", location, len] => DebugCommand::Dump(location_arg(location)?, number(len)?),
+            ["banks"] => DebugCommand::Banks,
+            ["l" | "dis"] => DebugCommand::Disassemble(None, 10),
+            ["l" | "dis", location] => DebugCommand::Disassemble(Some(location_arg(location)?), 10),
+            ["l" | "dis", location, count] => {
+                DebugCommand::Disassemble(Some(location_arg(location)?), number(count)?)
+            }
+            ["h" | "help"] => DebugCommand::Help,
+            _ => return Err(format!("Invalid command `{}`, try `help`", line.trim())),
+        };
+
+        Ok(Some(command))
+    }
+}
+
+/// The most instructions of a `step`, about a second of emulation, since it can't be interrupted
+const MAX_STEPS: usize = 1_000_000;
+
+fn number<T: TryFrom<usize>>(s: &str) -> Result<T, String> {
+    parse_number(s)
+        .and_then(|n| T::try_from(n).ok())
+        .ok_or_else(|| format!("Invalid number `{s}`"))
+}
+
+fn location_arg(s: &str) -> Result<Location, String> {
+    if let Some((bank, addr)) = s.split_once(':') {
+        let bank = usize::from_str_radix(bank, 16);
+        let addr = u16::from_str_radix(addr, 16);
+        return match (bank, addr) {
+            (Ok(bank), Ok(addr)) => Ok(Location::Banked(bank, addr)),
+            _ => Err(format!("Invalid location `{s}`")),
+        };
+    }
+
+    match s.starts_with(|c: char| c.is_ascii_digit() || c == '$') {
+        true => number(s).map(Location::Addr),
+        false => Ok(Location::Symbol(s.to_string())),
+    }
+}
+
+fn register_arg(s: &str) -> Result<Register, String> {
+    let register = match s.to_ascii_lowercase().as_str() {
+        "a" => Register::A,
+        "f" => Register::F,
+        "b" => Register::B,
+        "c" => Register::C,
+        "d" => Register::D,
+        "e" => Register::E,
+        "h" => Register::H,
+        "l" => Register::L,
+        "af" => Register::AF,
+        "bc" => Register::BC,
+        "de" => Register::DE,
+        "hl" => Register::HL,
+        "sp" => Register::SP,
+        "pc" => Register::PC,
+        _ => return Err(format!("Invalid register `{s}`")),
+    };
+    Ok(register)
+}
+
+fn comparison_arg(s: &str) -> Result<Comparison, String> {
+    let comparison = match s {
+        "==" => Comparison::Eq,
+        "!=" => Comparison::Ne,
+        "<" => Comparison::Lt,
+        "<=" => Comparison::Le,
+        ">" => Comparison::Gt,
+        ">=" => Comparison::Ge,
+        _ => return Err(format!("Invalid comparison `{s}`")),
+    };
+    Ok(comparison)
+}
+
+fn watchpoint_arg(range: &str, kind: &str) -> Result<Watchpoint, String> {
+    let (start, end) = range.split_once('-').unwrap_or((range, range));
+    let (start, end) = (number(start)?, number(end)?);
+    if start > end {
+        return Err(format!("Invalid range `{range}`"));
+    }
+
+    let kind = match kind {
+        "r" => WatchKind::Read,
+        "w" => WatchKind::Write,
+        "rw" => WatchKind::ReadWrite,
+        _ => return Err(format!("Invalid access `{kind}`, expected r, w or rw")),
+    };
+    Ok(Watchpoint {
+        range: start..=end,
+        kind,
+    })
+}
+
+/// Read the commands from stdin until it is closed
+fn read_commands(tx: Sender<DebugCommand>) {
+    let mut last = None;
+
+    print!("{PROMPT}");
+    io::stdout().flush().ok();
+
+    for line in io::stdin().lock().lines() {
+        let Ok(line) = line else { break };
+
+        match DebugCommand::parse(&line) {
+            Ok(Some(command)) => last = Some(command),
+            Ok(None) if last.is_some() => {}
+            Ok(None) => {
+                print!("{PROMPT}");
+                io::stdout().flush().ok();
+                continue;
+            }
+            Err(e) => {
+                print!("{e}\n{PROMPT}");
+                io::stdout().flush().ok();
+                continue;
+            }
+        }
+
+        // the emulation thread prints the prompt once it's done
+        if let Some(command) = last.clone() {
+            if tx.send(command).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// The debugger driving the emulation thread, with its commands typed on stdin
+pub struct Repl {
+    commands: Receiver<DebugCommand>,
+    paused: bool,
+}
+
+impl Repl {
+    /// Start reading the commands from stdin, the emulation starts paused
+    pub fn spawn() -> Repl {
+        println!("The emulation is paused, type `continue` to start or `help` for the commands");
+
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || read_commands(tx));
+
+        Repl {
+            commands: rx,
+            paused: true,
+        }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Execute the commands typed since the last call
+    pub fn process(&mut self, mut vm: Option<&mut Vm>) {
+        loop {
+            match self.commands.try_recv() {
+                Ok(command) => {
+                    self.execute(vm.as_deref_mut(), command);
+                    print!("{PROMPT}");
+                    io::stdout().flush().ok();
+                }
+                Err(mpsc::TryRecvError::Empty) => break,
+                // stdin was closed, let the game run
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.paused = false;
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Pause the emulation after a breakpoint or a watchpoint
+    pub fn stop(&mut self, vm: &Vm, reason: StopReason) {
+        self.paused = true;
+        println!("\nStopped: {reason}");
+        print_instructions(vm, &vm.disassemble(vm.cpu().pc, 1));
+        print!("{PROMPT}");
+        io::stdout().flush().ok();
+    }
+
+    fn execute(&mut self, vm: Option<&mut Vm>, command: DebugCommand) {
+        match (command, vm) {
+            (DebugCommand::Help, _) => println!("{HELP}"),
+            (DebugCommand::Continue, _) => self.paused = false,
+            (DebugCommand::Pause, None) => self.paused = true,
+            (_, None) => println!("No game loaded"),
+            (command, Some(vm)) => self.execute_with_vm(vm, command),
+        }
+    }
+
+    fn execute_with_vm(&mut self, vm: &mut Vm, command: DebugCommand) {
+        match command {
+            DebugCommand::Pause => {
+                self.paused = true;
+                print_instructions(vm, &vm.disassemble(vm.cpu().pc, 1));
+            }
+            DebugCommand::Step(count) => {
+                self.paused = true;
+                for _ in 0..count {
+                    if let (_, Some(reason)) = vm.tick_checked() {
+                        println!("Stopped: {reason}");
+                        break;
+                    }
+                }
+                print_instructions(vm, &vm.disassemble(vm.cpu().pc, 1));
+            }
+            DebugCommand::Next | DebugCommand::Finish => {
+                self.paused = true;
+                let reason = match command {
+                    DebugCommand::Next => vm.step_over(),
+                    _ => vm.step_out(),
+                };
+                if reason != StopReason::Step {
+                    println!("Stopped: {reason}");
+                }
+                print_instructions(vm, &vm.disassemble(vm.cpu().pc, 1));
+            }
+            DebugCommand::Break(location, condition) => {
+                let Some(mut breakpoint) = resolve(vm, &location).map(|(bank, addr)| match bank {
+                    Some(bank) => Breakpoint::at_bank(bank, addr),
+                    None => Breakpoint::at(addr),
+                }) else {
+                    return println!("Unknown label {location}");
+                };
+                breakpoint.condition = condition;
+                let index = vm.add_breakpoint(breakpoint);
+                println!("Breakpoint #{index}: {}", describe_breakpoint(&breakpoint));
+            }
+            DebugCommand::Watch(watchpoint) => {
+                let index = vm.add_watchpoint(watchpoint.clone());
+                println!("Watchpoint #{index}: {}", describe_watchpoint(&watchpoint));
+            }
+            DebugCommand::Delete(index) => match vm.remove_breakpoint(index) {
+                Some(_) => println!("Removed the breakpoint #{index}"),
+                None => println!("There is no breakpoint #{index}"),
+            },
+            DebugCommand::Unwatch(index) => match vm.remove_watchpoint(index) {
+                Some(_) => println!("Removed the watchpoint #{index}"),
+                None => println!("There is no watchpoint #{index}"),
+            },
+            DebugCommand::Info => {
+                for (i, breakpoint) in vm.breakpoints().iter().enumerate() {
+                    println!("Breakpoint #{i}: {}", describe_breakpoint(breakpoint));
+                }
+                for (i, watchpoint) in vm.watchpoints().iter().enumerate() {
+                    println!("Watchpoint #{i}: {}", describe_watchpoint(watchpoint));
+                }
+            }
+            DebugCommand::Registers => print_registers(vm),
+            DebugCommand::Dump(location, len) => match resolve(vm, &location) {
+                Some((bank, addr)) => print_memory(vm, bank, addr, len),
+                None => println!("Unknown label {location}"),
+            },
+            DebugCommand::Banks => println!(
+                "ROM0:{:02X} ROMX:{:02X} VRAM:{} SRAM:{:02X} WRAMX:{}",
+                vm.bank_at(0x0000),
+                vm.bank_at(0x4000),
+                vm.bank_at(0x8000),
+                vm.bank_at(0xA000),
+                vm.bank_at(0xD000),
+            ),
+            DebugCommand::Disassemble(None, count) => {
+                let pc = vm.cpu().pc;
+                let mut instructions = instructions_before(vm, pc, count / 2);
+                instructions.extend(vm.disassemble(pc, count - instructions.len()));
+                print_instructions(vm, &instructions);
+            }
+            DebugCommand::Disassemble(Some(location), count) => match resolve(vm, &location) {
+                Some((Some(bank), addr)) => {
+                    print_instructions(vm, &vm.disassemble_bank(bank, addr, count))
+                }
+                Some((None, addr)) => print_instructions(vm, &vm.disassemble(addr, count)),
+                None => println!("Unknown label {location}"),
+            },
+            DebugCommand::Continue | DebugCommand::Help => {}
+        }
+    }
+}
+
+/// The bank and address of a location, `None` if the label doesn't exist
+fn resolve(vm: &Vm, location: &Location) -> Option<(Option<usize>, u16)> {
+    match location {
+        Location::Addr(addr) => Some((None, *addr)),
+        Location::Banked(bank, addr) => Some((Some(*bank), *addr)),
+        Location::Symbol(name) => vm
+            .symbols()
+            .address(name)
+            .map(|(bank, addr)| (Some(bank), addr)),
+    }
+}
+
+fn describe_breakpoint(breakpoint: &Breakpoint) -> String {
+    let location = match (breakpoint.bank, breakpoint.addr) {
+        (Some(bank), Some(addr)) => format!("{bank:02X}:{addr:04X}"),
+        (None, Some(addr)) => format!("{addr:04X}"),
+        _ => "any address".to_string(),
+    };
+    match breakpoint.condition {
+        Some(condition) => format!(
+            "{location} if {:?} {:?} ${:X}",
+            condition.register, condition.comparison, condition.value
+        ),
+        None => location,
+    }
+}
+
+fn describe_watchpoint(watchpoint: &Watchpoint) -> String {
+    format!(
+        "{:?} {:04X}-{:04X}",
+        watchpoint.kind,
+        watchpoint.range.start(),
+        watchpoint.range.end()
+    )
+}
+
+fn print_registers(vm: &Vm) {
+    let cpu = vm.cpu();
+    let regs = &cpu.regs;
+    let flags = [
+        (Flags::Z, 'Z'),
+        (Flags::N, 'N'),
+        (Flags::H, 'H'),
+        (Flags::C, 'C'),
+    ]
+    .map(|(flag, name)| if regs.f.contains(flag) { name } else { '-' })
+    .iter()
+    .collect::<String>();
+
+    println!(
+        "AF:{:04X} BC:{:04X} DE:{:04X} HL:{:04X} SP:{:04X} PC:{:04X} [{flags}] IME:{} HALT:{}",
+        regs.af(),
+        regs.bc(),
+        regs.de(),
+        regs.hl(),
+        cpu.sp,
+        cpu.pc,
+        u8::from(cpu.ime()),
+        u8::from(cpu.halted()),
+    );
+}
+
+/// Dump the memory mapped at `addr`, unlocked by the PPU, or a bank that may not be mapped
+fn print_memory(vm: &Vm, bank: Option<usize>, addr: u16, len: usize) {
+    let bytes = (0..len)
+        .map(|i| {
+            let addr = addr.wrapping_add(i as u16);
+            match bank {
+                Some(bank) => vm.peek(bank, addr),
+                None => vm.peek(vm.bank_at(addr), addr),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let hex = line
+            .iter()
+            .map(|b| b.map_or("-- ".to_string(), |b| format!("{b:02X} ")))
+            .collect::<String>();
+        let ascii = line
+            .iter()
+            .map(|b| match b {
+                Some(b) if b.is_ascii_graphic() => *b as char,
+                _ => '.',
+            })
+            .collect::<String>();
+        println!(
+            "{:04X}: {hex:<48}|{ascii}|",
+            addr.wrapping_add(i as u16 * 16)
+        );
+    }
+}
+
+fn print_instructions(vm: &Vm, instructions: &[DecodedInstruction]) {
+    let pc = vm.cpu().pc;
+
+    for instruction in instructions {
+        if let Some(label) = vm.symbols().name(instruction.bank, instruction.addr) {
+            println!("{label}:");
+        }
+
+        let marker = if instruction.addr == pc { "=>" } else { "  " };
+        let bytes = instruction
+            .bytes()
+            .iter()
+            .map(|b| format!("{b:02X}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        println!(
+            "{marker} {:02X}:{:04X}  {bytes:<9} {}",
+            instruction.bank,
+            instruction.addr,
+            vm.format_instruction(instruction)
+        );
+    }
+}
+
+/// Up to `count` instructions ending right before `addr`. The instructions have
+/// variable lengths, so this looks for the furthest start that decodes into `addr`.
+fn instructions_before(vm: &Vm, addr: u16, count: usize) -> Vec<DecodedInstruction> {
+    (addr.saturating_sub(3 * count as u16)..addr)
+        .find_map(|start| {
+            let mut instructions = Vec::new();
+            let mut current = start;
+            while current < addr {
+                let instruction = vm.disassemble(current, 1).remove(0);
+                current = instruction.next_addr();
+                instructions.push(instruction);
+            }
+            (current == addr).then_some(instructions)
+        })
+        .map(|instructions| {
+            let skip = instructions.len().saturating_sub(count);
+            instructions[skip..].to_vec()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> DebugCommand {
+        DebugCommand::parse(line).unwrap().unwrap()
+    }
+
+    #[test]
+    fn run_commands() {
+        assert_eq!(DebugCommand::parse("  "), Ok(None));
+        assert_eq!(parse("c"), DebugCommand::Continue);
+        assert_eq!(parse("pause"), DebugCommand::Pause);
+        assert_eq!(parse("s"), DebugCommand::Step(1));
+        assert_eq!(parse("step 10"), DebugCommand::Step(10));
+        assert_eq!(parse("s $10"), DebugCommand::Step(16));
+        assert_eq!(parse("s 1000000"), DebugCommand::Step(1_000_000));
+        assert_eq!(parse("n"), DebugCommand::Next);
+        assert_eq!(parse("finish"), DebugCommand::Finish);
+    }
+
+    #[test]
+    fn breakpoints_and_watchpoints() {
+        assert_eq!(
+            parse("b $0150"),
+            DebugCommand::Break(Location::Addr(0x150), None)
+        );
+        assert_eq!(
+            parse("break Main.loop"),
+            DebugCommand::Break(Location::Symbol("Main.loop".to_string()), None)
+        );
+        assert_eq!(
+            parse("b 01:4000 if hl >= 0xC000"),
+            DebugCommand::Break(
+                Location::Banked(1, 0x4000),
+                Some(BreakCondition {
+                    register: Register::HL,
+                    comparison: Comparison::Ge,
+                    value: 0xC000,
+                })
+            )
+        );
+        assert_eq!(
+            parse("w $C000-$C0FF rw"),
+            DebugCommand::Watch(Watchpoint {
+                range: 0xC000..=0xC0FF,
+                kind: WatchKind::ReadWrite,
+            })
+        );
+        assert_eq!(
+            parse("watch $FF40"),
+            DebugCommand::Watch(Watchpoint {
+                range: 0xFF40..=0xFF40,
+                kind: WatchKind::Write,
+            })
+        );
+        assert_eq!(parse("d 2"), DebugCommand::Delete(2));
+        assert_eq!(parse("unwatch 0"), DebugCommand::Unwatch(0));
+    }
+
+    #[test]
+    fn inspection_commands() {
+        assert_eq!(parse("i"), DebugCommand::Info);
+        assert_eq!(parse("regs"), DebugCommand::Registers);
+        assert_eq!(
+            parse("x $C000"),
+            DebugCommand::Dump(Location::Addr(0xC000), 64)
+        );
+        assert_eq!(
+            parse("x 02:d000 16"),
+            DebugCommand::Dump(Location::Banked(2, 0xD000), 16)
+        );
+        assert_eq!(parse("banks"), DebugCommand::Banks);
+        assert_eq!(parse("l"), DebugCommand::Disassemble(None, 10));
+        assert_eq!(
+            parse("dis $0100 5"),
+            DebugCommand::Disassemble(Some(Location::Addr(0x100)), 5)
+        );
+        assert_eq!(parse("h"), DebugCommand::Help);
+    }
+
+    #[test]
+    fn invalid_commands() {
+        for line in [
+            "jump",
+            "c now",
+            "step x",
+            "step $FFFFFF",
+            "b $10000",
+            "x 1g:4000",
+            "b $0150 if q == 1",
+            "b $0150 if a =< 1",
+            "w $C0FF-$C000",
+            "w $C000 x",
+            "d -1",
+        ] {
+            assert!(DebugCommand::parse(line).is_err(), "{line}");
+        }
+    }
+}
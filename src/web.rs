@@ -29,7 +29,7 @@ pub fn init(window: Rc<Window>, ev_handler: &IoHandler, title_sender: &mpsc::Sen
         let title_sender = title_sender.clone();
         move |_: web_sys::Event| {
             let sender = sender.clone();
-            insert_cartridge(sender, title_sender.clone());
+            insert_cartridge(sender, title_sender.clone(), false);
         }
     }) as Box<dyn FnMut(_)>);
 